@@ -0,0 +1,6 @@
+-- Meilisearchを使わない場合の物品検索用のインデックス
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX fixtures_search_trgm_idx ON fixtures USING gin (
+    (coalesce(name, '') || ' ' || coalesce(description, '') || ' ' || coalesce(model_number, '') || ' ' || coalesce(note, '')) gin_trgm_ops
+);
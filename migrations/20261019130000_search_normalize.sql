@@ -0,0 +1,17 @@
+-- 表記ゆれを吸収するために正規化した文字列に対してインデックスを張り直す
+-- 式はsrc/search_engine/postgres.rsで組み立てるものと揃えておく
+DROP INDEX IF EXISTS fixtures_search_trgm_idx;
+
+CREATE INDEX fixtures_search_trgm_idx ON fixtures USING gin (
+    translate(
+        lower(normalize(coalesce(name, '') || ' ' || coalesce(description, '') || ' ' || coalesce(model_number, '') || ' ' || coalesce(note, ''), NFKC)),
+        'ァアィイゥウェエォオカガキギクグケゲコゴサザシジスズセゼソゾタダチヂッツヅテデトドナニヌネノハバパヒビピフブプヘベペホボポマミムメモャヤュユョヨラリルレロヮワヰヱヲンヴヵヶー',
+        'ぁあぃいぅうぇえぉおかがきぎくぐけげこごさざしじすずせぜそぞただちぢっつづてでとどなにぬねのはばぱひびぴふぶぷへべぺほぼぽまみむめもゃやゅゆょよらりるれろゎわゐゑをんゔゕゖ'
+    ) gin_trgm_ops
+);
+
+-- 検索で同じ意味として扱う単語のグループ
+CREATE TABLE search_synonym (
+    id uuid PRIMARY KEY,
+    words text[] NOT NULL
+);
@@ -0,0 +1,25 @@
+-- 物品以外の情報を検索するためのインデックス
+-- 式はsrc/search_engine/postgres.rsで組み立てるものと揃えておく
+CREATE INDEX spot_search_trgm_idx ON spot USING gin (
+    translate(
+        lower(normalize(coalesce(name, '') || ' ' || coalesce(building, '') || ' ' || coalesce(room, '') || ' ' || coalesce(note, ''), NFKC)),
+        'ァアィイゥウェエォオカガキギクグケゲコゴサザシジスズセゼソゾタダチヂッツヅテデトドナニヌネノハバパヒビピフブプヘベペホボポマミムメモャヤュユョヨラリルレロヮワヰヱヲンヴヵヶー',
+        'ぁあぃいぅうぇえぉおかがきぎくぐけげこごさざしじすずせぜそぞただちぢっつづてでとどなにぬねのはばぱひびぴふぶぷへべぺほぼぽまみむめもゃやゅゆょよらりるれろゎわゐゑをんゔゕゖ'
+    ) gin_trgm_ops
+);
+
+CREATE INDEX container_search_trgm_idx ON container USING gin (
+    translate(
+        lower(normalize(coalesce(description, '') || ' ' || coalesce(qr_id, ''), NFKC)),
+        'ァアィイゥウェエォオカガキギクグケゲコゴサザシジスズセゼソゾタダチヂッツヅテデトドナニヌネノハバパヒビピフブプヘベペホボポマミムメモャヤュユョヨラリルレロヮワヰヱヲンヴヵヶー',
+        'ぁあぃいぅうぇえぉおかがきぎくぐけげこごさざしじすずせぜそぞただちぢっつづてでとどなにぬねのはばぱひびぴふぶぷへべぺほぼぽまみむめもゃやゅゆょよらりるれろゎわゐゑをんゔゕゖ'
+    ) gin_trgm_ops
+);
+
+CREATE INDEX lending_search_trgm_idx ON lending USING gin (
+    translate(
+        lower(normalize(coalesce(borrower_name, '') || ' ' || coalesce(borrower_org, ''), NFKC)),
+        'ァアィイゥウェエォオカガキギクグケゲコゴサザシジスズセゼソゾタダチヂッツヅテデトドナニヌネノハバパヒビピフブプヘベペホボポマミムメモャヤュユョヨラリルレロヮワヰヱヲンヴヵヶー',
+        'ぁあぃいぅうぇえぉおかがきぎくぐけげこごさざしじすずせぜそぞただちぢっつづてでとどなにぬねのはばぱひびぴふぶぷへべぺほぼぽまみむめもゃやゅゆょよらりるれろゎわゐゑをんゔゕゖ'
+    ) gin_trgm_ops
+);
@@ -0,0 +1,4 @@
+-- トークンを失効させられるようにする
+-- トークンそのものを見せずに一覧や失効の指定ができるようにIDも振る
+ALTER TABLE passtoken ADD COLUMN id uuid NOT NULL UNIQUE DEFAULT gen_random_uuid();
+ALTER TABLE passtoken ADD COLUMN revoked_at timestamptz;
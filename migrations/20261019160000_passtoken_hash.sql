@@ -0,0 +1,8 @@
+-- トークンを平文ではなくソルト付きのハッシュで保存する
+-- 平文で保存されていたトークンは全て無効にする
+DELETE FROM passtoken;
+ALTER TABLE passtoken DROP COLUMN token;
+-- 照合するときに行を引くための、トークンの先頭の公開してよい部分
+ALTER TABLE passtoken ADD COLUMN prefix text PRIMARY KEY;
+ALTER TABLE passtoken ADD COLUMN salt text NOT NULL;
+ALTER TABLE passtoken ADD COLUMN token_hash text NOT NULL;
@@ -0,0 +1,13 @@
+-- 個人ごとのアカウント
+CREATE TABLE users (
+    id uuid PRIMARY KEY,
+    name text NOT NULL UNIQUE,
+    password_hash text NOT NULL,
+    role text NOT NULL,
+    enabled boolean NOT NULL DEFAULT true,
+    created_at timestamptz NOT NULL
+);
+
+-- アカウントから発行したトークンはそのアカウントに結びつける
+-- 権限ごとの共有パスキーから発行したものは`NULL`になる
+ALTER TABLE passtoken ADD COLUMN user_id uuid REFERENCES users (id) ON DELETE CASCADE;
@@ -0,0 +1,7 @@
+-- OpenID Connectでのログインの途中の状態
+-- 認可リクエストで渡したstateとnonceを、コールバックで照合するまで保存しておく
+CREATE TABLE oidc_login (
+    state text PRIMARY KEY,
+    nonce text NOT NULL,
+    created_at timestamptz NOT NULL
+);
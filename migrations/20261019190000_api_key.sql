@@ -0,0 +1,14 @@
+-- キオスク端末や連携スクリプト向けの期限の無いAPIキー
+-- キーそのものは保存せず、トークンと同じくソルト付きのハッシュだけを保存する
+CREATE TABLE api_key (
+    id uuid PRIMARY KEY,
+    label text NOT NULL,
+    prefix text NOT NULL UNIQUE,
+    salt text NOT NULL,
+    key_hash text NOT NULL,
+    role text NOT NULL,
+    scopes text[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
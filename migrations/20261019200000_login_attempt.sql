@@ -0,0 +1,17 @@
+-- /gen_passtokenで失敗した試行の記録
+CREATE TABLE login_failure (
+    id uuid PRIMARY KEY,
+    ip text NOT NULL,
+    name text NOT NULL,
+    attempted_at timestamptz NOT NULL
+);
+
+CREATE INDEX login_failure_attempted_at_idx ON login_failure (attempted_at DESC);
+
+-- IPアドレスごと、ユーザー名ごとの連続した失敗の回数とロックの期限
+-- keyは`ip:<IPアドレス>`または`name:<ユーザー名>`
+CREATE TABLE login_lockout (
+    key text PRIMARY KEY,
+    failures integer NOT NULL,
+    locked_until timestamptz
+);
@@ -0,0 +1,24 @@
+-- 短命なアクセストークンを更新するためのリフレッシュトークン
+-- 一度のログインで発行されたものは同じsession_idを持ち、使うたびに新しいものに置き換える
+CREATE TABLE refresh_token (
+    id uuid PRIMARY KEY,
+    session_id uuid NOT NULL,
+    prefix text NOT NULL UNIQUE,
+    salt text NOT NULL,
+    token_hash text NOT NULL,
+    role text NOT NULL,
+    user_id uuid REFERENCES users (id) ON DELETE CASCADE,
+    -- ログインした日時と、そこからの有効期間
+    session_started_at timestamptz NOT NULL,
+    limit_days integer NOT NULL,
+    created_at timestamptz NOT NULL,
+    -- 新しいものに置き換えた日時
+    used_at timestamptz,
+    revoked_at timestamptz
+);
+
+CREATE INDEX refresh_token_session_id_idx ON refresh_token (session_id);
+
+-- アクセストークンの期限と、発行したリフレッシュトークンのセッション
+ALTER TABLE passtoken ADD COLUMN expires_at timestamptz;
+ALTER TABLE passtoken ADD COLUMN session_id uuid;
@@ -0,0 +1,27 @@
+-- 同時に編集した時に上書きし合わないよう、行ごとに版を持たせる
+-- 更新するたびにトリガーで版を一つ増やし、更新日時を記録する
+ALTER TABLE fixtures ADD COLUMN version bigint NOT NULL DEFAULT 1;
+ALTER TABLE fixtures ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE lending ADD COLUMN version bigint NOT NULL DEFAULT 1;
+ALTER TABLE lending ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE spot ADD COLUMN version bigint NOT NULL DEFAULT 1;
+ALTER TABLE spot ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE container ADD COLUMN version bigint NOT NULL DEFAULT 1;
+ALTER TABLE container ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+
+CREATE FUNCTION bump_row_version() RETURNS trigger AS $$
+BEGIN
+    NEW.version := OLD.version + 1;
+    NEW.updated_at := now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER fixtures_bump_version BEFORE UPDATE ON fixtures
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
+CREATE TRIGGER lending_bump_version BEFORE UPDATE ON lending
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
+CREATE TRIGGER spot_bump_version BEFORE UPDATE ON spot
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
+CREATE TRIGGER container_bump_version BEFORE UPDATE ON container
+    FOR EACH ROW EXECUTE FUNCTION bump_row_version();
@@ -0,0 +1,18 @@
+-- 再送されたリクエストに最初の応答をそのまま返すための冪等キー
+-- キーは呼び出した人ごとに分け、他の端末や利用者が同じキーを送っても別のリクエストとして処理する
+CREATE TABLE idempotency_key (
+    -- `session:<id>`、`token:<id>`、`api_key:<id>`の形で呼び出した人を表す
+    caller text NOT NULL,
+    key text NOT NULL,
+    -- メソッド、パス、本文から計算したハッシュ
+    -- 同じキーで内容の違うリクエストが送られた場合の検出に使う
+    request_hash text NOT NULL,
+    -- 処理中の場合はNULL
+    status_code integer,
+    headers jsonb,
+    body bytea,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (caller, key)
+);
+
+CREATE INDEX idempotency_key_created_at_idx ON idempotency_key (created_at);
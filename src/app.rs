@@ -1,31 +1,61 @@
-use crate::error_handling::{QrError, Result};
+use crate::app::policy::Caller;
+use crate::app::version::Legacy;
+use crate::authentication::oidc::{OidcClient, OidcConfig};
+use crate::database::get_version::VersionKey;
+use crate::database::purge_expired::{self, PurgeReport, RetentionPolicy};
+use crate::error_handling::{result_to_handler, QrError, Result, ReturnData};
+use crate::Spot;
 use axum::{
-    extract::{Query, TypedHeader},
+    extract::{ConnectInfo, Extension, Json, Path, Query, TypedHeader},
     headers::authorization::{Authorization, Basic, Bearer},
-    http::Method,
+    http::{HeaderMap, Method},
+    middleware,
+    response::IntoResponse,
     routing::{delete, get, post},
     Router,
 };
 use chrono::Utc;
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH};
+use sqlx::{pool::Pool, postgres::Postgres};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::*;
+use uuid::Uuid;
 
-use crate::search_engine;
+use crate::search_engine::{
+    meilisearch::MeilisearchContext, memory::InMemoryContext, postgres::PgSearchContext,
+    BackendKind, FallbackContext, FixturesDocument, SearchBackend, SearchContexts, SpotDocument,
+    CONTAINER_SEARCHABLE_ATTRIBUTES, LENDING_SEARCHABLE_ATTRIBUTES, SPOT_SEARCHABLE_ATTRIBUTES,
+};
 
 /// 認証まわりのエンドポイントの定義
 pub mod authentication;
 /// コンテナの管理を行うエンドポイントの定義
 pub mod container;
+/// 楽観的な排他制御に使うETagの扱い
+pub mod etag;
 /// 物品情報の登録を行うエンドポイントの定義
 pub mod fixtures;
+/// 再送されたリクエストを一度だけ処理するミドルウェア
+pub mod idempotency;
 /// 貸出情報の管理を行うエンドポイントの定義
 pub mod lending;
+/// OpenAPIの仕様書を返すエンドポイントの定義
+pub mod openapi;
+/// エンドポイントごとに必要な権限の定義
+pub mod policy;
+/// 全ての種類の情報をまとめて検索するエンドポイントの定義
+pub mod search;
 /// 場所の管理を行うエンドポイントの定義
 pub mod spot;
+/// 検索に使う同義語の管理を行うエンドポイントの定義
+pub mod synonym;
+/// 個人ごとのアカウントの管理を行うエンドポイントの定義
+pub mod user;
+/// APIのバージョンと従来のパスの扱い
+pub mod version;
 
 /// ログを出力するための設定など
 async fn init_logger() -> Result<()> {
@@ -45,16 +75,183 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
     let conn = Arc::new(crate::database::create_pool().await?);
     info!("Success generate DB connection pool");
 
-    info!("Try generate search engine context for fixtures");
-    let search_fixtures_context = Arc::new(search_engine::SearchFixtures::new().await?);
-    info!("Success generate search engine context for fixtures");
-
     // migrateファイルを適用
     crate::database::migrate(&mut conn.acquire().await.map_err(|_| QrError::ConnectionPool)?)
         .await?;
 
-    // pathと関数の実体の紐づけ
-    let app = Router::new()
+    // 期限が切れたトークンなどを定期的に削除する
+    purge_expired::spawn_purge_job(Arc::clone(&conn), RetentionPolicy::from_env());
+
+    let oidc = match OidcConfig::from_env()? {
+        Some(config) => {
+            info!("Try discover OpenID provider");
+            let client = OidcClient::discover(config).await?;
+            Some(oidc_router(Arc::clone(&conn), Arc::new(client)))
+        }
+        None => None,
+    };
+
+    info!("Try generate search engine contexts");
+    let backend = BackendKind::from_env()?;
+    let fallback = BackendKind::fallback_from_env()?;
+    info!("Search backend: {backend:?} (fallback: {fallback:?})");
+    let app = match (backend, fallback) {
+        (BackendKind::Meilisearch, Some(BackendKind::Postgres)) => {
+            let backends = meilisearch_backends()
+                .await?
+                .with_fallback(pg_backends(&conn));
+            let contexts = init_search_contexts(&conn, backends).await?;
+            router(conn, contexts)
+        }
+        (BackendKind::Meilisearch, Some(BackendKind::Memory)) => {
+            let backends = meilisearch_backends()
+                .await?
+                .with_fallback(memory_backends(&conn).await?);
+            let contexts = init_search_contexts(&conn, backends).await?;
+            router(conn, contexts)
+        }
+        (BackendKind::Meilisearch, _) => {
+            let backends = meilisearch_backends().await?;
+            let contexts = init_search_contexts(&conn, backends).await?;
+            router(conn, contexts)
+        }
+        (BackendKind::Postgres, _) => {
+            let backends = pg_backends(&conn);
+            let contexts = init_search_contexts(&conn, backends).await?;
+            router(conn, contexts)
+        }
+        (BackendKind::Memory, _) => {
+            let backends = memory_backends(&conn).await?;
+            let contexts = init_search_contexts(&conn, backends).await?;
+            router(conn, contexts)
+        }
+    };
+    info!("Success generate search engine contexts");
+    let app = match oidc {
+        Some(oidc) => app.merge(oidc),
+        None => app,
+    };
+
+    // サーバーの実行
+    axum::Server::bind(&bind)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .map_err(|_| QrError::Serve)?;
+
+    Ok(())
+}
+
+/// インデックスごとの検索バックエンド
+struct Backends<B> {
+    fixtures: B,
+    spot: B,
+    container: B,
+    lending: B,
+}
+
+impl<P: SearchBackend> Backends<P> {
+    /// 検索に失敗したときに代わりに使うバックエンドを組み合わせる
+    fn with_fallback<S: SearchBackend>(
+        self,
+        secondary: Backends<S>,
+    ) -> Backends<FallbackContext<P, S>> {
+        Backends {
+            fixtures: FallbackContext::new(self.fixtures, secondary.fixtures),
+            spot: FallbackContext::new(self.spot, secondary.spot),
+            container: FallbackContext::new(self.container, secondary.container),
+            lending: FallbackContext::new(self.lending, secondary.lending),
+        }
+    }
+}
+
+async fn meilisearch_backends() -> Result<Backends<MeilisearchContext>> {
+    Ok(Backends {
+        fixtures: MeilisearchContext::new("fixtures", "id").await?,
+        spot: MeilisearchContext::new("spot", "id")
+            .await?
+            .with_searchable_attributes(&SPOT_SEARCHABLE_ATTRIBUTES),
+        container: MeilisearchContext::new("container", "id")
+            .await?
+            .with_searchable_attributes(&CONTAINER_SEARCHABLE_ATTRIBUTES),
+        lending: MeilisearchContext::new("lending", "id")
+            .await?
+            .with_searchable_attributes(&LENDING_SEARCHABLE_ATTRIBUTES),
+    })
+}
+
+fn pg_backends(conn: &Arc<Pool<Postgres>>) -> Backends<PgSearchContext> {
+    Backends {
+        fixtures: PgSearchContext::fixtures(Arc::clone(conn)),
+        spot: PgSearchContext::spot(Arc::clone(conn)),
+        container: PgSearchContext::container(Arc::clone(conn)),
+        lending: PgSearchContext::lending(Arc::clone(conn)),
+    }
+}
+
+/// データベースにある情報を読み込んだプロセス内の検索コンテキストを作る
+async fn memory_backends(conn: &Pool<Postgres>) -> Result<Backends<InMemoryContext>> {
+    let fixtures = InMemoryContext::new("fixtures", "id");
+    let lending_list = crate::database::get_lending_list::get_lending_list(conn).await?;
+    let lst = crate::database::get_fixtures_list::get_fixtures_list(conn)
+        .await?
+        .into_iter()
+        .map(|fixtures| FixturesDocument {
+            is_lending: lending_list.iter().any(|l| l.fixtures_id == fixtures.id),
+            fixtures,
+        })
+        .collect::<Vec<_>>();
+    fixtures.add_or_replace_documents(&lst).await?;
+
+    let spot =
+        InMemoryContext::new("spot", "id").with_searchable_attributes(&SPOT_SEARCHABLE_ATTRIBUTES);
+    let lst = crate::database::get_spot_list::get_spot_list(conn)
+        .await?
+        .into_iter()
+        .map(SpotDocument::from)
+        .collect::<Vec<_>>();
+    spot.add_or_replace_documents(&lst).await?;
+
+    let container = InMemoryContext::new("container", "id")
+        .with_searchable_attributes(&CONTAINER_SEARCHABLE_ATTRIBUTES);
+    let lst = crate::database::get_container_list::get_container_list(conn).await?;
+    container.add_or_replace_documents(&lst).await?;
+
+    let lending = InMemoryContext::new("lending", "id")
+        .with_searchable_attributes(&LENDING_SEARCHABLE_ATTRIBUTES);
+    lending.add_or_replace_documents(&lending_list).await?;
+
+    Ok(Backends {
+        fixtures,
+        spot,
+        container,
+        lending,
+    })
+}
+
+/// 検索コンテキストを作り、インデックスの設定を行う
+/// 同義語はデータベースに登録されているものを読み込む
+async fn init_search_contexts<B: SearchBackend>(
+    conn: &Pool<Postgres>,
+    backends: Backends<B>,
+) -> Result<SearchContexts<B>> {
+    let contexts = SearchContexts::new(
+        backends.fixtures,
+        backends.spot,
+        backends.container,
+        backends.lending,
+    );
+    let synonyms = crate::database::get_synonym_list::get_synonym_list(conn).await?;
+    contexts.init(&synonyms).await?;
+    Ok(contexts)
+}
+
+/// pathと関数の実体の紐づけ
+/// 検索エンジンの実体を差し替えられるようにサーバーの起動とは分けておく
+pub fn router<B>(conn: Arc<Pool<Postgres>>, search_contexts: SearchContexts<B>) -> Router
+where
+    B: SearchBackend + 'static,
+{
+    let api = Router::new()
         .route(
             "/ping",
             get({
@@ -62,16 +259,34 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
                 ping
             }),
         )
+        .route(
+            "/openapi.json",
+            get({
+                info!("GET /openapi.json");
+                openapi::openapi_json
+            }),
+        )
+        .route(
+            "/docs",
+            get({
+                info!("GET /docs");
+                openapi::docs
+            }),
+        )
+        .route(
+            "/get_purge_report",
+            get({
+                info!("GET /get_purge_report");
+                get_purge_report
+            }),
+        )
         .route(
             "/insert_fixtures",
             post({
                 info!("POST /insert_fixtures");
                 let conn = Arc::clone(&conn);
-                let context = Arc::clone(&search_fixtures_context);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| {
-                    fixtures::insert_fixtures(bearer, body, conn, context)
-                }
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |body| fixtures::insert_fixtures(body, conn, context)
             }),
         )
         .route(
@@ -79,9 +294,15 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /update_fixtures");
                 let conn = Arc::clone(&conn);
-                let context = Arc::clone(&search_fixtures_context);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| fixtures::update_fixtures(bearer, body, conn, context)
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |legacy: Option<Extension<Legacy>>, headers: HeaderMap, body| {
+                    fixtures::update_fixtures(
+                        body,
+                        etag::legacy_if_match(&headers, legacy.is_some()),
+                        conn,
+                        context,
+                    )
+                }
             }),
         )
         .route(
@@ -89,11 +310,8 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             delete({
                 info!("DELETE /delete_fixtures");
                 let conn = Arc::clone(&conn);
-                let context = Arc::clone(&search_fixtures_context);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      Query(query)| {
-                    fixtures::delete_fixtures(bearer, query, conn, context)
-                }
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |Query(query)| fixtures::delete_fixtures(query, conn, context)
             }),
         )
         .route(
@@ -101,21 +319,70 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             get({
                 info!("GET /get_fixtures");
                 let conn = Arc::clone(&conn);
-                move |Query(query)| fixtures::get_fixtures(query, conn)
+                move |Query(query): Query<HashMap<String, String>>| {
+                    let key = match (query.get("id"), query.get("qr_id")) {
+                        (Some(id), _) => Uuid::parse_str(id).ok().map(VersionKey::FixturesId),
+                        (_, Some(qr_id)) => Some(VersionKey::FixturesQrId(qr_id.clone())),
+                        _ => None,
+                    };
+                    etag::read_with_etag(
+                        Arc::clone(&conn),
+                        key,
+                        fixtures::get_fixtures(query, conn),
+                    )
+                }
             }),
         )
         .route(
             "/search_fixtures",
             get({
                 info!("GET /search_fixtures");
-                let context = Arc::clone(&search_fixtures_context);
-                move |query: Query<HashMap<String, String>>| {
-                    let keywords_str = query
-                        .0
-                        .get("keywords")
-                        .map(|s| s.to_string())
-                        .unwrap_or_default();
-                    fixtures::search_fixtures(keywords_str, context)
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |legacy: Option<Extension<Legacy>>, Query(query)| async move {
+                    match legacy {
+                        Some(_) => fixtures::search_fixtures_legacy(query, context)
+                            .await
+                            .into_response(),
+                        None => fixtures::search_fixtures(query, context)
+                            .await
+                            .into_response(),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/insert_synonym",
+            post({
+                info!("POST /insert_synonym");
+                let conn = Arc::clone(&conn);
+                let contexts = search_contexts.clone();
+                move |body| synonym::insert_synonym(body, conn, contexts)
+            }),
+        )
+        .route(
+            "/get_synonym_list",
+            get({
+                info!("GET /get_synonym_list");
+                let conn = Arc::clone(&conn);
+                move || synonym::get_synonym_list(conn)
+            }),
+        )
+        .route(
+            "/delete_synonym",
+            delete({
+                info!("DELETE /delete_synonym");
+                let conn = Arc::clone(&conn);
+                let contexts = search_contexts.clone();
+                move |Query(query)| synonym::delete_synonym(query, conn, contexts)
+            }),
+        )
+        .route(
+            "/search",
+            get({
+                info!("GET /search");
+                let contexts = search_contexts.clone();
+                move |Extension(caller): Extension<Caller>, Query(query)| {
+                    search::search(caller.role, query, contexts)
                 }
             }),
         )
@@ -124,8 +391,8 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /insert_lending");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| lending::insert_lending(bearer, body, conn)
+                let contexts = search_contexts.clone();
+                move |body| lending::insert_lending(body, conn, contexts)
             }),
         )
         .route(
@@ -133,8 +400,15 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /update_lending");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| lending::update_lending(bearer, body, conn)
+                let contexts = search_contexts.clone();
+                move |legacy: Option<Extension<Legacy>>, headers: HeaderMap, body| {
+                    lending::update_lending(
+                        body,
+                        etag::legacy_if_match(&headers, legacy.is_some()),
+                        conn,
+                        contexts,
+                    )
+                }
             }),
         )
         .route(
@@ -142,10 +416,10 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /returned_lending");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      Query(query)| {
+                let contexts = search_contexts.clone();
+                move |Query(query)| {
                     let now = Utc::now();
-                    lending::returned_lending(bearer, query, now, conn)
+                    lending::returned_lending(query, now, conn, contexts)
                 }
             }),
         )
@@ -154,7 +428,9 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             get({
                 info!("GET /get_lending_list");
                 let conn = Arc::clone(&conn);
-                move || lending::get_lending_list(conn)
+                move |Extension(caller): Extension<Caller>| {
+                    lending::get_lending_list(caller.role, conn)
+                }
             }),
         )
         .route(
@@ -162,7 +438,9 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             get({
                 info!("GET /get_lending");
                 let conn = Arc::clone(&conn);
-                move |Query(query)| lending::get_one_lending(query, conn)
+                move |Extension(caller): Extension<Caller>, Query(query)| {
+                    lending::get_one_lending(caller.role, query, conn)
+                }
             }),
         )
         .route(
@@ -178,8 +456,8 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /insert_spot");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| spot::insert_spot(bearer, body, conn)
+                let context = Arc::clone(&search_contexts.spot);
+                move |body| spot::insert_spot(body, conn, context)
             }),
         )
         .route(
@@ -187,8 +465,15 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /update_spot");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| spot::update_spot(bearer, body, conn)
+                let context = Arc::clone(&search_contexts.spot);
+                move |legacy: Option<Extension<Legacy>>, headers: HeaderMap, body| {
+                    spot::update_spot(
+                        body,
+                        etag::legacy_if_match(&headers, legacy.is_some()),
+                        conn,
+                        context,
+                    )
+                }
             }),
         )
         .route(
@@ -196,7 +481,10 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             get({
                 info!("GET /get_spot");
                 let conn = Arc::clone(&conn);
-                move |Query(query)| spot::get_one_spot(query, conn)
+                move |Query(query): Query<HashMap<String, String>>| {
+                    let key = query.get("name").cloned().map(VersionKey::Spot);
+                    etag::read_with_etag(Arc::clone(&conn), key, spot::get_one_spot(query, conn))
+                }
             }),
         )
         .route(
@@ -212,8 +500,8 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             delete({
                 info!("DELETE /delete_spot");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      Query(query)| spot::delte_spot(bearer, query, conn)
+                let context = Arc::clone(&search_contexts.spot);
+                move |Query(query)| spot::delte_spot(query, conn, context)
             }),
         )
         .route(
@@ -221,8 +509,8 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /insert_container");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
-                      body| container::insert_container(bearer, body, conn)
+                let context = Arc::clone(&search_contexts.container);
+                move |body| container::insert_container(body, conn, context)
             }),
         )
         .route(
@@ -230,28 +518,1358 @@ pub async fn app(bind: SocketAddr) -> Result<()> {
             post({
                 info!("POST /gen_passtoken");
                 let conn = Arc::clone(&conn);
-                move |TypedHeader(Authorization(basic)): TypedHeader<Authorization<Basic>>| {
-                    authentication::api_gen_passtoken(basic, conn)
+                move |connect_info: Option<ConnectInfo<SocketAddr>>,
+                      headers: HeaderMap,
+                      TypedHeader(Authorization(basic)): TypedHeader<Authorization<Basic>>| {
+                    let ip =
+                        authentication::client_ip(connect_info.map(|ConnectInfo(a)| a), &headers);
+                    authentication::api_gen_passtoken(basic, ip, conn)
                 }
             }),
         )
-        .layer(
-            CorsLayer::new()
-                .allow_methods([Method::GET, Method::POST, Method::DELETE])
-                .allow_headers([CONTENT_TYPE, AUTHORIZATION])
-                .allow_origin(Any),
-        );
+        .route(
+            "/refresh_token",
+            post({
+                info!("POST /refresh_token");
+                let conn = Arc::clone(&conn);
+                move |body| authentication::refresh_token(body, conn)
+            }),
+        )
+        .route(
+            "/me",
+            get({
+                info!("GET /me");
+                let conn = Arc::clone(&conn);
+                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>| {
+                    authentication::me(bearer, conn)
+                }
+            }),
+        )
+        .route(
+            "/logout",
+            post({
+                info!("POST /logout");
+                let conn = Arc::clone(&conn);
+                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>| {
+                    authentication::logout(bearer, conn)
+                }
+            }),
+        )
+        .route(
+            "/get_passtoken_list",
+            get({
+                info!("GET /get_passtoken_list");
+                let conn = Arc::clone(&conn);
+                move |Query(query)| authentication::get_passtoken_list(query, conn)
+            }),
+        )
+        .route(
+            "/revoke_passtoken",
+            post({
+                info!("POST /revoke_passtoken");
+                let conn = Arc::clone(&conn);
+                move |Query(query)| authentication::revoke_passtoken(query, conn)
+            }),
+        )
+        .route(
+            "/revoke_all_passtoken",
+            post({
+                info!("POST /revoke_all_passtoken");
+                let conn = Arc::clone(&conn);
+                move |TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+                      Query(query)| {
+                    authentication::revoke_all_passtoken(bearer, query, conn)
+                }
+            }),
+        )
+        .route(
+            "/get_login_failure_list",
+            get({
+                info!("GET /get_login_failure_list");
+                let conn = Arc::clone(&conn);
+                move |Query(query)| authentication::get_login_failure_list(query, conn)
+            }),
+        )
+        .route(
+            "/insert_api_key",
+            post({
+                info!("POST /insert_api_key");
+                let conn = Arc::clone(&conn);
+                move |body| authentication::insert_api_key(body, conn)
+            }),
+        )
+        .route(
+            "/get_api_key_list",
+            get({
+                info!("GET /get_api_key_list");
+                let conn = Arc::clone(&conn);
+                move || authentication::get_api_key_list(conn)
+            }),
+        )
+        .route(
+            "/revoke_api_key",
+            post({
+                info!("POST /revoke_api_key");
+                let conn = Arc::clone(&conn);
+                move |Query(query)| authentication::revoke_api_key(query, conn)
+            }),
+        )
+        .route(
+            "/insert_user",
+            post({
+                info!("POST /insert_user");
+                let conn = Arc::clone(&conn);
+                move |body| user::insert_user(body, conn)
+            }),
+        )
+        .route(
+            "/update_user",
+            post({
+                info!("POST /update_user");
+                let conn = Arc::clone(&conn);
+                move |body| user::update_user(body, conn)
+            }),
+        )
+        .route(
+            "/get_user_list",
+            get({
+                info!("GET /get_user_list");
+                let conn = Arc::clone(&conn);
+                move || user::get_user_list(conn)
+            }),
+        )
+        .route(
+            "/get_user",
+            get({
+                info!("GET /get_user");
+                let conn = Arc::clone(&conn);
+                move |Query(query)| user::get_one_user(query, conn)
+            }),
+        )
+        .route(
+            "/delete_user",
+            delete({
+                info!("DELETE /delete_user");
+                let conn = Arc::clone(&conn);
+                move |Query(query)| user::delete_user(query, conn)
+            }),
+        )
+        .merge(resource_router(&conn, &search_contexts))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&conn),
+            idempotency::idempotency,
+        ))
+        .route_layer(middleware::from_fn_with_state(conn, policy::authorize));
+    version::versioned(api).layer(
+        CorsLayer::new()
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers([
+                CONTENT_TYPE,
+                AUTHORIZATION,
+                IF_MATCH,
+                idempotency::IDEMPOTENCY_KEY,
+            ])
+            .expose_headers([ETAG, idempotency::IDEMPOTENT_REPLAYED])
+            .allow_origin(Any),
+    )
+}
 
-    // サーバーの実行
-    axum::Server::bind(&bind)
-        .serve(app.into_make_service())
-        .await
-        .map_err(|_| QrError::Serve)?;
+/// パスの一部をクエリと同じ形にする
+/// RPC形式のエンドポイントと同じ関数を使えるようにするためのもの
+fn path_query(key: &str, value: String) -> HashMap<String, String> {
+    HashMap::from([(key.to_string(), value)])
+}
 
-    Ok(())
+/// 物品・貸出情報・地点・コンテナ・同義語をリソースとして扱うエンドポイント
+/// `/fixtures/:id`のように対象をパスで指定し、操作はHTTPメソッドで表す
+fn resource_router<B>(conn: &Arc<Pool<Postgres>>, search_contexts: &SearchContexts<B>) -> Router
+where
+    B: SearchBackend + 'static,
+{
+    Router::new()
+        .route(
+            "/fixtures",
+            get({
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |Query(query)| fixtures::search_fixtures(query, context)
+            })
+            .post({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |body| fixtures::insert_fixtures(body, conn, context)
+            }),
+        )
+        .route(
+            "/fixtures/:id",
+            get({
+                let conn = Arc::clone(conn);
+                move |Path(id): Path<String>| {
+                    let key = Uuid::parse_str(&id).ok().map(VersionKey::FixturesId);
+                    let read = fixtures::get_fixtures(path_query("id", id), conn.clone());
+                    etag::read_with_etag(conn, key, read)
+                }
+            })
+            .put({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |Path(id): Path<String>, headers: HeaderMap, body| {
+                    let if_match = etag::require_if_match(&headers);
+                    fixtures::replace_fixtures(id, body, if_match, conn, context)
+                }
+            })
+            .patch({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |Path(id): Path<String>, headers: HeaderMap, body| {
+                    let if_match = etag::require_if_match(&headers);
+                    fixtures::patch_fixtures(id, body, if_match, conn, context)
+                }
+            })
+            .delete({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.fixtures);
+                move |Path(id): Path<String>| {
+                    fixtures::delete_fixtures(path_query("id", id), conn, context)
+                }
+            }),
+        )
+        .route(
+            "/fixtures/by-qr/:qr_id",
+            get({
+                let conn = Arc::clone(conn);
+                move |Path(qr_id): Path<String>| {
+                    let key = Some(VersionKey::FixturesQrId(qr_id.clone()));
+                    let read = fixtures::get_fixtures(path_query("qr_id", qr_id), conn.clone());
+                    etag::read_with_etag(conn, key, read)
+                }
+            }),
+        )
+        .route(
+            "/fixtures/:id/lending",
+            get({
+                let conn = Arc::clone(conn);
+                move |Extension(caller): Extension<Caller>, Path(id): Path<String>| {
+                    lending::get_one_lending(caller.role, path_query("fixtures_id", id), conn)
+                }
+            }),
+        )
+        .route(
+            "/fixtures/:id/return",
+            post({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |Path(id): Path<String>| {
+                    lending::returned_lending(path_query("id", id), Utc::now(), conn, contexts)
+                }
+            }),
+        )
+        .route(
+            "/fixtures/by-qr/:qr_id/return",
+            post({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |Path(qr_id): Path<String>| {
+                    let query = path_query("qr_id", qr_id);
+                    lending::returned_lending(query, Utc::now(), conn, contexts)
+                }
+            }),
+        )
+        .route(
+            "/lendings",
+            get({
+                let conn = Arc::clone(conn);
+                move |Extension(caller): Extension<Caller>| {
+                    lending::get_lending_list(caller.role, conn)
+                }
+            })
+            .post({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |body| lending::insert_lending(body, conn, contexts)
+            }),
+        )
+        .route(
+            "/lendings/:id",
+            get({
+                let conn = Arc::clone(conn);
+                move |Extension(caller): Extension<Caller>, Path(id): Path<String>| {
+                    let key = Uuid::parse_str(&id).ok().map(VersionKey::LendingId);
+                    let query = path_query("lending_id", id);
+                    let read = lending::get_one_lending(caller.role, query, conn.clone());
+                    etag::read_with_etag(conn, key, read)
+                }
+            })
+            .put({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |Path(id): Path<String>, headers: HeaderMap, body| {
+                    let if_match = etag::require_if_match(&headers);
+                    lending::replace_lending(id, body, if_match, conn, contexts)
+                }
+            })
+            .patch({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |Path(id): Path<String>, headers: HeaderMap, body| {
+                    let if_match = etag::require_if_match(&headers);
+                    lending::patch_lending(id, body, if_match, conn, contexts)
+                }
+            }),
+        )
+        .route(
+            "/lendings/by-qr/:qr_id",
+            get({
+                let conn = Arc::clone(conn);
+                move |Extension(caller): Extension<Caller>, Path(qr_id): Path<String>| {
+                    let query = path_query("fixtures_qr_id", qr_id);
+                    lending::get_one_lending(caller.role, query, conn)
+                }
+            }),
+        )
+        .route(
+            "/spots",
+            get({
+                let conn = Arc::clone(conn);
+                move || spot::get_spot_list(conn)
+            })
+            .post({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.spot);
+                move |body| spot::insert_spot(body, conn, context)
+            }),
+        )
+        .route(
+            "/spots/:name",
+            get({
+                let conn = Arc::clone(conn);
+                move |Path(name): Path<String>| {
+                    let key = Some(VersionKey::Spot(name.clone()));
+                    let read = spot::get_one_spot(path_query("name", name), conn.clone());
+                    etag::read_with_etag(conn, key, read)
+                }
+            })
+            .put({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.spot);
+                move |Path(name): Path<String>, headers: HeaderMap, Json(spot): Json<Spot>| {
+                    let body = Json(Spot { name, ..spot });
+                    spot::update_spot(body, etag::require_if_match(&headers), conn, context)
+                }
+            })
+            .patch({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.spot);
+                move |Path(name): Path<String>, headers: HeaderMap, body| {
+                    let if_match = etag::require_if_match(&headers);
+                    spot::patch_spot(name, body, if_match, conn, context)
+                }
+            })
+            .delete({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.spot);
+                move |Path(name): Path<String>| {
+                    spot::delte_spot(path_query("name", name), conn, context)
+                }
+            }),
+        )
+        .route(
+            "/containers",
+            get({
+                let conn = Arc::clone(conn);
+                move || container::get_container_list(conn)
+            })
+            .post({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.container);
+                move |body| container::insert_container(body, conn, context)
+            }),
+        )
+        .route(
+            "/containers/:id",
+            get({
+                let conn = Arc::clone(conn);
+                move |Path(id): Path<String>| {
+                    let key = Uuid::parse_str(&id).ok().map(VersionKey::ContainerId);
+                    let read = container::get_one_container(path_query("id", id), conn.clone());
+                    etag::read_with_etag(conn, key, read)
+                }
+            })
+            .put({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.container);
+                move |Path(id): Path<String>, headers: HeaderMap, body| {
+                    let if_match = etag::require_if_match(&headers);
+                    container::replace_container(id, body, if_match, conn, context)
+                }
+            })
+            .delete({
+                let conn = Arc::clone(conn);
+                let context = Arc::clone(&search_contexts.container);
+                move |Path(id): Path<String>| {
+                    container::delete_container(path_query("id", id), conn, context)
+                }
+            }),
+        )
+        .route(
+            "/containers/by-qr/:qr_id",
+            get({
+                let conn = Arc::clone(conn);
+                move |Path(qr_id): Path<String>| {
+                    let key = Some(VersionKey::ContainerQrId(qr_id.clone()));
+                    let query = path_query("qr_id", qr_id);
+                    let read = container::get_one_container(query, conn.clone());
+                    etag::read_with_etag(conn, key, read)
+                }
+            }),
+        )
+        .route(
+            "/synonyms",
+            get({
+                let conn = Arc::clone(conn);
+                move || synonym::get_synonym_list(conn)
+            })
+            .post({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |body| synonym::insert_synonym(body, conn, contexts)
+            }),
+        )
+        .route(
+            "/synonyms/:id",
+            delete({
+                let conn = Arc::clone(conn);
+                let contexts = search_contexts.clone();
+                move |Path(id): Path<String>| {
+                    synonym::delete_synonym(path_query("id", id), conn, contexts)
+                }
+            }),
+        )
+}
+
+/// OpenID Connectでのログインに使うエンドポイント
+/// `OIDC_ISSUER_URL`が設定されている時だけ追加する
+pub fn oidc_router(conn: Arc<Pool<Postgres>>, client: Arc<OidcClient>) -> Router {
+    let api = Router::new()
+        .route(
+            "/oidc_login",
+            get({
+                info!("GET /oidc_login");
+                let conn = Arc::clone(&conn);
+                let client = Arc::clone(&client);
+                move || authentication::oidc_login(conn, client)
+            }),
+        )
+        .route(
+            "/oidc_callback",
+            get({
+                info!("GET /oidc_callback");
+                let conn = Arc::clone(&conn);
+                let client = Arc::clone(&client);
+                move |Query(query)| authentication::oidc_callback(query, conn, client)
+            }),
+        )
+        .route_layer(middleware::from_fn_with_state(conn, policy::authorize));
+    version::versioned(api).layer(
+        CorsLayer::new()
+            .allow_methods([Method::GET])
+            .allow_headers([CONTENT_TYPE, AUTHORIZATION])
+            .allow_origin(Any),
+    )
 }
 
 /// ダミー
 pub async fn ping() -> &'static str {
     "pong"
 }
+
+/// 古いデータの削除の実行結果を返すエンドポイント
+pub async fn get_purge_report() -> ReturnData<PurgeReport> {
+    result_to_handler(&Ok(purge_expired::purge_report())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::router;
+    use crate::authentication::{insert_passtoken, Passtoken, Role};
+    use crate::search_engine::{
+        memory::InMemoryContext, SearchContexts, LENDING_SEARCHABLE_ATTRIBUTES,
+    };
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use sqlx::{pool::Pool, Postgres};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn memory_contexts() -> SearchContexts<InMemoryContext> {
+        SearchContexts::new(
+            InMemoryContext::new("fixtures", "id"),
+            InMemoryContext::new("spot", "id"),
+            InMemoryContext::new("container", "id"),
+            InMemoryContext::new("lending", "id")
+                .with_searchable_attributes(&LENDING_SEARCHABLE_ATTRIBUTES),
+        )
+    }
+
+    async fn body_json(res: axum::response::Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// 貸出情報の貸し出し先にする地点を登録する
+    async fn insert_test_spot(pool: &Pool<Postgres>, name: &str) {
+        let spot = serde_json::from_value(serde_json::json!({"name": name, "area": "area3"}));
+        crate::database::insert_spot::insert_spot(pool, spot.unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_insert_and_search_fixtures(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::Administrator, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+
+        let body = serde_json::json!({
+          "qr_id": "test",
+          "qr_color":"red",
+          "name":"延長コード",
+          "description":"テスト説明",
+          "storage": "room101",
+          "usage": "無い",
+          "note": "DBを確認",
+          "parent_id": "null"
+        });
+        let res = app
+            .clone()
+            .oneshot(
+                Request::post("/insert_fixtures")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        // IDと作成日時はサーバーで振り、作成した物品の場所を返す
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let location = res.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = body_json(res).await;
+        assert_eq!(
+            location,
+            format!("/v1/fixtures/{}", body["data"]["id"].as_str().unwrap())
+        );
+        assert!(body["data"]["created_at"].is_string());
+
+        let keywords = url::form_urlencoded::byte_serialize("ｺｰﾄﾞ".as_bytes()).collect::<String>();
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get(format!(
+                    "/v1/search_fixtures?keywords={keywords}&storage=room101&is_lending=false"
+                ))
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .body(Body::empty())
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"]["hits"][0]["data"]["name"], "延長コード");
+        assert_eq!(body["data"]["facets"]["storage"]["room101"], 1);
+
+        // 従来のパスでは以前と同じく検索結果の物品の一覧を返す
+        let res = app
+            .oneshot(
+                Request::get(format!("/search_fixtures?keywords={keywords}"))
+                    .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"][0]["data"]["name"], "延長コード");
+        assert!(body["data"][0]["data"].get("is_lending").is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_search_all(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        insert_test_spot(&pool, "test").await;
+        let app = router(Arc::new(pool), memory_contexts());
+
+        let insert = |uri: &str, body: serde_json::Value| {
+            Request::post(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let res = app
+            .clone()
+            .oneshot(insert(
+                "/insert_fixtures",
+                serde_json::json!({
+                  "qr_id": "test",
+                  "qr_color":"red",
+                  "name":"延長コード",
+                  "storage": "room101",
+                  "note": "",
+                  "parent_id": "null"
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let fixtures_id = body_json(res).await["data"]["id"].clone();
+        let res = app
+            .clone()
+            .oneshot(insert(
+                "/insert_lending",
+                serde_json::json!({
+                  "fixtures_id": fixtures_id,
+                  "fixtures_qr_id": "test",
+                  "spot_name": "test",
+                  "borrower_name": "筑波太郎",
+                  "borrower_number": 202200000,
+                  "borrower_org": "情報科学類"
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let keywords =
+            url::form_urlencoded::byte_serialize("情報科学類".as_bytes()).collect::<String>();
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/search?keywords={keywords}"))
+                    .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"]["lending"]["total"], 1);
+        assert_eq!(
+            body["data"]["lending"]["hits"][0]["data"]["fixtures_qr_id"],
+            "test"
+        );
+        assert_eq!(body["data"]["fixtures"]["total"], 0);
+
+        // 返却すると貸し出し中の一覧からは消える
+        let res = app
+            .clone()
+            .oneshot(
+                Request::post("/returned_lending?qr_id=test")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .oneshot(
+                Request::get(format!("/search?keywords={keywords}&types=lending"))
+                    .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = body_json(res).await;
+        assert_eq!(body["data"]["lending"]["total"], 0);
+        assert!(body["data"].get("fixtures").is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_read_requires_general(pool: Pool<Postgres>) {
+        let manager = Passtoken::new(Role::EquipmentManager, 1);
+        let general = Passtoken::new(Role::General, 1);
+        insert_passtoken(&pool, &manager).await.unwrap();
+        insert_passtoken(&pool, &general).await.unwrap();
+        insert_test_spot(&pool, "test").await;
+        let app = router(Arc::new(pool), memory_contexts());
+
+        let body = serde_json::json!({
+          "fixtures_id": "550e8400-e29b-41d4-a716-446655440000",
+          "fixtures_qr_id": "test",
+          "spot_name": "test",
+          "borrower_name": "筑波太郎",
+          "borrower_number": 202200000,
+          "borrower_org": "情報科学類"
+        });
+        let res = app
+            .clone()
+            .oneshot(
+                Request::post("/insert_lending")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", manager.token))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // 権限が足りない場合はトークンが無い場合と区別する
+        let res = app
+            .clone()
+            .oneshot(
+                Request::post("/insert_lending")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", general.token))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // トークンが無ければ閲覧できない
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/get_lending_list")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // 一般ユーザーには学籍番号を返さない
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/get_lending_list")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", general.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"][0]["borrower_name"], "筑波太郎");
+        assert!(body["data"][0].get("borrower_number").is_none());
+
+        let res = app
+            .oneshot(
+                Request::get("/get_lending?fixtures_qr_id=test")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", manager.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"]["borrower_number"], 202200000);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_me(pool: Pool<Postgres>) {
+        let manager = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &manager).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/me")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", manager.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"]["role"], "equipment_manager");
+        assert!(body["data"]["expires_at"].is_string());
+        let operations = body["data"]["operations"].as_array().unwrap();
+        assert!(operations
+            .iter()
+            .any(|o| o["method"] == "POST" && o["path"] == "/v1/insert_fixtures"));
+        assert!(operations
+            .iter()
+            .any(|o| o["method"] == "PATCH" && o["path"] == "/v1/fixtures/:id"));
+        assert!(!operations
+            .iter()
+            .any(|o| o["path"] == "/v1/delete_fixtures"));
+
+        let res = app
+            .oneshot(Request::get("/me").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_resource_routes(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::Administrator, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+        let request = |method: &str, uri: &str, body: Option<&serde_json::Value>| {
+            let builder = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_MATCH, "*");
+            match body {
+                Some(body) => builder.body(Body::from(body.to_string())).unwrap(),
+                None => builder.body(Body::empty()).unwrap(),
+            }
+        };
+
+        let mut body = serde_json::json!({
+          "qr_id": "test",
+          "qr_color":"red",
+          "name":"延長コード",
+          "description":"テスト説明",
+          "storage": "room101",
+          "usage": "無い",
+          "note": "DBを確認",
+          "parent_id": "null"
+        });
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/fixtures", Some(&body)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let id = body_json(res).await["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let res = app
+            .clone()
+            .oneshot(request("GET", "/fixtures/by-qr/test", None))
+            .await
+            .unwrap();
+        assert_eq!(body_json(res).await["data"]["id"], id);
+
+        // 本文のidではなくパスのidの物品が更新される
+        body["id"] = serde_json::json!("550e8400-e29b-41d4-a716-446655440009");
+        body["created_at"] = serde_json::json!("2023-08-07 15:56:35 UTC");
+        body["name"] = serde_json::json!("ドラム");
+        let res = app
+            .clone()
+            .oneshot(request("PUT", &format!("/fixtures/{id}"), Some(&body)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(request("GET", &format!("/fixtures/{id}"), None))
+            .await
+            .unwrap();
+        assert_eq!(body_json(res).await["data"]["name"], "ドラム");
+
+        let res = app
+            .clone()
+            .oneshot(request("DELETE", &format!("/fixtures/{id}"), None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(request("GET", &format!("/fixtures/{id}"), None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = app
+            .oneshot(request("GET", "/fixtures/broken", None))
+            .await
+            .unwrap();
+        assert_eq!(body_json(res).await["error_type"], "BrokenUuid");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_versioned_routes(pool: Pool<Postgres>) {
+        let manager = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &manager).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+
+        // バージョン付きのパスでも同じ権限の一覧で検査する
+        let res = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/fixtures/by-qr/unknown")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", manager.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(res.headers().get("deprecation").is_none());
+        let body = body_json(res).await;
+        assert_eq!(body["version"], 1);
+        assert_eq!(body["error_type"], "DatabaseNotFound");
+        let res = app
+            .clone()
+            .oneshot(
+                Request::delete("/v1/delete_fixtures?id=550e8400-e29b-41d4-a716-446655440000")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", manager.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // 従来のパスは非推奨のヘッダーを付けて応答する
+        let res = app
+            .oneshot(
+                Request::get("/me")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", manager.token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["deprecation"], "true");
+        assert_eq!(res.headers()["link"], "</v1/me>; rel=\"successor-version\"");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_patch_fixtures(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+        let request = |method: &str, uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_MATCH, "*")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let body = serde_json::json!({
+          "qr_id": "test",
+          "qr_color":"red",
+          "name":"延長コード",
+          "description":"テスト説明",
+          "storage": "room101",
+          "usage": "無い",
+          "note": "DBを確認",
+          "parent_id": "null"
+        });
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/v1/fixtures", body))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let uri = res.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // 含まれる項目だけが変わり、nullを指定した項目は消える
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                &uri,
+                serde_json::json!({"name": "ドラム", "description": null}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = body_json(res).await;
+        assert_eq!(body["data"]["name"], "ドラム");
+        assert!(body["data"]["description"].is_null());
+        assert_eq!(body["data"]["usage"], "無い");
+
+        // 変更できない項目は受け付けない
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                &uri,
+                serde_json::json!({"created_at": "2024-01-01 00:00:00 UTC"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(res).await["error_type"], "ImmutableField");
+
+        let keywords =
+            url::form_urlencoded::byte_serialize("ドラム".as_bytes()).collect::<String>();
+        let res = app
+            .oneshot(request(
+                "GET",
+                &format!("/v1/search_fixtures?keywords={keywords}"),
+                serde_json::json!({}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(body_json(res).await["data"]["total"], 1);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_validation(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+        let request = |uri: &str, body: serde_json::Value| {
+            Request::post(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // 不正な項目を全てまとめて返す
+        let res = app
+            .clone()
+            .oneshot(request(
+                "/v1/lendings",
+                serde_json::json!({
+                  "fixtures_id": "550e8400-e29b-41d4-a716-446655440000",
+                  "fixtures_qr_id": "test",
+                  "spot_name": "unknown",
+                  "borrower_name": "",
+                  "borrower_number": -202200000,
+                  "borrower_org": null
+                }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = body_json(res).await;
+        assert_eq!(body["error_type"], "Validation");
+        let errors = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("borrower_name", "required"),
+                ("borrower_number", "out_of_range"),
+                ("spot_name", "not_found")
+            ]
+        );
+
+        let res = app
+            .clone()
+            .oneshot(request(
+                "/v1/spots",
+                serde_json::json!({"name": "room101", "area": "area3", "floor": 9999}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body_json(res).await["errors"][0]["field"], "floor");
+
+        // 成功した場合は`errors`を含めない
+        let res = app
+            .oneshot(request(
+                "/v1/spots",
+                serde_json::json!({"name": "room101", "area": "area3", "floor": 1}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(body_json(res).await.get("errors").is_none());
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_etag(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+        let uri = "/v1/spots/room101";
+        let spot = serde_json::json!({"name": "room101", "area": "area3"});
+        let request =
+            |method: &str, uri: &str, if_match: Option<&str>, body: &serde_json::Value| {
+                let builder = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                    .header(header::CONTENT_TYPE, "application/json");
+                let builder = match if_match {
+                    Some(if_match) => builder.header(header::IF_MATCH, if_match),
+                    None => builder,
+                };
+                builder.body(Body::from(body.to_string())).unwrap()
+            };
+
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/v1/spots", None, &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(request("GET", uri, None, &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[header::ETAG], "\"1\"");
+
+        // 取得した版を指定すれば更新でき、新しい版が返る
+        let res = app
+            .clone()
+            .oneshot(request(
+                "PATCH",
+                uri,
+                Some("\"1\""),
+                &serde_json::json!({"note": "更新"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ETAG], "\"2\"");
+
+        // 古い版を指定すると他の更新を上書きしない
+        let res = app
+            .clone()
+            .oneshot(request("PUT", uri, Some("\"1\""), &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(body_json(res).await["error_type"], "PreconditionFailed");
+
+        let res = app
+            .clone()
+            .oneshot(request("PUT", uri, None, &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+
+        // `/v1`の従来のパスでも版の指定が必要で、弱いETagは一致しない
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/v1/update_spot", None, &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/v1/update_spot", Some("W/\"2\""), &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/v1/update_spot", Some("\"2\""), &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ETAG], "\"3\"");
+
+        // バージョンの無いパスでは省略できるが、送った場合は版を確かめる
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/update_spot", None, &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ETAG], "\"4\"");
+        let res = app
+            .clone()
+            .oneshot(request("POST", "/update_spot", Some("W/\"4\""), &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let res = app
+            .oneshot(request("POST", "/update_spot", Some("\"4\""), &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ETAG], "\"5\"");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_idempotency(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+        let request = |uri: &str, key: &str, body: &serde_json::Value| {
+            Request::post(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("idempotency-key", key)
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let spot = serde_json::json!({"name": "room101", "area": "area3"});
+
+        let res = app
+            .clone()
+            .oneshot(request("/v1/spots", "key1", &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("idempotent-replayed").is_none());
+
+        // 再送されたリクエストは処理せず、最初の応答を返す
+        let res = app
+            .clone()
+            .oneshot(request("/spots", "key1", &spot))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["idempotent-replayed"], "true");
+        assert_eq!(body_json(res).await["ok"], true);
+
+        // 同じキーで内容の違うリクエストは受け付けない
+        let other = serde_json::json!({"name": "room102", "area": "area3"});
+        let res = app
+            .clone()
+            .oneshot(request("/v1/spots", "key1", &other))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(body_json(res).await["error_type"], "IdempotencyKeyMismatch");
+
+        // キーが違えば別のリクエストとして処理する
+        let res = app
+            .oneshot(request("/v1/spots", "key2", &spot))
+            .await
+            .unwrap();
+        assert_eq!(body_json(res).await["error_type"], "DatabaseAdd");
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_idempotency_per_caller(pool: Pool<Postgres>) {
+        let first = Passtoken::new(Role::EquipmentManager, 1);
+        let second = Passtoken::new(Role::EquipmentManager, 1);
+        insert_passtoken(&pool, &first).await.unwrap();
+        insert_passtoken(&pool, &second).await.unwrap();
+        let app = router(Arc::new(pool), memory_contexts());
+        let logout = |passtoken: &Passtoken| {
+            Request::post("/v1/logout")
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header("idempotency-key", "key1")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let me = |passtoken: &Passtoken| {
+            Request::get("/v1/me")
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // 別のトークンが同じキーを使っても、最初の応答を返さずに処理する
+        for passtoken in [&first, &second] {
+            let res = app.clone().oneshot(logout(passtoken)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get("idempotent-replayed").is_none());
+        }
+        for passtoken in [&first, &second] {
+            let res = app.clone().oneshot(me(passtoken)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    /// 仕様書に書いたリクエストの本文と応答の`data`の型が、実際のルーターと合っているか
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_openapi_matches_router(pool: Pool<Postgres>) {
+        use crate::app::openapi::{Data, BODIES};
+        let conn = Arc::new(pool);
+        let app = router(Arc::clone(&conn), memory_contexts());
+        let id = "550e8400-e29b-41d4-a716-446655440000";
+        for (method, path, request, data) in BODIES {
+            // `/logout`などでトークンが失効しても続けられるよう、毎回発行する
+            let passtoken = Passtoken::new(Role::Administrator, 1);
+            insert_passtoken(&*conn, &passtoken).await.unwrap();
+            let uri = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with(':') {
+                        id
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            let builder = Request::builder()
+                .method(method)
+                .uri(format!("/v1{uri}"))
+                .header(header::AUTHORIZATION, format!("Bearer {}", passtoken.token))
+                .header(header::IF_MATCH, "*");
+            // 本文を読むエンドポイントだけが壊れたJSONを拒否する
+            let req = if *method == Method::GET || *method == Method::DELETE {
+                builder.body(Body::empty()).unwrap()
+            } else {
+                builder
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from("{"))
+                    .unwrap()
+            };
+            let res = app.clone().oneshot(req).await.unwrap();
+            // OpenID Connectのエンドポイントなど、設定しないとルーターに無いもの
+            if res.status() == StatusCode::NOT_FOUND {
+                continue;
+            }
+            let content_type = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let rejected = String::from_utf8_lossy(&body).starts_with("Failed to parse");
+            assert_eq!(request.is_some(), rejected, "{method} {path}");
+            if let Data::Raw(raw) = data {
+                assert!(content_type.starts_with(raw), "{method} {path}");
+                continue;
+            }
+            if rejected {
+                continue;
+            }
+            // 成功した応答だけ`data`の形を比べる
+            // 本文以外の取り出しに失敗した応答は`Msg`に包まれない
+            let Ok(body) = serde_json::from_slice::<serde_json::Value>(&body) else {
+                continue;
+            };
+            if body["ok"] != true {
+                continue;
+            }
+            let data_matches = match data {
+                Data::Empty => body["data"].is_null(),
+                Data::Bool => body["data"].is_boolean(),
+                Data::Integer => body["data"].is_u64(),
+                Data::String => body["data"].is_string(),
+                Data::One(_) | Data::Created(_) => body["data"].is_object(),
+                Data::List(_) => body["data"].is_array(),
+                Data::Page(_) => body["data"]["hits"].is_array(),
+                Data::Raw(_) => unreachable!(),
+            };
+            assert!(data_matches, "{method} {path}: {body}");
+        }
+    }
+}
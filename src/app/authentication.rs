@@ -1,15 +1,38 @@
 use crate::{
-    authentication::{self, str_to_role_opt},
+    app::policy::{permitted_operations, Operation},
+    authentication::{
+        self,
+        api_key::{self, ApiKey, IssuedApiKey, NewApiKey, Scope},
+        login_attempt::{self, LockoutConfig, LoginFailure},
+        oidc::{self, OidcClient},
+        refresh::{self, RefreshRequest, TokenPair},
+        str_to_role_opt, Passtoken, PasstokenInfo, Role,
+    },
     error_handling::{result_to_handler_with_log, QrError, Result, ReturnData},
 };
-use axum::headers::authorization::Basic;
+use axum::{
+    extract::Json,
+    headers::authorization::{Basic, Bearer},
+    http::HeaderMap,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::{pool::Pool, postgres::Postgres};
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tracing::*;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-pub async fn api_gen_passtoken(token_info: Basic, conn: Arc<Pool<Postgres>>) -> ReturnData<String> {
-    info!("Try gen passtoken: {}", token_info.username());
-    let res = gen_passtoken(token_info, conn).await;
+pub async fn api_gen_passtoken(
+    token_info: Basic,
+    ip: String,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<TokenPair> {
+    info!("Try gen passtoken: {} from {ip}", token_info.username());
+    let res = gen_passtoken(token_info, &ip, conn).await;
     result_to_handler_with_log(
         |_| Some("Success gen passtoken".to_string()),
         |e| Some(format!("Failed gen passtoken: {e}")),
@@ -18,15 +41,333 @@ pub async fn api_gen_passtoken(token_info: Basic, conn: Arc<Pool<Postgres>>) ->
     .await
 }
 
-pub async fn gen_passtoken(token_info: Basic, conn: Arc<Pool<Postgres>>) -> Result<String> {
-    let role_str = token_info.username();
+/// Basic認証の情報からアクセストークンとリフレッシュトークンの組を発行する
+/// - ユーザー名が権限の名前の場合は、共有パスキーと照合する
+/// - それ以外の場合は、アカウントの名前とパスワードと照合し、アカウントに結びついたトークンを発行する
+///
+/// 失敗が続いたIPアドレスやユーザー名はロックし、照合せずに`QrError::TooManyAttempts`を返す
+pub async fn gen_passtoken(
+    token_info: Basic,
+    ip: &str,
+    conn: Arc<Pool<Postgres>>,
+) -> Result<TokenPair> {
+    let name = token_info.username();
     let key = token_info.password();
-    match str_to_role_opt(role_str) {
-        Some(role) => {
-            let passtoken = authentication::gen_passtoken(role, key)?;
-            authentication::insert_passtoken(&*conn, &passtoken).await?;
-            Ok(passtoken.token)
+    login_attempt::check_lockout(&*conn, ip, name).await?;
+    let res = match str_to_role_opt(name) {
+        Some(role) => authentication::gen_passtoken(role, key),
+        None => authentication::user::authenticate_user(&*conn, name, key)
+            .await
+            .and_then(|user| {
+                let limit_days = authentication::limit_days(&user.role)?;
+                Ok(Passtoken::new(user.role, limit_days).with_user(user.id))
+            }),
+    };
+    let passtoken = match res {
+        Ok(passtoken) => passtoken,
+        Err(QrError::Authorized) => {
+            warn!("Failed login attempt: {name} from {ip}");
+            let config = LockoutConfig::from_env();
+            login_attempt::record_failure(&*conn, &config, ip, name).await?;
+            return Err(QrError::Authorized);
         }
-        None => Err(QrError::Authorized),
+        Err(e) => return Err(e),
+    };
+    login_attempt::record_success(&*conn, ip, name).await?;
+    refresh::start_session(&*conn, passtoken).await
+}
+
+/// リフレッシュトークンを新しいトークンの組に置き換えるエンドポイント
+pub async fn refresh_token(
+    Json(req): Json<RefreshRequest>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<TokenPair> {
+    info!("Try refresh token");
+    let res = refresh::refresh(&*conn, &req.refresh_token).await;
+    result_to_handler_with_log(
+        |_| Some("Success refresh token".to_string()),
+        |e| Some(format!("Failed refresh token: {e}")),
+        &res,
+    )
+    .await
+}
+
+/// トークンの発行を試行したクライアントのIPアドレスを決める
+/// `TRUST_PROXY_HEADERS`が`true`の場合は、リバースプロキシが付けた`X-Forwarded-For`の最後の値を使う
+pub fn client_ip(remote: Option<SocketAddr>, headers: &HeaderMap) -> String {
+    let trust_proxy = env::var("TRUST_PROXY_HEADERS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    let forwarded = headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.rsplit(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    match (trust_proxy, forwarded, remote) {
+        (true, Some(ip), _) => ip,
+        (_, _, Some(remote)) => remote.ip().to_string(),
+        _ => "unknown".to_string(),
     }
 }
+
+/// 失敗した試行の記録を新しい順に取得するエンドポイント
+/// - `limit`: 取得する件数。デフォルトは100件で、1000件まで
+pub async fn get_login_failure_list(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<Vec<LoginFailure>> {
+    info!("Try get login failure list");
+    let limit = match query.get("limit") {
+        Some(limit) => limit
+            .parse::<i64>()
+            .ok()
+            .filter(|n| (1..=1000).contains(n))
+            .ok_or_else(|| QrError::InvalidQuery("limit".to_string())),
+        None => Ok(100),
+    };
+    let res = match limit {
+        Ok(limit) => login_attempt::get_login_failure_list(&*conn, limit).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success get login failure list".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// URLのクエリにある`role`を読む
+/// 指定が無い場合は`None`
+fn parse_role_query(query: &HashMap<String, String>) -> Result<Option<Role>> {
+    match query.get("role") {
+        Some(role) => str_to_role_opt(role)
+            .map(Some)
+            .ok_or_else(|| QrError::InvalidQuery("role".to_string())),
+        None => Ok(None),
+    }
+}
+
+/// 提示されたトークンを失効させるエンドポイント
+pub async fn logout(bearer: Bearer, conn: Arc<Pool<Postgres>>) -> ReturnData<()> {
+    info!("Try logout");
+    let res = authentication::revoke_passtoken(&*conn, bearer.token()).await;
+    result_to_handler_with_log(
+        |_| Some("Success logout".to_string()),
+        |e| Some(format!("Failed logout: {e}")),
+        &res,
+    )
+    .await
+}
+
+/// 提示したトークンやAPIキーの情報
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct Me {
+    pub role: Role,
+    /// トークンが使えなくなる日時
+    /// 期限の無いAPIキーの場合は`None`
+    pub expires_at: Option<DateTime<Utc>>,
+    /// アカウントで発行したトークンの場合はそのアカウント
+    pub user_id: Option<Uuid>,
+    pub user_name: Option<String>,
+    /// APIキーの場合はそのラベルとスコープ
+    pub api_key_label: Option<String>,
+    pub scopes: Option<Vec<Scope>>,
+    /// 呼び出せるエンドポイントの一覧
+    pub operations: Vec<Operation>,
+}
+
+/// 提示したトークンの情報を返す
+async fn get_me(bearer: &Bearer, conn: &Pool<Postgres>) -> Result<Me> {
+    let token = bearer.token();
+    if api_key::is_api_key(token) {
+        let key = api_key::authenticate_api_key(conn, token).await?;
+        Ok(Me {
+            operations: permitted_operations(&key.role, Some(&key.scopes)),
+            role: key.role,
+            expires_at: None,
+            user_id: None,
+            user_name: None,
+            api_key_label: Some(key.label),
+            scopes: Some(key.scopes),
+        })
+    } else {
+        let info = authentication::introspect_passtoken(conn, token).await?;
+        Ok(Me {
+            operations: permitted_operations(&info.role, None),
+            role: info.role,
+            expires_at: Some(info.expires_at),
+            user_id: info.user_id,
+            user_name: info.user_name,
+            api_key_label: None,
+            scopes: None,
+        })
+    }
+}
+
+/// 提示したトークンの権限や期限、呼び出せるエンドポイントを返すエンドポイント
+/// フロントエンドが使えない操作のボタンを隠すのに使う
+pub async fn me(bearer: Bearer, conn: Arc<Pool<Postgres>>) -> ReturnData<Me> {
+    info!("Try get me");
+    let res = get_me(&bearer, &conn).await;
+    result_to_handler_with_log(
+        |_| Some("Success get me".to_string()),
+        |e| Some(format!("Failed get me: {e}")),
+        &res,
+    )
+    .await
+}
+
+/// 有効なトークンの一覧を取得するエンドポイント
+/// - `role`: 指定した権限のトークンだけを返す
+pub async fn get_passtoken_list(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<Vec<PasstokenInfo>> {
+    info!("Try get passtoken list");
+    let res = match parse_role_query(&query) {
+        Ok(role) => authentication::get_active_passtoken_list(&*conn, role).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success get passtoken list".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// IDで指定したトークンを失効させるエンドポイント
+pub async fn revoke_passtoken(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<()> {
+    match query.get("id") {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(uuid) => {
+                info!("Try revoke passtoken: {uuid}");
+                let res = authentication::revoke_passtoken_by_id(&*conn, uuid).await;
+                result_to_handler_with_log(
+                    |_| Some(format!("Success revoke passtoken[{uuid}]")),
+                    |e| Some(format!("{e}[{uuid}]")),
+                    &res,
+                )
+                .await
+            }
+            Err(_) => {
+                let err = Err(QrError::BrokenUuid(id.to_string()));
+                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+            }
+        },
+        None => {
+            let err = Err(QrError::UrlQuery("id".to_string()));
+            result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+        }
+    }
+}
+
+/// 有効なトークンをまとめて失効させ、失効させた数を返すエンドポイント
+/// 操作した管理者自身のトークンは失効させない
+/// - `role`: 指定した権限のトークンだけを失効させる
+pub async fn revoke_all_passtoken(
+    bearer: Bearer,
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<u64> {
+    info!("Try revoke all passtoken: {:?}", query.get("role"));
+    let res = match parse_role_query(&query) {
+        Ok(role) => authentication::revoke_all_passtoken(&*conn, role, bearer.token()).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |n| Some(format!("Success revoke all passtoken: {n}")),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// APIキーを発行するエンドポイント
+/// キーそのものはこの時にしか返さない
+pub async fn insert_api_key(
+    Json(new_key): Json<NewApiKey>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<IssuedApiKey> {
+    info!("Try insert api key: {}", new_key.label);
+    let res = api_key::insert_api_key(&*conn, &new_key).await;
+    result_to_handler_with_log(
+        |_| Some(format!("Success insert api key[{}]", new_key.label)),
+        |e| Some(format!("{e}[{}]", new_key.label)),
+        &res,
+    )
+    .await
+}
+
+/// APIキーの一覧を取得するエンドポイント
+pub async fn get_api_key_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<ApiKey>> {
+    info!("Try get api key list");
+    let res = api_key::get_api_key_list(&*conn).await;
+    result_to_handler_with_log(
+        |_| Some("Success get api key list".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// IDで指定したAPIキーを失効させるエンドポイント
+pub async fn revoke_api_key(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<()> {
+    let res = match query.get("id") {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(uuid) => {
+                info!("Try revoke api key: {uuid}");
+                api_key::revoke_api_key(&*conn, uuid).await
+            }
+            Err(_) => Err(QrError::BrokenUuid(id.to_string())),
+        },
+        None => Err(QrError::UrlQuery("id".to_string())),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success revoke api key".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// OpenID Connectでのログインを始めるエンドポイント
+/// IDプロバイダのログイン画面のURLを返すので、フロントエンドはそこへ遷移させる
+pub async fn oidc_login(conn: Arc<Pool<Postgres>>, client: Arc<OidcClient>) -> ReturnData<String> {
+    info!("Try start OpenID Connect login");
+    let res = oidc::start_login(&*conn, &client).await;
+    result_to_handler_with_log(
+        |_| Some("Success start OpenID Connect login".to_string()),
+        |e| Some(format!("Failed start OpenID Connect login: {e}")),
+        &res,
+    )
+    .await
+}
+
+/// IDプロバイダからのリダイレクトで受け取った`code`と`state`でトークンを発行するエンドポイント
+pub async fn oidc_callback(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+    client: Arc<OidcClient>,
+) -> ReturnData<TokenPair> {
+    info!("Try finish OpenID Connect login");
+    let res = match (query.get("code"), query.get("state")) {
+        (Some(code), Some(state)) => oidc::finish_login(&*conn, &client, code, state).await,
+        (None, _) => Err(QrError::UrlQuery("code".to_string())),
+        (_, None) => Err(QrError::UrlQuery("state".to_string())),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success gen passtoken by OpenID Connect".to_string()),
+        |e| Some(format!("Failed finish OpenID Connect login: {e}")),
+        &res,
+    )
+    .await
+}
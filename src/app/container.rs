@@ -1,30 +1,159 @@
-use crate::authentication::{get_role, Role};
 use crate::{
-    error_handling::{result_to_handler, result_to_handler_with_log, QrError, ReturnData},
+    app::etag::{with_etag, ReturnWithETag},
+    error_handling::{result_to_handler_with_log, QrError, Result, ReturnData},
+    search_engine::{SearchBackend, SearchContainer},
     Container,
 };
-use axum::{extract::Json, headers::authorization::Bearer};
+use axum::extract::Json;
 use sqlx::{pool::Pool, postgres::Postgres};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::*;
+use uuid::Uuid;
 
-pub async fn insert_container(
-    bearer: Bearer,
+/// コンテナの登録を行うエンドポイント
+pub async fn insert_container<B: SearchBackend>(
     Json(container): Json<Container>,
     conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchContainer<B>>,
 ) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try insert container: {container:?}");
-        let res =
-            crate::database::insert_container::insert_container(&*conn, container.clone()).await;
-        result_to_handler_with_log(
-            |_| Some(format!("Success insert container[{}]", &container.id)),
-            |e| Some(format!("{e} [{}]", &container.id)),
-            &res,
-        )
-        .await
-    } else {
-        result_to_handler(&Err(QrError::Authorized)).await
+    info!("Try insert container: {container:?}");
+    let res = crate::database::insert_container::insert_container(&*conn, container.clone()).await;
+    let res = match res {
+        Ok(()) => {
+            context
+                .add_or_replace(std::slice::from_ref(&container))
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some(format!("Success insert container[{}]", &container.id)),
+        |e| Some(format!("{e} [{}]", &container.id)),
+        &res,
+    )
+    .await
+}
+
+/// コンテナの一覧の取得を行うエンドポイント
+pub async fn get_container_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<Container>> {
+    info!("Try get container list");
+    let res = crate::database::get_container_list::get_container_list(&*conn).await;
+    result_to_handler_with_log(
+        |_| Some("Success get container list".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// コンテナの取得を行うエンドポイント
+/// - `id`: コンテナのID
+/// - `qr_id`: QRコードのID
+pub async fn get_one_container(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<Container> {
+    use crate::database::get_one_container::*;
+    let id = match (query.get("id"), query.get("qr_id")) {
+        (Some(id), _) => Uuid::parse_str(id)
+            .map(IdType::ContainerId)
+            .map_err(|_| QrError::BrokenUuid(id.to_string())),
+        (_, Some(qr_id)) => Ok(IdType::QrId(qr_id.clone())),
+        _ => Err(QrError::UrlQuery("qr_id, id".to_string())),
+    };
+    info!("Try get container: {id:?}");
+    let res = match id {
+        Ok(id) => get_one_container(&*conn, id).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success get container".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// コンテナの情報の更新を行うエンドポイント
+/// `if_match`で版を指定した場合は、その版から変わっていない時だけ更新する
+pub async fn update_container<B: SearchBackend>(
+    Json(container): Json<Container>,
+    if_match: Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchContainer<B>>,
+) -> ReturnWithETag<()> {
+    info!("Try update container: {container:?}");
+    let version = match if_match {
+        Ok(expected) => {
+            crate::database::update_container::update_container(&*conn, container.clone(), expected)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    let res = match &version {
+        Ok(_) => {
+            context
+                .add_or_replace(std::slice::from_ref(&container))
+                .await
+        }
+        Err(e) => Err(e.clone()),
+    };
+    let ret = result_to_handler_with_log(
+        |_| Some(format!("Success update container[{}]", &container.id)),
+        |e| Some(format!("{e} [{}]", &container.id)),
+        &res,
+    )
+    .await;
+    with_etag(ret, version.ok())
+}
+
+/// パスで指定したコンテナの情報を置き換えるエンドポイント
+/// 本文の`id`は無視し、パスの`id`を使う
+pub async fn replace_container<B: SearchBackend>(
+    id: String,
+    Json(container): Json<Container>,
+    if_match: Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchContainer<B>>,
+) -> ReturnWithETag<()> {
+    match Uuid::parse_str(&id) {
+        Ok(id) => {
+            update_container(Json(Container { id, ..container }), if_match, conn, context).await
+        }
+        Err(_) => {
+            let err = Err(QrError::BrokenUuid(id));
+            with_etag(
+                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await,
+                None,
+            )
+        }
     }
 }
+
+/// コンテナの削除を行うエンドポイント
+pub async fn delete_container<B: SearchBackend>(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchContainer<B>>,
+) -> ReturnData<()> {
+    let res = match query.get("id") {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(uuid) => {
+                info!("Try delete container: {uuid}");
+                match crate::database::delete_container::delete_container(&*conn, uuid).await {
+                    Ok(()) => context.delete(&[uuid]).await,
+                    Err(e) => Err(e),
+                }
+            }
+            Err(_) => Err(QrError::BrokenUuid(id.to_string())),
+        },
+        None => Err(QrError::UrlQuery("id".to_string())),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success delete container".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
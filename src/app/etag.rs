@@ -0,0 +1,138 @@
+//! 行の版を使った楽観的な排他制御
+//!
+//! 物品・貸出情報・地点・コンテナを取得すると、行の版を`ETag`で返す。
+//! 更新する時は取得した`ETag`を`If-Match`で送り、その間に他の人が更新していた場合は412を返す。
+use crate::authentication::env_or;
+use crate::database::get_version::{get_version, VersionKey};
+use crate::error_handling::{Msg, QrError, Result, ReturnData};
+use axum::{
+    extract::Json,
+    http::{
+        header::{ETAG, IF_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+};
+use serde::Serialize;
+use sqlx::{pool::Pool, postgres::Postgres};
+use std::future::Future;
+use std::sync::Arc;
+
+/// `ETag`ヘッダーを付けた応答
+pub type ReturnWithETag<T> = (StatusCode, HeaderMap, Json<Msg<T>>);
+
+/// 版を`ETag`の値にする
+pub fn etag(version: i64) -> String {
+    format!("\"{version}\"")
+}
+
+/// `If-Match`を読み込み、更新の前提になる版を返す
+/// - ヘッダーが無い場合と`*`の場合は版を確認しないので`None`
+/// - 版として読めない場合はどの版とも一致しないので`QrError::PreconditionFailed`
+/// - `If-Match`は強い比較なので、`W/"3"`のような弱いETagも一致しないものとして扱う
+pub fn if_match(headers: &HeaderMap) -> Result<Option<i64>> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| QrError::PreconditionFailed)?
+        .trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .and_then(|v| v.parse().ok())
+        .map(Some)
+        .ok_or(QrError::PreconditionFailed)
+}
+
+/// `If-Match`を必須にして読み込む
+/// ヘッダーが無い場合は`QrError::PreconditionRequired`
+pub fn require_if_match(headers: &HeaderMap) -> Result<Option<i64>> {
+    if headers.contains_key(IF_MATCH) {
+        if_match(headers)
+    } else {
+        Err(QrError::PreconditionRequired)
+    }
+}
+
+/// `If-Match`を`legacy_if_match`で読み込む従来の更新のエンドポイント
+pub const LEGACY_UPDATE_PATHS: &[&str] = &["/update_fixtures", "/update_lending", "/update_spot"];
+
+/// バージョンの無いパスの従来の更新のエンドポイントで`If-Match`を省略できるようにするか
+/// デフォルトは`true`で、古いフロントエンドが移行を終えたら環境変数`LEGACY_IF_MATCH_OPTIONAL`を`false`にする
+pub fn legacy_if_match_optional() -> bool {
+    env_or("LEGACY_IF_MATCH_OPTIONAL", true)
+}
+
+/// `/update_fixtures`などの従来の更新のエンドポイントで`If-Match`を読み込む
+/// `/v1`のパスでは必須にし、バージョンの無いパスでは`legacy_if_match_optional`の場合だけ省略できる
+pub fn legacy_if_match(headers: &HeaderMap, legacy: bool) -> Result<Option<i64>> {
+    if legacy && legacy_if_match_optional() {
+        if_match(headers)
+    } else {
+        require_if_match(headers)
+    }
+}
+
+/// 成功した応答に版を`ETag`として付ける
+pub fn with_etag<T: Serialize>(
+    (status, json): ReturnData<T>,
+    version: Option<i64>,
+) -> ReturnWithETag<T> {
+    let mut headers = HeaderMap::new();
+    if let Some(version) = version.filter(|_| status.is_success()) {
+        if let Ok(value) = HeaderValue::from_str(&etag(version)) {
+            headers.insert(ETAG, value);
+        }
+    }
+    (status, headers, json)
+}
+
+/// 取得するエンドポイントの応答に`ETag`を付ける
+/// 取得した内容より新しい版を返すことが無いよう、版は取得する前に読み込む
+pub async fn read_with_etag<T, F>(
+    conn: Arc<Pool<Postgres>>,
+    key: Option<VersionKey>,
+    read: F,
+) -> ReturnWithETag<T>
+where
+    T: Serialize,
+    F: Future<Output = ReturnData<T>>,
+{
+    let version = match key {
+        Some(key) => get_version(&*conn, &key).await.ok(),
+        None => None,
+    };
+    with_etag(read.await, version)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::etag::{if_match, require_if_match};
+    use crate::error_handling::QrError;
+    use axum::http::{header::IF_MATCH, HeaderMap};
+
+    #[test]
+    fn test_if_match() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_MATCH, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(if_match(&headers("\"3\"")), Ok(Some(3)));
+        assert_eq!(
+            if_match(&headers("W/\"3\"")),
+            Err(QrError::PreconditionFailed)
+        );
+        assert_eq!(if_match(&headers("*")), Ok(None));
+        assert_eq!(if_match(&HeaderMap::new()), Ok(None));
+        assert_eq!(if_match(&headers("3")), Err(QrError::PreconditionFailed));
+        assert_eq!(
+            require_if_match(&HeaderMap::new()),
+            Err(QrError::PreconditionRequired)
+        );
+    }
+}
@@ -1,9 +1,18 @@
-use crate::authentication::{get_role, Role};
+use crate::app::etag::{with_etag, ReturnWithETag};
+use crate::app::search::parse_keyword_query;
 use crate::database::get_one_fixtures::{get_one_fixtures, IdType};
-use crate::error_handling::{result_to_handler, result_to_handler_with_log, QrError, ReturnData};
-use crate::search_engine::{SearchFixtures, SearchResult};
-use crate::Fixtures;
-use axum::{extract::Json, headers::authorization::Bearer};
+use crate::error_handling::{
+    created, result_to_handler_with_log, QrError, Result, ReturnCreated, ReturnData,
+};
+use crate::patch::{parse_patch, patch_fields, FixturesPatch, FIXTURES_IMMUTABLE_FIELDS};
+use crate::search_engine::{
+    query::{SearchPage, SearchQuery, Sort},
+    FixturesDocument, SearchBackend, SearchFixtures, SearchResult, FIXTURES_FILTERABLE_ATTRIBUTES,
+    FIXTURES_SORTABLE_ATTRIBUTES,
+};
+use crate::validation::validate;
+use crate::{Fixtures, NewFixtures};
+use axum::extract::Json;
 use sqlx::{pool::Pool, postgres::Postgres};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -12,133 +21,192 @@ use uuid::Uuid;
 
 /// 備品情報の登録を行うエンドポイント
 /// - https://github.com/sohosai/qr-backend/issues/11
-pub async fn insert_fixtures(
-    bearer: Bearer,
-    Json(fixtures): Json<Fixtures>,
+///
+/// IDと作成日時はサーバーで振り、登録した物品の情報を返す
+pub async fn insert_fixtures<B: SearchBackend>(
+    Json(fixtures): Json<NewFixtures>,
     conn: Arc<Pool<Postgres>>,
-    context: Arc<SearchFixtures>,
-) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try insert fixtures: {fixtures:?}");
-        let res = crate::database::insert_fixtures::insert_fixtures(&*conn, fixtures.clone()).await;
-
-        // DBの処理が成功した時の結果
-        let r1 = result_to_handler_with_log(
-            |_| Some(format!("Success insert fixtures(DB)[{}]", &fixtures.id)),
+    context: Arc<SearchFixtures<B>>,
+) -> ReturnCreated<Fixtures> {
+    let valid = validate(&fixtures);
+    let fixtures = Fixtures::new(fixtures);
+    info!("Try insert fixtures: {fixtures:?}");
+    let res = match valid {
+        Ok(()) => crate::database::insert_fixtures::insert_fixtures(&*conn, fixtures.clone())
+            .await
+            .map(|_| fixtures.clone()),
+        Err(e) => Err(e),
+    };
+
+    // DBの処理が成功した時の結果
+    let r1 = result_to_handler_with_log(
+        |_| Some(format!("Success insert fixtures(DB)[{}]", &fixtures.id)),
+        |e| Some(format!("{e}[{}]", &fixtures.id)),
+        &res,
+    )
+    .await;
+
+    let ret = if res.is_ok() {
+        let res = add_or_replace_document(&conn, &context, fixtures.clone())
+            .await
+            .map(|_| fixtures.clone());
+        result_to_handler_with_log(
+            |_| {
+                Some(format!(
+                    "Success insert fixtures(Search Engine)[{}]",
+                    &fixtures.id
+                ))
+            },
             |e| Some(format!("{e}[{}]", &fixtures.id)),
             &res,
         )
-        .await;
-
-        if res.is_ok() {
-            let res = context.add_or_replace(&[fixtures.clone()]).await;
-            result_to_handler_with_log(
-                |_| {
-                    Some(format!(
-                        "Success insert fixtures(Search Engine)[{}]",
-                        &fixtures.id
-                    ))
-                },
-                |e| Some(format!("{e}[{}]", &fixtures.id)),
-                &res,
-            )
-            .await
-        } else {
-            r1
-        }
+        .await
     } else {
-        result_to_handler(&Err(QrError::Authorized)).await
-    }
+        r1
+    };
+    created(ret, &format!("/fixtures/{}", fixtures.id))
 }
 
-pub async fn update_fixtures(
-    bearer: Bearer,
+/// 物品情報の更新を行うエンドポイント
+/// `if_match`で版を指定した場合は、その版から変わっていない時だけ更新する
+pub async fn update_fixtures<B: SearchBackend>(
     Json(fixtures): Json<Fixtures>,
+    if_match: Result<Option<i64>>,
     conn: Arc<Pool<Postgres>>,
-    context: Arc<SearchFixtures>,
-) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try update fixtures: {fixtures:?}");
-        let res = crate::database::update_fixtures::update_fixtures(&*conn, fixtures.clone()).await;
-
-        // DBの処理が成功した時の結果
-        let r1 = result_to_handler_with_log(
-            |_| Some(format!("Success update fixtures(DB)[{}]", &fixtures.id)),
+    context: Arc<SearchFixtures<B>>,
+) -> ReturnWithETag<()> {
+    info!("Try update fixtures: {fixtures:?}");
+    let version = match if_match.and_then(|expected| validate(&fixtures).map(|_| expected)) {
+        Ok(expected) => {
+            crate::database::update_fixtures::update_fixtures(&*conn, fixtures.clone(), expected)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    let res = version.clone().map(|_| ());
+
+    // DBの処理が成功した時の結果
+    let r1 = result_to_handler_with_log(
+        |_| Some(format!("Success update fixtures(DB)[{}]", &fixtures.id)),
+        |e| Some(format!("{e}[{}]", &fixtures.id)),
+        &res,
+    )
+    .await;
+
+    let ret = if res.is_ok() {
+        let res = add_or_replace_document(&conn, &context, fixtures.clone()).await;
+        result_to_handler_with_log(
+            |_| {
+                Some(format!(
+                    "Success update fixtures(Search Engine)[{}]",
+                    &fixtures.id
+                ))
+            },
             |e| Some(format!("{e}[{}]", &fixtures.id)),
             &res,
         )
-        .await;
-
-        if res.is_ok() {
-            let res = context.add_or_replace(&[fixtures.clone()]).await;
-            result_to_handler_with_log(
-                |_| {
-                    Some(format!(
-                        "Success update fixtures(Search Engine)[{}]",
-                        &fixtures.id
-                    ))
-                },
-                |e| Some(format!("{e}[{}]", &fixtures.id)),
-                &res,
-            )
-            .await
-        } else {
-            r1
-        }
+        .await
     } else {
-        result_to_handler(&Err(QrError::Authorized)).await
-    }
+        r1
+    };
+    with_etag(ret, version.ok())
 }
 
-pub async fn delete_fixtures(
-    bearer: Bearer,
+pub async fn delete_fixtures<B: SearchBackend>(
     query: HashMap<String, String>,
     conn: Arc<Pool<Postgres>>,
-    context: Arc<SearchFixtures>,
+    context: Arc<SearchFixtures<B>>,
 ) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::Administrator) == role {
-        let id_opt = query.get("id");
-        if let Some(id) = id_opt {
-            let uuid_opt = Uuid::parse_str(id).ok();
-            if let Some(uuid) = uuid_opt {
-                info!("Try delete fixtures: {uuid}");
-                let res = crate::database::delete_fixtures::delete_fixtures(&*conn, uuid).await;
+    let id_opt = query.get("id");
+    if let Some(id) = id_opt {
+        let uuid_opt = Uuid::parse_str(id).ok();
+        if let Some(uuid) = uuid_opt {
+            info!("Try delete fixtures: {uuid}");
+            let res = crate::database::delete_fixtures::delete_fixtures(&*conn, uuid).await;
+
+            // DBの処理が成功した時の結果
+            let r1 = result_to_handler_with_log(
+                |_| Some(format!("Success delete fixtures(DB)[{uuid}]")),
+                |e| Some(format!("{e}[{uuid}]")),
+                &res,
+            )
+            .await;
 
-                // DBの処理が成功した時の結果
-                let r1 = result_to_handler_with_log(
-                    |_| Some(format!("Success delete fixtures(DB)[{uuid}]")),
+            if res.is_ok() {
+                let res = context.delete(&[uuid]).await;
+                result_to_handler_with_log(
+                    |_| Some(format!("Success delete fixtures(Search Engine)[{uuid}]")),
                     |e| Some(format!("{e}[{uuid}]")),
                     &res,
                 )
-                .await;
-
-                if res.is_ok() {
-                    let res = context.delete(&[uuid]).await;
-                    result_to_handler_with_log(
-                        |_| Some(format!("Success delete fixtures(Search Engine)[{uuid}]")),
-                        |e| Some(format!("{e}[{uuid}]")),
-                        &res,
-                    )
-                    .await
-                } else {
-                    r1
-                }
+                .await
             } else {
-                let err = Err(QrError::BrokenUuid(id.to_string()));
-                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+                r1
             }
         } else {
-            let err = Err(QrError::UrlQuery("id".to_string()));
+            let err = Err(QrError::BrokenUuid(id.to_string()));
             result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
         }
     } else {
-        result_to_handler(&Err(QrError::Authorized)).await
+        let err = Err(QrError::UrlQuery("id".to_string()));
+        result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
     }
 }
 
+/// パスで指定した物品の情報を置き換えるエンドポイント
+/// 本文の`id`は無視し、パスの`id`を使う
+pub async fn replace_fixtures<B: SearchBackend>(
+    id: String,
+    Json(fixtures): Json<Fixtures>,
+    if_match: Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchFixtures<B>>,
+) -> ReturnWithETag<()> {
+    match Uuid::parse_str(&id) {
+        Ok(id) => update_fixtures(Json(Fixtures { id, ..fixtures }), if_match, conn, context).await,
+        Err(_) => {
+            let err = Err(QrError::BrokenUuid(id));
+            with_etag(
+                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await,
+                None,
+            )
+        }
+    }
+}
+
+/// パスで指定した物品の情報のうち、本文に含まれる項目だけを更新するエンドポイント
+/// 更新後の物品の情報を返す
+pub async fn patch_fixtures<B: SearchBackend>(
+    id: String,
+    Json(body): Json<serde_json::Value>,
+    if_match: Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchFixtures<B>>,
+) -> ReturnWithETag<Fixtures> {
+    info!("Try patch fixtures[{id}]: {:?}", patch_fields(&body));
+    let res = async {
+        let expected = if_match?;
+        let id = Uuid::parse_str(&id).map_err(|_| QrError::BrokenUuid(id.clone()))?;
+        let patch: FixturesPatch = parse_patch(body, FIXTURES_IMMUTABLE_FIELDS)?;
+        validate(&patch)?;
+        let (fixtures, version) =
+            crate::database::patch_fixtures::patch_fixtures(&*conn, id, &patch, expected).await?;
+        add_or_replace_document(&conn, &context, fixtures.clone()).await?;
+        Ok((fixtures, version))
+    }
+    .await;
+    let version = res.as_ref().ok().map(|(_, version)| *version);
+    let res = res.map(|(fixtures, _)| fixtures);
+    let ret = result_to_handler_with_log(
+        |_| Some(format!("Success patch fixtures[{id}]")),
+        |e| Some(format!("{e}[{id}]")),
+        &res,
+    )
+    .await;
+    with_etag(ret, version)
+}
+
+/// 物品情報の取得を行うエンドポイント
 pub async fn get_fixtures(
     query: HashMap<String, String>,
     conn: Arc<Pool<Postgres>>,
@@ -177,20 +245,138 @@ pub async fn get_fixtures(
     }
 }
 
-pub async fn search_fixtures(
-    keywords_str: String,
-    context: Arc<SearchFixtures>,
-) -> ReturnData<Vec<SearchResult<Fixtures>>> {
-    let keywords = keywords_str
-        .split(',') // カンマ区切りであることを要求する
+/// 貸し出し状況を付け加えて検索エンジンに登録する
+async fn add_or_replace_document<B: SearchBackend>(
+    conn: &Pool<Postgres>,
+    context: &SearchFixtures<B>,
+    fixtures: Fixtures,
+) -> Result<()> {
+    use crate::database::get_one_lending::{get_one_lending, IdType};
+    let is_lending = match get_one_lending(conn, IdType::FixturesId(fixtures.id)).await {
+        Ok(_) => true,
+        Err(QrError::DatabaseNotFound(_)) => false,
+        Err(e) => return Err(e),
+    };
+    context
+        .add_or_replace(&[FixturesDocument {
+            fixtures,
+            is_lending,
+        }])
+        .await
+}
+
+/// 貸し出し状況が変わった物品を検索エンジンに登録し直す
+/// 物品が登録されていない場合は何もしない
+pub async fn reindex_fixtures<B: SearchBackend>(
+    conn: &Pool<Postgres>,
+    context: &SearchFixtures<B>,
+    id: Uuid,
+) -> Result<()> {
+    match get_one_fixtures(conn, IdType::FixturesId(id)).await {
+        Ok(fixtures) => add_or_replace_document(conn, context, fixtures).await,
+        Err(QrError::DatabaseNotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// URLのクエリを検索条件にする
+/// 絞り込みの値やファセットの属性はカンマ区切りで複数指定できる
+fn parse_search_query(query: &HashMap<String, String>) -> Result<SearchQuery> {
+    let mut search_query = parse_keyword_query(query)?;
+    search_query.facets = FIXTURES_FILTERABLE_ATTRIBUTES
+        .iter()
         .map(|s| s.to_string())
-        .collect::<Vec<String>>();
-    let context = &*context;
-    info!("Try search fixtures: {keywords:?}");
-    let res = context.search(&keywords).await;
+        .collect();
+    for attribute in FIXTURES_FILTERABLE_ATTRIBUTES.iter() {
+        if let Some(values) = query.get(*attribute) {
+            let values = values
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>();
+            if *attribute == "is_lending" && values.iter().any(|v| v != "true" && v != "false") {
+                return Err(QrError::InvalidQuery(attribute.to_string()));
+            }
+            search_query.filters.insert(attribute.to_string(), values);
+        }
+    }
+    if let Some(facets) = query.get("facets") {
+        let facets = facets
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        if facets
+            .iter()
+            .any(|f| !FIXTURES_FILTERABLE_ATTRIBUTES.contains(&f.as_str()))
+        {
+            return Err(QrError::InvalidQuery("facets".to_string()));
+        }
+        search_query.facets = facets;
+    }
+    if let Some(sort) = query.get("sort") {
+        match Sort::parse(sort) {
+            Some(sort) if FIXTURES_SORTABLE_ATTRIBUTES.contains(&sort.attribute.as_str()) => {
+                search_query.sort = Some(sort)
+            }
+            _ => return Err(QrError::InvalidQuery("sort".to_string())),
+        }
+    }
+    Ok(search_query)
+}
+
+/// 物品の検索を行うエンドポイント
+/// - `keywords`: 空白またはカンマ区切りの検索語
+///   `"`で囲むとフレーズ、先頭に`-`を付けると除外する語になる
+/// - `mode`: 検索語を`or`（デフォルト）と`and`のどちらで組み合わせるか
+/// - `storage`, `qr_color`, `parent_id`, `is_lending`: 絞り込み
+/// - `facets`: 件数を集計する属性
+/// - `sort`: `created_at:desc`のような形式の並び替え
+/// - `offset`, `limit`: ページ分割
+pub async fn search_fixtures<B: SearchBackend>(
+    query: HashMap<String, String>,
+    context: Arc<SearchFixtures<B>>,
+) -> ReturnData<SearchPage<FixturesDocument>> {
+    match parse_search_query(&query) {
+        Ok(search_query) => {
+            let context = &*context;
+            info!("Try search fixtures: {search_query:?}");
+            let res = context.search(&search_query).await;
+            result_to_handler_with_log(
+                |_| Some(format!("Success search fixtures[{search_query:?}]")),
+                |e| Some(format!("{e}[{search_query:?}]")),
+                &res,
+            )
+            .await
+        }
+        Err(e) => result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &Err(e)).await,
+    }
+}
+
+/// バージョンの付かない従来の`/search_fixtures`
+/// 更新していないフロントエンドのために、ページの形ではなく検索結果の物品の一覧を返す
+pub async fn search_fixtures_legacy<B: SearchBackend>(
+    query: HashMap<String, String>,
+    context: Arc<SearchFixtures<B>>,
+) -> ReturnData<Vec<SearchResult<Fixtures>>> {
+    let res = match parse_search_query(&query) {
+        Ok(search_query) => {
+            info!("Try search fixtures: {search_query:?}");
+            context.search(&search_query).await.map(|page| {
+                page.hits
+                    .into_iter()
+                    .map(|hit| SearchResult {
+                        data: hit.data.fixtures,
+                        ranking: hit.ranking,
+                    })
+                    .collect::<Vec<_>>()
+            })
+        }
+        Err(e) => Err(e),
+    };
     result_to_handler_with_log(
-        |_| Some(format!("Success search fixtures[{keywords:?}]")),
-        |e| Some(format!("{e}[{keywords:?}]")),
+        |_| Some("Success search fixtures".to_string()),
+        |e| Some(e.to_string()),
         &res,
     )
     .await
@@ -0,0 +1,219 @@
+//! 再送されたリクエストを一度だけ処理するための`Idempotency-Key`
+//!
+//! キオスク端末やスマートフォンは通信が不安定な時に同じリクエストを再送する。
+//! POST・PUT・PATCH・DELETEに`Idempotency-Key`ヘッダーが付いている場合は最初の応答を保存し、
+//! 同じキーで再送されたリクエストは処理せずに保存した応答を返す。
+//! 同じキーで内容の違うリクエストが送られた場合は409を返す。
+//! キーは呼び出した端末ごとに分けるので、他の端末や利用者が同じキーを使っても別のリクエストとして処理する。
+use crate::app::policy::Caller;
+use crate::app::version::strip_version;
+use crate::database::idempotency_key::{
+    complete, idempotency_key_hours, release, reserve, Reservation, StoredResponse,
+};
+use crate::error_handling::{result_to_handler, QrError, Result};
+use axum::{
+    body::{boxed, Body, Full},
+    extract::State,
+    http::{
+        header::{CONTENT_TYPE, ETAG, LOCATION},
+        HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::Pool, postgres::Postgres};
+use std::sync::Arc;
+use tracing::*;
+
+/// 冪等キーを送るヘッダー
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// 保存した応答を返したことを示すヘッダー
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// キーの長さの上限
+const MAX_KEY_LENGTH: usize = 255;
+
+/// 再送されたリクエストへの応答にも付けるヘッダー
+const STORED_HEADERS: [HeaderName; 3] = [CONTENT_TYPE, ETAG, LOCATION];
+
+/// 応答にトークンなどの秘密の値を含むので保存しないエンドポイント
+/// これらでは`Idempotency-Key`を無視する
+const NOT_STORED: &[&str] = &["/gen_passtoken", "/refresh_token", "/insert_api_key"];
+
+/// `Idempotency-Key`を受け付けるエンドポイントかどうか
+/// `path`にはバージョンの付かないパスを渡す
+pub fn accepts_idempotency_key(method: &Method, path: &str) -> bool {
+    [Method::POST, Method::PUT, Method::PATCH, Method::DELETE].contains(method)
+        && !NOT_STORED.contains(&path)
+}
+
+/// `Idempotency-Key`を読み込む
+/// 空白や制御文字を含まない255文字までのASCII文字列を受け付ける
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    let key = String::from_utf8_lossy(value.as_bytes()).to_string();
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH || !key.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(QrError::InvalidIdempotencyKey(key));
+    }
+    Ok(Some(key))
+}
+
+/// 同じキーで送られたリクエストが同じ内容かを比べるためのハッシュ
+/// `/v1`の有無はバージョン付きのパスと従来のパスで同じ操作になるので区別しない
+fn request_hash(caller: &str, method: &Method, path_and_query: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(caller.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path_and_query.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// 応答から保存する部分を取り出す
+fn store(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> StoredResponse {
+    let headers = STORED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = headers.get(name)?.to_str().ok()?;
+            Some((name.to_string(), serde_json::Value::from(value)))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    StoredResponse {
+        status_code: status.as_u16() as i32,
+        headers: headers.into(),
+        body: body.to_vec(),
+    }
+}
+
+/// 保存した応答を返す
+fn replay(stored: StoredResponse) -> Response {
+    let mut res = Response::new(boxed(Full::from(stored.body)));
+    *res.status_mut() = StatusCode::from_u16(stored.status_code as u16).unwrap_or(StatusCode::OK);
+    if let Some(headers) = stored.headers.as_object() {
+        for (name, value) in headers {
+            if let (Ok(name), Some(Ok(value))) = (
+                HeaderName::try_from(name.as_str()),
+                value.as_str().map(HeaderValue::from_str),
+            ) {
+                res.headers_mut().insert(name, value);
+            }
+        }
+    }
+    res.headers_mut()
+        .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    res
+}
+
+async fn error_response(e: QrError) -> Response {
+    result_to_handler::<()>(&Err(e)).await.into_response()
+}
+
+/// `Idempotency-Key`が付いたリクエストを一度だけ処理するミドルウェア
+/// 権限の検査の後に呼び出し、権限の無いリクエストの応答は保存しない
+/// 呼び出した人が分からないトークン無しのリクエストではキーを無視する
+pub async fn idempotency(
+    State(conn): State<Arc<Pool<Postgres>>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let path = strip_version(req.uri().path()).to_string();
+    if !accepts_idempotency_key(req.method(), &path) {
+        return next.run(req).await;
+    }
+    let Some(caller) = req
+        .extensions()
+        .get::<Caller>()
+        .and_then(|caller| caller.id.clone())
+    else {
+        return next.run(req).await;
+    };
+    let key = match idempotency_key(req.headers()) {
+        Ok(Some(key)) => key,
+        Ok(None) => return next.run(req).await,
+        Err(e) => return error_response(e).await,
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => return error_response(QrError::InvalidBody(e.to_string())).await,
+    };
+    let path_and_query = match parts.uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let hash = request_hash(&caller, &parts.method, &path_and_query, &body);
+    let window = Duration::hours(idempotency_key_hours());
+    match reserve(&*conn, &caller, &key, &hash, Utc::now(), window).await {
+        Ok(Reservation::Reserved) => (),
+        Ok(Reservation::Completed(stored)) => {
+            info!("Replay the response for Idempotency-Key {key}");
+            return replay(stored);
+        }
+        Ok(Reservation::Mismatch) => {
+            return error_response(QrError::IdempotencyKeyMismatch(key)).await
+        }
+        Ok(Reservation::InProgress) => {
+            return error_response(QrError::IdempotencyKeyInProgress(key)).await
+        }
+        Err(e) => return error_response(e).await,
+    }
+
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let (parts, body) = res.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read the response for Idempotency-Key {key}: {e}");
+            if let Err(e) = release(&*conn, &caller, &key).await {
+                error!("{e}[{key}]");
+            }
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // サーバー側の失敗は同じキーで再送すれば成功する可能性があるので保存しない
+    let res = if parts.status.is_server_error() {
+        release(&*conn, &caller, &key).await
+    } else {
+        let stored = store(parts.status, &parts.headers, &body);
+        complete(&*conn, &caller, &key, &stored).await
+    };
+    if let Err(e) = res {
+        error!("{e}[{key}]");
+    }
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::idempotency::{idempotency_key, IDEMPOTENCY_KEY};
+    use crate::error_handling::QrError;
+    use axum::http::HeaderMap;
+
+    #[test]
+    fn test_idempotency_key() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IDEMPOTENCY_KEY, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(
+            idempotency_key(&headers("550e8400-e29b-41d4-a716-446655440000")),
+            Ok(Some("550e8400-e29b-41d4-a716-446655440000".to_string()))
+        );
+        assert_eq!(idempotency_key(&HeaderMap::new()), Ok(None));
+        assert_eq!(
+            idempotency_key(&headers("a b")),
+            Err(QrError::InvalidIdempotencyKey("a b".to_string()))
+        );
+        assert!(idempotency_key(&headers(&"a".repeat(256))).is_err());
+    }
+}
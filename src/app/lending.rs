@@ -1,9 +1,14 @@
-use crate::authentication::{get_role, Role};
+use crate::app::etag::{with_etag, ReturnWithETag};
+use crate::app::fixtures::reindex_fixtures;
+use crate::authentication::Role;
+use crate::patch::{parse_patch, patch_fields, LendingPatch, LENDING_IMMUTABLE_FIELDS};
+use crate::search_engine::{SearchBackend, SearchContexts, SearchLending};
+use crate::validation::validate_with_spot;
 use crate::{
-    error_handling::{result_to_handler, result_to_handler_with_log, QrError, ReturnData},
-    Lending,
+    error_handling::{created, result_to_handler_with_log, QrError, ReturnCreated, ReturnData},
+    Lending, LendingView, NewLending,
 };
-use axum::{extract::Json, headers::authorization::Bearer};
+use axum::extract::Json;
 use chrono::{DateTime, Utc};
 use sqlx::{pool::Pool, postgres::Postgres};
 use std::collections::HashMap;
@@ -11,91 +16,149 @@ use std::sync::Arc;
 use tracing::*;
 use uuid::Uuid;
 
+/// 貸出情報を検索エンジンに登録し直す
+/// 返却済みなどで貸し出し中でない場合は検索エンジンから取り除く
+async fn reindex_lending<B: SearchBackend>(
+    conn: &Pool<Postgres>,
+    context: &SearchLending<B>,
+    id: Uuid,
+) -> crate::error_handling::Result<()> {
+    use crate::database::get_one_lending::*;
+    match get_one_lending(conn, IdType::LendingId(id)).await {
+        Ok(lending) => context.add_or_replace(&[lending]).await,
+        Err(QrError::DatabaseNotFound(_)) => context.delete(&[id]).await,
+        Err(e) => Err(e),
+    }
+}
+
+/// 物品を返却し、検索エンジンに登録されている物品と貸出情報を更新する
+async fn return_fixtures<B: SearchBackend>(
+    conn: &Pool<Postgres>,
+    contexts: &SearchContexts<B>,
+    fixtures_id: Uuid,
+    returned_at: DateTime<Utc>,
+) -> crate::error_handling::Result<()> {
+    use crate::database::get_one_lending::*;
+    let lending = get_one_lending(conn, IdType::FixturesId(fixtures_id)).await;
+    crate::database::returned_lending::returned_lending(conn, fixtures_id, returned_at).await?;
+    reindex_fixtures(conn, &contexts.fixtures, fixtures_id).await?;
+    match lending {
+        Ok(lending) => reindex_lending(conn, &contexts.lending, lending.id).await,
+        Err(_) => Ok(()),
+    }
+}
+
 /// 備品情報の登録を行うエンドポイント
 /// - https://github.com/sohosai/qr-backend/issues/11
-pub async fn insert_lending(
-    bearer: Bearer,
-    Json(lending): Json<Lending>,
+///
+/// IDと貸し出し日時はサーバーで振り、登録した貸出情報を返す
+pub async fn insert_lending<B: SearchBackend>(
+    Json(lending): Json<NewLending>,
     conn: Arc<Pool<Postgres>>,
-) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try insert lending: {lending:?}");
-        let res = crate::database::insert_lending::insert_lending(&*conn, lending.clone()).await;
+    contexts: SearchContexts<B>,
+) -> ReturnCreated<Lending> {
+    let valid = validate_with_spot(&*conn, &lending, Some(&lending.spot_name)).await;
+    let lending = Lending::new(lending);
+    info!("Try insert lending: {lending:?}");
+    let res = match valid {
+        Ok(()) => crate::database::insert_lending::insert_lending(&*conn, lending.clone())
+            .await
+            .map(|_| lending.clone()),
+        Err(e) => Err(e),
+    };
+    let r1 = result_to_handler_with_log(
+        |_| Some(format!("Success insert lending[{}]", &lending.id)),
+        |e| Some(format!("{e}[{}]", &lending.id)),
+        &res,
+    )
+    .await;
+
+    let ret = if res.is_ok() {
+        let res = reindex_fixtures(&conn, &contexts.fixtures, lending.fixtures_id).await;
+        let res = match res {
+            Ok(()) => reindex_lending(&conn, &contexts.lending, lending.id).await,
+            Err(e) => Err(e),
+        };
         result_to_handler_with_log(
-            |_| Some(format!("Success insert lending[{}]", &lending.id)),
-            |e| Some(format!("{e}[{}]", &lending.id)),
-            &res,
+            |_| None,
+            |e| Some(format!("{e} fixtures[{}]", &lending.fixtures_id)),
+            &res.map(|_| lending.clone()),
         )
         .await
     } else {
-        result_to_handler(&Err(QrError::Authorized)).await
-    }
+        r1
+    };
+    created(ret, &format!("/lendings/{}", lending.id))
 }
 
-pub async fn returned_lending(
-    bearer: Bearer,
+pub async fn returned_lending<B: SearchBackend>(
     query: HashMap<String, String>,
     returned_at: DateTime<Utc>,
     conn: Arc<Pool<Postgres>>,
+    contexts: SearchContexts<B>,
 ) -> ReturnData<()> {
     use crate::database::get_one_fixtures::*;
-    use crate::database::returned_lending::*;
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        match (query.get("id"), query.get("qr_id")) {
-            (Some(id), _) => {
-                let uuid_opt = Uuid::parse_str(id).ok();
-                if let Some(uuid) = uuid_opt {
-                    info!("Try get fixtures with uuid: {uuid}");
-                    let res = returned_lending(&*conn, uuid, returned_at).await;
+    match (query.get("id"), query.get("qr_id")) {
+        (Some(id), _) => {
+            let uuid_opt = Uuid::parse_str(id).ok();
+            if let Some(uuid) = uuid_opt {
+                info!("Try get fixtures with uuid: {uuid}");
+                let res = return_fixtures(&conn, &contexts, uuid, returned_at).await;
+                result_to_handler_with_log(
+                    |_| Some(format!("Success returned lending with uuid[{uuid}]")),
+                    |e| Some(format!("{e} uuid[{uuid}]")),
+                    &res,
+                )
+                .await
+            } else {
+                let err = Err(QrError::BrokenUuid(id.to_string()));
+                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+            }
+        }
+        (_, Some(qr_id)) => {
+            info!("Try get fixtures with qr_id: {qr_id}");
+            let fixtures = get_one_fixtures(&*conn, IdType::QrId(qr_id.clone())).await;
+            match fixtures {
+                Ok(fixtures) => {
+                    let res = return_fixtures(&conn, &contexts, fixtures.id, returned_at).await;
                     result_to_handler_with_log(
-                        |_| Some(format!("Success returned lending with uuid[{uuid}]")),
-                        |e| Some(format!("{e} uuid[{uuid}]")),
+                        |_| Some(format!("Success returned lending with qr_id[{qr_id}]")),
+                        |e| Some(format!("{e} qr_id[{qr_id}]")),
                         &res,
                     )
                     .await
-                } else {
-                    let err = Err(QrError::BrokenUuid(id.to_string()));
-                    result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
                 }
-            }
-            (_, Some(qr_id)) => {
-                info!("Try get fixtures with qr_id: {qr_id}");
-                let fixtures = get_one_fixtures(&*conn, IdType::QrId(qr_id.clone())).await;
-                match fixtures {
-                    Ok(fixtures) => {
-                        let res = returned_lending(&*conn, fixtures.id, returned_at).await;
-                        result_to_handler_with_log(
-                            |_| Some(format!("Success returned lending with qr_id[{qr_id}]")),
-                            |e| Some(format!("{e} qr_id[{qr_id}]")),
-                            &res,
-                        )
-                        .await
-                    }
-                    Err(e) => {
-                        result_to_handler_with_log(
-                            |_| None,
-                            |e| Some(format!("{e} qr_id[{qr_id}]")),
-                            &Err(e),
-                        )
-                        .await
-                    }
+                Err(e) => {
+                    result_to_handler_with_log(
+                        |_| None,
+                        |e| Some(format!("{e} qr_id[{qr_id}]")),
+                        &Err(e),
+                    )
+                    .await
                 }
             }
-            _ => {
-                let err = Err(QrError::UrlQuery("qr_id, id".to_string()));
-                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
-            }
         }
-    } else {
-        result_to_handler(&Err(QrError::Authorized)).await
+        _ => {
+            let err = Err(QrError::UrlQuery("qr_id, id".to_string()));
+            result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+        }
     }
 }
 
-pub async fn get_lending_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<Lending>> {
+/// 貸出情報の一覧の取得を行うエンドポイント
+/// 物品管理者未満の権限では学籍番号を含めない
+pub async fn get_lending_list(
+    role: Option<Role>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<Vec<LendingView>> {
     info!("Try get lending list");
-    let res = crate::database::get_lending_list::get_lending_list(&*conn).await;
+    let res = crate::database::get_lending_list::get_lending_list(&*conn)
+        .await
+        .map(|lst| {
+            lst.into_iter()
+                .map(|lending| LendingView::new(lending, role.as_ref()))
+                .collect()
+        });
     result_to_handler_with_log(
         |_| Some("Success get lending list".to_string()),
         |e| Some(e.to_string()),
@@ -104,11 +167,15 @@ pub async fn get_lending_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<Lendi
     .await
 }
 
+/// 貸出情報の取得を行うエンドポイント
+/// 物品管理者未満の権限では学籍番号を含めない
 pub async fn get_one_lending(
+    role: Option<Role>,
     query: HashMap<String, String>,
     conn: Arc<Pool<Postgres>>,
-) -> ReturnData<Lending> {
+) -> ReturnData<LendingView> {
     use crate::database::get_one_lending::*;
+    let view = |lending| LendingView::new(lending, role.as_ref());
     match (
         query.get("lending_id"),
         query.get("fixtures_id"),
@@ -118,7 +185,9 @@ pub async fn get_one_lending(
             info!("Try get one lending info with lending_id[{lending_id}]");
             let uuid_opt = Uuid::parse_str(lending_id).ok();
             if let Some(uuid) = uuid_opt {
-                let res = get_one_lending(&*conn, IdType::LendingId(uuid)).await;
+                let res = get_one_lending(&*conn, IdType::LendingId(uuid))
+                    .await
+                    .map(view);
                 result_to_handler_with_log(
                     |_| Some(format!("Success get lending with lending_id[{lending_id}]")),
                     |e| Some(format!("{e} lending_id[{lending_id}]")),
@@ -134,7 +203,9 @@ pub async fn get_one_lending(
             info!("Try get one lending info with fixtures_id[{fixtures_id}]");
             let uuid_opt = Uuid::parse_str(fixtures_id).ok();
             if let Some(uuid) = uuid_opt {
-                let res = get_one_lending(&*conn, IdType::FixturesId(uuid)).await;
+                let res = get_one_lending(&*conn, IdType::FixturesId(uuid))
+                    .await
+                    .map(view);
                 result_to_handler_with_log(
                     |_| {
                         Some(format!(
@@ -152,7 +223,9 @@ pub async fn get_one_lending(
         }
         (_, _, Some(qr_id)) => {
             info!("Try get one lending info with fixtures_qr_id[{qr_id}]");
-            let res = get_one_lending(&*conn, IdType::QrId(qr_id.to_string())).await;
+            let res = get_one_lending(&*conn, IdType::QrId(qr_id.to_string()))
+                .await
+                .map(view);
             result_to_handler_with_log(
                 |_| Some(format!("Success get lending with fixtures_qr_id[{qr_id}]")),
                 |e| Some(format!("{e} fixtures_qr_id[{qr_id}]")),
@@ -228,22 +301,112 @@ pub async fn get_is_lending(
     }
 }
 
-pub async fn update_lending(
-    bearer: Bearer,
+/// 貸出情報の更新を行うエンドポイント
+/// `if_match`で版を指定した場合は、その版から変わっていない時だけ更新する
+pub async fn update_lending<B: SearchBackend>(
     Json(lending): Json<Lending>,
+    if_match: crate::error_handling::Result<Option<i64>>,
     conn: Arc<Pool<Postgres>>,
-) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try update lending: {lending:?}");
-        let res = crate::database::update_lending::update_lending(&*conn, lending.clone()).await;
-        result_to_handler_with_log(
-            |_| Some(format!("Success update lending[{}]", lending.id)),
-            |e| Some(format!("{e} lending[{}]", lending.id)),
-            &res,
+    contexts: SearchContexts<B>,
+) -> ReturnWithETag<()> {
+    use crate::database::get_one_lending::*;
+    info!("Try update lending: {lending:?}");
+    // 対象の物品が変わった場合は元の物品の貸し出し状況も更新する
+    let old = get_one_lending(&*conn, IdType::LendingId(lending.id)).await;
+    let valid = validate_with_spot(&*conn, &lending, Some(&lending.spot_name)).await;
+    let version = match if_match.and_then(|expected| valid.map(|_| expected)) {
+        Ok(expected) => {
+            crate::database::update_lending::update_lending(&*conn, lending.clone(), expected).await
+        }
+        Err(e) => Err(e),
+    };
+    let res = match &version {
+        Ok(_) => {
+            let mut ids = vec![lending.fixtures_id];
+            if let Ok(old) = old {
+                ids.push(old.fixtures_id);
+            }
+            ids.dedup();
+            let mut res = Ok(());
+            for id in ids {
+                res = res.and(reindex_fixtures(&conn, &contexts.fixtures, id).await);
+            }
+            res.and(reindex_lending(&conn, &contexts.lending, lending.id).await)
+        }
+        Err(e) => Err(e.clone()),
+    };
+    let ret = result_to_handler_with_log(
+        |_| Some(format!("Success update lending[{}]", lending.id)),
+        |e| Some(format!("{e} lending[{}]", lending.id)),
+        &res,
+    )
+    .await;
+    with_etag(ret, version.ok())
+}
+
+/// パスで指定した貸出情報を置き換えるエンドポイント
+/// 本文の`id`は無視し、パスの`id`を使う
+pub async fn replace_lending<B: SearchBackend>(
+    id: String,
+    Json(lending): Json<Lending>,
+    if_match: crate::error_handling::Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    contexts: SearchContexts<B>,
+) -> ReturnWithETag<()> {
+    match Uuid::parse_str(&id) {
+        Ok(id) => update_lending(Json(Lending { id, ..lending }), if_match, conn, contexts).await,
+        Err(_) => {
+            let err = Err(QrError::BrokenUuid(id));
+            with_etag(
+                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await,
+                None,
+            )
+        }
+    }
+}
+
+/// パスで指定した貸出情報のうち、本文に含まれる項目だけを更新するエンドポイント
+/// 更新後の貸出情報を返す
+pub async fn patch_lending<B: SearchBackend>(
+    id: String,
+    Json(body): Json<serde_json::Value>,
+    if_match: crate::error_handling::Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    contexts: SearchContexts<B>,
+) -> ReturnWithETag<Lending> {
+    info!("Try patch lending[{id}]: {:?}", patch_fields(&body));
+    let res = async {
+        let expected = if_match?;
+        let id = Uuid::parse_str(&id).map_err(|_| QrError::BrokenUuid(id.clone()))?;
+        let patch: LendingPatch = parse_patch(body, LENDING_IMMUTABLE_FIELDS)?;
+        validate_with_spot(&*conn, &patch, patch.spot_name.as_deref()).await?;
+        let old = crate::database::get_one_lending::get_one_lending(
+            &*conn,
+            crate::database::get_one_lending::IdType::LendingId(id),
         )
-        .await
-    } else {
-        result_to_handler(&Err(QrError::Authorized)).await
+        .await;
+        let (lending, version) =
+            crate::database::patch_lending::patch_lending(&*conn, id, &patch, expected).await?;
+        // 対象の物品が変わった場合は元の物品の貸し出し状況も更新する
+        let mut ids = vec![lending.fixtures_id];
+        if let Ok(old) = old {
+            ids.push(old.fixtures_id);
+        }
+        ids.dedup();
+        for fixtures_id in ids {
+            reindex_fixtures(&conn, &contexts.fixtures, fixtures_id).await?;
+        }
+        reindex_lending(&conn, &contexts.lending, id).await?;
+        Ok((lending, version))
     }
+    .await;
+    let version = res.as_ref().ok().map(|(_, version)| *version);
+    let res = res.map(|(lending, _)| lending);
+    let ret = result_to_handler_with_log(
+        |_| Some(format!("Success patch lending[{id}]")),
+        |e| Some(format!("{e} lending[{id}]")),
+        &res,
+    )
+    .await;
+    with_etag(ret, version)
 }
@@ -0,0 +1,771 @@
+//! OpenAPIの仕様書を作るエンドポイント
+//!
+//! パスとメソッド、必要な権限は`POLICY`から、データの形は`ToSchema`を実装した型から作る。
+//! 応答は全て`Msg`に包まれるので、エンドポイントごとに`data`の型だけを差し替えた形を書く。
+//! 型は`ToSchema::schema`で指定し、仕様書の`components`には書いた型を全て載せる。
+use crate::app::etag::LEGACY_UPDATE_PATHS;
+use crate::app::idempotency::accepts_idempotency_key;
+use crate::app::policy::{Permission, POLICY};
+use crate::app::search::SearchAllResult;
+use crate::app::version::{API_PREFIX, API_VERSION};
+use crate::app::{authentication::Me, policy::Operation as PermittedOperation};
+use crate::authentication::{
+    api_key::{ApiKey, IssuedApiKey, NewApiKey, Scope},
+    login_attempt::LoginFailure,
+    refresh::{RefreshRequest, TokenPair},
+    user::{NewUser, UpdateUser, User},
+    PasstokenInfo, Role,
+};
+use crate::database::purge_expired::{PurgeCounts, PurgeReport};
+use crate::patch::{FixturesPatch, LendingPatch, SpotPatch};
+use crate::search_engine::{query::SearchPage, FixturesDocument, SpotDocument};
+use crate::validation::{FieldError, FieldErrorCode};
+use crate::{
+    Area, Container, Fixtures, Lending, LendingView, NewFixtures, NewLending, QrColor, Spot,
+    Stroge, Synonym,
+};
+use axum::{
+    extract::Json,
+    http::Method,
+    response::{Html, IntoResponse},
+};
+use std::collections::BTreeMap;
+use utoipa::openapi::{
+    path::{OperationBuilder, ParameterBuilder, ParameterIn, PathItem, PathItemType},
+    request_body::RequestBodyBuilder,
+    schema::{ArrayBuilder, ObjectBuilder, Ref, Schema, SchemaType},
+    security::{HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme},
+    ComponentsBuilder, Content, Header, Info, OpenApi, OpenApiBuilder, PathsBuilder, RefOr,
+    Required, ResponseBuilder,
+};
+use utoipa::ToSchema;
+
+/// 型の名前と形を返す`ToSchema::schema`
+pub(crate) type SchemaFn = fn() -> (&'static str, RefOr<Schema>);
+
+/// 応答の`data`の型
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Data {
+    /// 何も返さない
+    Empty,
+    Bool,
+    Integer,
+    String,
+    One(SchemaFn),
+    List(SchemaFn),
+    /// 作成したリソースを`201 Created`で返す
+    Created(SchemaFn),
+    /// 検索結果の一ページ分（`SearchPage<T>`）。文書の型ごとに形が変わるので埋め込む
+    Page(SchemaFn),
+    /// `Msg`に包まずに返す。指定したContent-Typeで返す
+    Raw(&'static str),
+}
+
+/// エンドポイントごとのリクエストの本文と応答の`data`の型
+/// 権限の一覧にある全てのエンドポイントを書く
+pub(crate) static BODIES: &[(Method, &str, Option<SchemaFn>, Data)] = &[
+    (Method::GET, "/ping", None, Data::Raw("text/plain")),
+    (
+        Method::GET,
+        "/openapi.json",
+        None,
+        Data::Raw("application/json"),
+    ),
+    (Method::GET, "/docs", None, Data::Raw("text/html")),
+    (
+        Method::GET,
+        "/get_purge_report",
+        None,
+        Data::One(PurgeReport::schema),
+    ),
+    (
+        Method::POST,
+        "/insert_fixtures",
+        Some(NewFixtures::schema),
+        Data::Created(Fixtures::schema),
+    ),
+    (
+        Method::POST,
+        "/update_fixtures",
+        Some(Fixtures::schema),
+        Data::Empty,
+    ),
+    (Method::DELETE, "/delete_fixtures", None, Data::Empty),
+    (
+        Method::GET,
+        "/get_fixtures",
+        None,
+        Data::One(Fixtures::schema),
+    ),
+    (
+        Method::GET,
+        "/search_fixtures",
+        None,
+        Data::Page(<SearchPage<FixturesDocument>>::schema),
+    ),
+    (
+        Method::POST,
+        "/insert_synonym",
+        Some(Synonym::schema),
+        Data::Empty,
+    ),
+    (
+        Method::GET,
+        "/get_synonym_list",
+        None,
+        Data::List(Synonym::schema),
+    ),
+    (Method::DELETE, "/delete_synonym", None, Data::Empty),
+    (
+        Method::GET,
+        "/search",
+        None,
+        Data::One(SearchAllResult::schema),
+    ),
+    (
+        Method::POST,
+        "/insert_lending",
+        Some(NewLending::schema),
+        Data::Created(Lending::schema),
+    ),
+    (
+        Method::POST,
+        "/update_lending",
+        Some(Lending::schema),
+        Data::Empty,
+    ),
+    (Method::POST, "/returned_lending", None, Data::Empty),
+    (
+        Method::GET,
+        "/get_lending_list",
+        None,
+        Data::List(LendingView::schema),
+    ),
+    (
+        Method::GET,
+        "/get_lending",
+        None,
+        Data::One(LendingView::schema),
+    ),
+    (Method::GET, "/get_is_lending", None, Data::Bool),
+    (
+        Method::POST,
+        "/insert_spot",
+        Some(Spot::schema),
+        Data::Empty,
+    ),
+    (
+        Method::POST,
+        "/update_spot",
+        Some(Spot::schema),
+        Data::Empty,
+    ),
+    (Method::GET, "/get_spot", None, Data::One(Spot::schema)),
+    (
+        Method::GET,
+        "/get_spot_list",
+        None,
+        Data::List(Spot::schema),
+    ),
+    (Method::DELETE, "/delete_spot", None, Data::Empty),
+    (
+        Method::POST,
+        "/insert_container",
+        Some(Container::schema),
+        Data::Empty,
+    ),
+    (
+        Method::POST,
+        "/gen_passtoken",
+        None,
+        Data::One(TokenPair::schema),
+    ),
+    (
+        Method::POST,
+        "/refresh_token",
+        Some(RefreshRequest::schema),
+        Data::One(TokenPair::schema),
+    ),
+    (Method::POST, "/logout", None, Data::Empty),
+    (
+        Method::GET,
+        "/get_passtoken_list",
+        None,
+        Data::List(PasstokenInfo::schema),
+    ),
+    (Method::POST, "/revoke_passtoken", None, Data::Empty),
+    (Method::POST, "/revoke_all_passtoken", None, Data::Integer),
+    (
+        Method::GET,
+        "/get_login_failure_list",
+        None,
+        Data::List(LoginFailure::schema),
+    ),
+    (
+        Method::POST,
+        "/insert_api_key",
+        Some(NewApiKey::schema),
+        Data::One(IssuedApiKey::schema),
+    ),
+    (
+        Method::GET,
+        "/get_api_key_list",
+        None,
+        Data::List(ApiKey::schema),
+    ),
+    (Method::POST, "/revoke_api_key", None, Data::Empty),
+    (
+        Method::POST,
+        "/insert_user",
+        Some(NewUser::schema),
+        Data::One(User::schema),
+    ),
+    (
+        Method::POST,
+        "/update_user",
+        Some(UpdateUser::schema),
+        Data::One(User::schema),
+    ),
+    (
+        Method::GET,
+        "/get_user_list",
+        None,
+        Data::List(User::schema),
+    ),
+    (Method::GET, "/get_user", None, Data::One(User::schema)),
+    (Method::DELETE, "/delete_user", None, Data::Empty),
+    (Method::GET, "/oidc_login", None, Data::String),
+    (
+        Method::GET,
+        "/oidc_callback",
+        None,
+        Data::One(TokenPair::schema),
+    ),
+    (Method::GET, "/me", None, Data::One(Me::schema)),
+    (
+        Method::GET,
+        "/fixtures",
+        None,
+        Data::Page(<SearchPage<FixturesDocument>>::schema),
+    ),
+    (
+        Method::POST,
+        "/fixtures",
+        Some(NewFixtures::schema),
+        Data::Created(Fixtures::schema),
+    ),
+    (
+        Method::GET,
+        "/fixtures/:id",
+        None,
+        Data::One(Fixtures::schema),
+    ),
+    (
+        Method::PUT,
+        "/fixtures/:id",
+        Some(Fixtures::schema),
+        Data::Empty,
+    ),
+    (
+        Method::PATCH,
+        "/fixtures/:id",
+        Some(FixturesPatch::schema),
+        Data::One(Fixtures::schema),
+    ),
+    (Method::DELETE, "/fixtures/:id", None, Data::Empty),
+    (
+        Method::GET,
+        "/fixtures/by-qr/:qr_id",
+        None,
+        Data::One(Fixtures::schema),
+    ),
+    (
+        Method::GET,
+        "/fixtures/:id/lending",
+        None,
+        Data::One(LendingView::schema),
+    ),
+    (Method::POST, "/fixtures/:id/return", None, Data::Empty),
+    (
+        Method::POST,
+        "/fixtures/by-qr/:qr_id/return",
+        None,
+        Data::Empty,
+    ),
+    (
+        Method::GET,
+        "/lendings",
+        None,
+        Data::List(LendingView::schema),
+    ),
+    (
+        Method::POST,
+        "/lendings",
+        Some(NewLending::schema),
+        Data::Created(Lending::schema),
+    ),
+    (
+        Method::GET,
+        "/lendings/:id",
+        None,
+        Data::One(LendingView::schema),
+    ),
+    (
+        Method::PUT,
+        "/lendings/:id",
+        Some(Lending::schema),
+        Data::Empty,
+    ),
+    (
+        Method::PATCH,
+        "/lendings/:id",
+        Some(LendingPatch::schema),
+        Data::One(Lending::schema),
+    ),
+    (
+        Method::GET,
+        "/lendings/by-qr/:qr_id",
+        None,
+        Data::One(LendingView::schema),
+    ),
+    (Method::GET, "/spots", None, Data::List(Spot::schema)),
+    (Method::POST, "/spots", Some(Spot::schema), Data::Empty),
+    (Method::GET, "/spots/:name", None, Data::One(Spot::schema)),
+    (Method::PUT, "/spots/:name", Some(Spot::schema), Data::Empty),
+    (
+        Method::PATCH,
+        "/spots/:name",
+        Some(SpotPatch::schema),
+        Data::One(Spot::schema),
+    ),
+    (Method::DELETE, "/spots/:name", None, Data::Empty),
+    (
+        Method::GET,
+        "/containers",
+        None,
+        Data::List(Container::schema),
+    ),
+    (
+        Method::POST,
+        "/containers",
+        Some(Container::schema),
+        Data::Empty,
+    ),
+    (
+        Method::GET,
+        "/containers/:id",
+        None,
+        Data::One(Container::schema),
+    ),
+    (
+        Method::PUT,
+        "/containers/:id",
+        Some(Container::schema),
+        Data::Empty,
+    ),
+    (Method::DELETE, "/containers/:id", None, Data::Empty),
+    (
+        Method::GET,
+        "/containers/by-qr/:qr_id",
+        None,
+        Data::One(Container::schema),
+    ),
+    (Method::GET, "/synonyms", None, Data::List(Synonym::schema)),
+    (
+        Method::POST,
+        "/synonyms",
+        Some(Synonym::schema),
+        Data::Empty,
+    ),
+    (Method::DELETE, "/synonyms/:id", None, Data::Empty),
+];
+
+/// 入力値を検証し、不正な場合は`422 Unprocessable Entity`を返すリクエストの本文の型
+const VALIDATED: &[SchemaFn] = &[
+    Fixtures::schema,
+    NewFixtures::schema,
+    FixturesPatch::schema,
+    Lending::schema,
+    NewLending::schema,
+    LendingPatch::schema,
+    Spot::schema,
+    SpotPatch::schema,
+];
+
+/// エンドポイントの型からは直接使わず、他の型の中で参照する型
+const NESTED: &[SchemaFn] = &[
+    QrColor::schema,
+    Stroge::schema,
+    Area::schema,
+    FieldError::schema,
+    FieldErrorCode::schema,
+    PermittedOperation::schema,
+    Role::schema,
+    Scope::schema,
+    PurgeCounts::schema,
+    FixturesDocument::schema,
+    SpotDocument::schema,
+];
+
+/// 仕様書に載せるデータの型
+/// `BODIES`に書いた型と`NESTED`を名前で重複を除いて集める
+fn schemas() -> BTreeMap<&'static str, RefOr<Schema>> {
+    BODIES
+        .iter()
+        .flat_map(|(_, _, request, data)| {
+            let data = match data {
+                Data::One(schema) | Data::List(schema) | Data::Created(schema) => Some(*schema),
+                _ => None,
+            };
+            request.iter().copied().chain(data)
+        })
+        .chain(NESTED.iter().copied())
+        .map(|schema| schema())
+        .collect()
+}
+
+impl Data {
+    fn schema(self) -> RefOr<Schema> {
+        match self {
+            Data::Empty => ObjectBuilder::new()
+                .nullable(true)
+                .description(Some("常に`null`"))
+                .into(),
+            Data::Bool => ObjectBuilder::new().schema_type(SchemaType::Boolean).into(),
+            Data::Integer => ObjectBuilder::new().schema_type(SchemaType::Integer).into(),
+            Data::String => ObjectBuilder::new().schema_type(SchemaType::String).into(),
+            Data::One(schema) | Data::Created(schema) => Ref::from_schema_name(schema().0).into(),
+            Data::List(schema) => ArrayBuilder::new()
+                .items(Ref::from_schema_name(schema().0))
+                .into(),
+            Data::Page(schema) => schema().1,
+            Data::Raw("application/json") => ObjectBuilder::new().into(),
+            Data::Raw(_) => ObjectBuilder::new().schema_type(SchemaType::String).into(),
+        }
+    }
+}
+
+/// `data`を指定した型にした`error_handling::Msg`の形
+fn msg_schema(data: RefOr<Schema>) -> Schema {
+    let string = || ObjectBuilder::new().schema_type(SchemaType::String);
+    ObjectBuilder::new()
+        .property(
+            "version",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::Integer)
+                .description(Some("応答の形のバージョン"))
+                .example(Some(API_VERSION.into())),
+        )
+        .required("version")
+        .property(
+            "ok",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::Boolean)
+                .description(Some("成功した場合は`true`")),
+        )
+        .required("ok")
+        .property("data", data)
+        .required("data")
+        .property(
+            "error_type",
+            string()
+                .nullable(true)
+                .description(Some("失敗した場合のエラーの種類（`DatabaseNotFound`など）")),
+        )
+        .required("error_type")
+        .property(
+            "error_message",
+            string()
+                .nullable(true)
+                .description(Some("失敗した場合のエラーの説明")),
+        )
+        .required("error_message")
+        .property(
+            "errors",
+            ArrayBuilder::new()
+                .items(Ref::from_schema_name("FieldError"))
+                .description(Some("入力値が不正な場合の項目ごとの理由")),
+        )
+        .into()
+}
+
+/// 権限の一覧に書かれた権限とスコープの説明
+fn permission_description(permission: &Permission, scope: Option<Scope>) -> String {
+    let role = match permission {
+        Permission::Public => return "トークン無しで呼び出せる".to_string(),
+        Permission::Role(Role::Administrator) => "管理者",
+        Permission::Role(Role::EquipmentManager) => "物品管理者以上",
+        Permission::Role(Role::General) => "一般ユーザー以上",
+    };
+    match scope {
+        Some(scope) => format!("必要な権限: {role}、APIキーのスコープ: `{scope}`"),
+        None => format!("必要な権限: {role}、APIキーでは呼び出せない"),
+    }
+}
+
+/// `/fixtures/:id`を`/v1/fixtures/{id}`の形にし、パスのパラメーターの名前と一緒に返す
+fn openapi_path(path: &str) -> (String, Vec<String>) {
+    let mut params = vec![];
+    let path = path
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => {
+                params.push(name.to_string());
+                format!("{{{name}}}")
+            }
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    (format!("{API_PREFIX}{path}"), params)
+}
+
+fn path_item_type(method: &Method) -> PathItemType {
+    match *method {
+        Method::POST => PathItemType::Post,
+        Method::PUT => PathItemType::Put,
+        Method::PATCH => PathItemType::Patch,
+        Method::DELETE => PathItemType::Delete,
+        _ => PathItemType::Get,
+    }
+}
+
+/// 仕様書を作る
+pub fn openapi() -> OpenApi {
+    let mut items: BTreeMap<String, PathItem> = BTreeMap::new();
+    for (method, path, permission, scope) in POLICY {
+        let (request, data) = BODIES
+            .iter()
+            .find(|(m, p, _, _)| m == method && p == path)
+            .map(|(_, _, request, data)| (*request, *data))
+            .unwrap_or((None, Data::Empty));
+        let success = match data {
+            Data::Created(_) => ResponseBuilder::new()
+                .description("作成した。作成したリソースのパスを`Location`で返す")
+                .header(
+                    "Location",
+                    Header::new(ObjectBuilder::new().schema_type(SchemaType::String)),
+                ),
+            _ => ResponseBuilder::new().description("成功"),
+        };
+        let status = match data {
+            Data::Created(_) => "201",
+            _ => "200",
+        };
+        let (openapi_path, params) = openapi_path(path);
+        let mut operation = OperationBuilder::new()
+            .operation_id(Some(format!("{method} {path}")))
+            .description(Some(permission_description(permission, *scope)))
+            .response(
+                status,
+                match data {
+                    Data::Raw(content_type) => {
+                        success.content(content_type, Content::new(data.schema()))
+                    }
+                    _ => {
+                        success.content("application/json", Content::new(msg_schema(data.schema())))
+                    }
+                },
+            )
+            .response(
+                "default",
+                ResponseBuilder::new().description("失敗").content(
+                    "application/json",
+                    Content::new(Ref::from_schema_name("Msg")),
+                ),
+            );
+        for name in params {
+            operation = operation.parameter(
+                ParameterBuilder::new()
+                    .name(name)
+                    .parameter_in(ParameterIn::Path)
+                    .required(Required::True)
+                    .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String))),
+            );
+        }
+        // 置き換えと部分的な更新は取得した時の版を指定する
+        if *method == Method::PUT || *method == Method::PATCH || LEGACY_UPDATE_PATHS.contains(path)
+        {
+            operation = operation.parameter(
+                ParameterBuilder::new()
+                    .name("If-Match")
+                    .parameter_in(ParameterIn::Header)
+                    .required(Required::True)
+                    .description(Some("取得した時の`ETag`。`*`の場合は版を確認しない"))
+                    .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String))),
+            );
+        }
+        if accepts_idempotency_key(method, path) {
+            operation = operation.parameter(
+                ParameterBuilder::new()
+                    .name("Idempotency-Key")
+                    .parameter_in(ParameterIn::Header)
+                    .required(Required::False)
+                    .description(Some("再送した時に同じ応答を返すためのキー"))
+                    .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String))),
+            );
+        }
+        if request.is_some_and(|request| {
+            VALIDATED
+                .iter()
+                .any(|validated| validated().0 == request().0)
+        }) {
+            operation = operation.response(
+                "422",
+                ResponseBuilder::new()
+                    .description("入力値が不正。不正な項目を`errors`で返す")
+                    .content(
+                        "application/json",
+                        Content::new(Ref::from_schema_name("Msg")),
+                    ),
+            );
+        }
+        if let Some(request) = request {
+            operation = operation.request_body(Some(
+                RequestBodyBuilder::new()
+                    .content(
+                        "application/json",
+                        Content::new(Ref::from_schema_name(request().0)),
+                    )
+                    .required(Some(Required::True))
+                    .build(),
+            ));
+        }
+        if *permission != Permission::Public {
+            operation =
+                operation.security(SecurityRequirement::new("bearer", Vec::<String>::new()));
+        }
+        items
+            .entry(openapi_path)
+            .or_insert_with(|| PathItem::new(path_item_type(method), OperationBuilder::new()))
+            .operations
+            .insert(path_item_type(method), operation.build());
+    }
+    let paths = items
+        .into_iter()
+        .fold(PathsBuilder::new(), |paths, (path, item)| {
+            paths.path(path, item)
+        });
+
+    let components = schemas()
+        .into_iter()
+        .fold(ComponentsBuilder::new(), |components, (name, schema)| {
+            components.schema(name, schema)
+        })
+        .schema(
+            "Msg",
+            msg_schema(ObjectBuilder::new().nullable(true).into()),
+        )
+        .security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        )
+        .build();
+
+    OpenApiBuilder::new()
+        .info(Info::new("qr-backend", env!("CARGO_PKG_VERSION")))
+        .paths(paths)
+        .components(Some(components))
+        .build()
+}
+
+/// 仕様書をJSONで返す
+/// 他のエンドポイントと違い`Msg`には包まない
+pub async fn openapi_json() -> impl IntoResponse {
+    Json(openapi())
+}
+
+/// 仕様書を閲覧するページ
+/// 外部のスクリプトを読み込まないよう、表示に使うページはバイナリに埋め込む
+pub async fn docs() -> Html<&'static str> {
+    Html(include_str!("docs.html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::openapi::{openapi, openapi_path, BODIES};
+    use crate::app::policy::POLICY;
+
+    #[test]
+    fn test_openapi() {
+        assert_eq!(
+            openapi_path("/fixtures/by-qr/:qr_id/return"),
+            (
+                "/v1/fixtures/by-qr/{qr_id}/return".to_string(),
+                vec!["qr_id".to_string()]
+            )
+        );
+
+        // 型を書いたエンドポイントは全て権限の一覧にあり、その逆も成り立つ
+        // 実際のルーターと型が合っているかは`app::tests::test_openapi_matches_router`で確かめる
+        for (method, path, _, _) in BODIES {
+            assert!(
+                POLICY.iter().any(|(m, p, _, _)| m == method && p == path),
+                "{method} {path}"
+            );
+        }
+        for (method, path, _, _) in POLICY {
+            assert!(
+                BODIES.iter().any(|(m, p, _, _)| m == method && p == path),
+                "{method} {path}"
+            );
+        }
+
+        let doc = serde_json::to_value(openapi()).unwrap();
+        let operation = &doc["paths"]["/v1/fixtures/{id}"]["put"];
+        assert_eq!(
+            operation["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Fixtures"
+        );
+        assert_eq!(operation["parameters"][0]["name"], "id");
+        assert_eq!(operation["parameters"][1]["name"], "If-Match");
+        assert_eq!(operation["parameters"][2]["name"], "Idempotency-Key");
+        assert!(doc["paths"]["/v1/gen_passtoken"]["post"]["security"].is_null());
+        let data = &doc["paths"]["/v1/spots"]["get"]["responses"]["200"]["content"]
+            ["application/json"]["schema"]["properties"]["data"];
+        assert_eq!(data["items"]["$ref"], "#/components/schemas/Spot");
+        let operation = &doc["paths"]["/v1/lendings"]["post"];
+        assert_eq!(
+            operation["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/NewLending"
+        );
+        assert!(operation["responses"]["201"]["headers"]["Location"].is_object());
+        assert!(operation["responses"]["422"].is_object());
+        assert_eq!(
+            doc["components"]["schemas"]["Msg"]["properties"]["errors"]["items"]["$ref"],
+            "#/components/schemas/FieldError"
+        );
+        let search = &doc["components"]["schemas"]["SearchAllResult"]["properties"]["lending"];
+        assert_eq!(
+            search["allOf"][0]["properties"]["hits"]["items"]["properties"]["data"]["$ref"],
+            "#/components/schemas/LendingView"
+        );
+
+        // 参照している型は全て`components`にある
+        fn refs<'a>(value: &'a serde_json::Value, found: &mut Vec<&'a str>) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    if let Some(serde_json::Value::String(r)) = map.get("$ref") {
+                        found.push(r);
+                    }
+                    map.values().for_each(|v| refs(v, found));
+                }
+                serde_json::Value::Array(list) => list.iter().for_each(|v| refs(v, found)),
+                _ => {}
+            }
+        }
+        let mut found = vec![];
+        refs(&doc, &mut found);
+        for r in found {
+            let name = r.strip_prefix("#/components/schemas/").unwrap();
+            assert!(doc["components"]["schemas"][name].is_object(), "{r}");
+        }
+
+        let qr_color = &doc["components"]["schemas"]["QrColor"]["enum"];
+        assert!(qr_color
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|c| c == "light_blue"));
+    }
+}
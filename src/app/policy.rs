@@ -0,0 +1,378 @@
+//! エンドポイントごとに必要な権限の一覧と、それを検査するミドルウェア
+//!
+//! トークンの検査はここで一度だけ行い、ハンドラには呼び出した人の権限だけを渡す。
+//! 有効なトークンが無い場合は401、トークンは有効だが権限が足りない場合は403を返す。
+//! APIキーの場合は権限に加えて、エンドポイントのスコープをキーが持っているかも検査する。
+use crate::app::version::{strip_version, API_PREFIX};
+use crate::authentication::{
+    api_key::{authenticate_api_key, is_api_key, Scope},
+    identify_passtoken, Role,
+};
+use crate::error_handling::{result_to_handler, QrError, Result};
+use axum::{
+    extract::{MatchedPath, State},
+    headers::{
+        authorization::{Authorization, Bearer},
+        HeaderMapExt,
+    },
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{pool::Pool, postgres::Postgres};
+use std::env;
+use std::sync::Arc;
+use tracing::*;
+use utoipa::ToSchema;
+
+/// エンドポイントを呼び出すのに必要な権限
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Permission {
+    /// トークン無しで呼び出せる
+    Public,
+    /// 指定した権限以上のトークンが必要
+    /// 一般ユーザーの権限で足りるものは`PUBLIC_ENDPOINTS`でトークン無しでも呼び出せるようにできる
+    Role(Role),
+}
+
+use Permission::Public;
+const GENERAL: Permission = Permission::Role(Role::General);
+const EQUIPMENT_MANAGER: Permission = Permission::Role(Role::EquipmentManager);
+const ADMINISTRATOR: Permission = Permission::Role(Role::Administrator);
+const READ: Option<Scope> = Some(Scope::Read);
+const LENDING: Option<Scope> = Some(Scope::Lending);
+const EDIT: Option<Scope> = Some(Scope::Edit);
+
+/// エンドポイントごとに必要な権限の一覧
+/// 破壊的な変更（削除）と認証情報の管理は管理者、それ以外の変更は物品管理者、閲覧は一般ユーザー以上とする
+/// 最後の項目はAPIキーで呼び出すのに必要なスコープで、`None`のものはAPIキーでは呼び出せない
+pub static POLICY: &[(Method, &str, Permission, Option<Scope>)] = &[
+    (Method::GET, "/ping", Public, None),
+    (Method::GET, "/openapi.json", Public, None),
+    (Method::GET, "/docs", Public, None),
+    (Method::GET, "/get_purge_report", ADMINISTRATOR, None),
+    (Method::POST, "/insert_fixtures", EQUIPMENT_MANAGER, EDIT),
+    (Method::POST, "/update_fixtures", EQUIPMENT_MANAGER, EDIT),
+    (Method::DELETE, "/delete_fixtures", ADMINISTRATOR, None),
+    (Method::GET, "/get_fixtures", GENERAL, READ),
+    (Method::GET, "/search_fixtures", GENERAL, READ),
+    (Method::POST, "/insert_synonym", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/get_synonym_list", GENERAL, READ),
+    (Method::DELETE, "/delete_synonym", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/search", GENERAL, READ),
+    (Method::POST, "/insert_lending", EQUIPMENT_MANAGER, LENDING),
+    (Method::POST, "/update_lending", EQUIPMENT_MANAGER, LENDING),
+    (
+        Method::POST,
+        "/returned_lending",
+        EQUIPMENT_MANAGER,
+        LENDING,
+    ),
+    (Method::GET, "/get_lending_list", GENERAL, READ),
+    (Method::GET, "/get_lending", GENERAL, READ),
+    (Method::GET, "/get_is_lending", GENERAL, READ),
+    (Method::POST, "/insert_spot", EQUIPMENT_MANAGER, EDIT),
+    (Method::POST, "/update_spot", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/get_spot", GENERAL, READ),
+    (Method::GET, "/get_spot_list", GENERAL, READ),
+    (Method::DELETE, "/delete_spot", ADMINISTRATOR, None),
+    (Method::POST, "/insert_container", EQUIPMENT_MANAGER, EDIT),
+    (Method::POST, "/gen_passtoken", Public, None),
+    (Method::POST, "/refresh_token", Public, None),
+    (Method::POST, "/logout", GENERAL, None),
+    (Method::GET, "/me", GENERAL, READ),
+    (Method::GET, "/get_passtoken_list", ADMINISTRATOR, None),
+    (Method::POST, "/revoke_passtoken", ADMINISTRATOR, None),
+    (Method::POST, "/revoke_all_passtoken", ADMINISTRATOR, None),
+    (Method::GET, "/get_login_failure_list", ADMINISTRATOR, None),
+    (Method::POST, "/insert_user", ADMINISTRATOR, None),
+    (Method::POST, "/update_user", ADMINISTRATOR, None),
+    (Method::GET, "/get_user_list", ADMINISTRATOR, None),
+    (Method::GET, "/get_user", ADMINISTRATOR, None),
+    (Method::DELETE, "/delete_user", ADMINISTRATOR, None),
+    (Method::POST, "/insert_api_key", ADMINISTRATOR, None),
+    (Method::GET, "/get_api_key_list", ADMINISTRATOR, None),
+    (Method::POST, "/revoke_api_key", ADMINISTRATOR, None),
+    // リソース形式のエンドポイント
+    (Method::GET, "/fixtures", GENERAL, READ),
+    (Method::POST, "/fixtures", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/fixtures/:id", GENERAL, READ),
+    (Method::PUT, "/fixtures/:id", EQUIPMENT_MANAGER, EDIT),
+    (Method::PATCH, "/fixtures/:id", EQUIPMENT_MANAGER, EDIT),
+    (Method::DELETE, "/fixtures/:id", ADMINISTRATOR, None),
+    (Method::GET, "/fixtures/by-qr/:qr_id", GENERAL, READ),
+    (Method::GET, "/fixtures/:id/lending", GENERAL, READ),
+    (
+        Method::POST,
+        "/fixtures/:id/return",
+        EQUIPMENT_MANAGER,
+        LENDING,
+    ),
+    (
+        Method::POST,
+        "/fixtures/by-qr/:qr_id/return",
+        EQUIPMENT_MANAGER,
+        LENDING,
+    ),
+    (Method::GET, "/lendings", GENERAL, READ),
+    (Method::POST, "/lendings", EQUIPMENT_MANAGER, LENDING),
+    (Method::GET, "/lendings/:id", GENERAL, READ),
+    (Method::PUT, "/lendings/:id", EQUIPMENT_MANAGER, LENDING),
+    (Method::PATCH, "/lendings/:id", EQUIPMENT_MANAGER, LENDING),
+    (Method::GET, "/lendings/by-qr/:qr_id", GENERAL, READ),
+    (Method::GET, "/spots", GENERAL, READ),
+    (Method::POST, "/spots", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/spots/:name", GENERAL, READ),
+    (Method::PUT, "/spots/:name", EQUIPMENT_MANAGER, EDIT),
+    (Method::PATCH, "/spots/:name", EQUIPMENT_MANAGER, EDIT),
+    (Method::DELETE, "/spots/:name", ADMINISTRATOR, None),
+    (Method::GET, "/containers", GENERAL, READ),
+    (Method::POST, "/containers", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/containers/:id", GENERAL, READ),
+    (Method::PUT, "/containers/:id", EQUIPMENT_MANAGER, EDIT),
+    (Method::DELETE, "/containers/:id", ADMINISTRATOR, None),
+    (Method::GET, "/containers/by-qr/:qr_id", GENERAL, READ),
+    (Method::GET, "/synonyms", GENERAL, READ),
+    (Method::POST, "/synonyms", EQUIPMENT_MANAGER, EDIT),
+    (Method::DELETE, "/synonyms/:id", EQUIPMENT_MANAGER, EDIT),
+    (Method::GET, "/oidc_login", Public, None),
+    (Method::GET, "/oidc_callback", Public, None),
+];
+
+/// エンドポイントに必要な権限を返す
+/// 一覧に無いものは管理者のみ呼び出せるようにしておく
+pub fn required_permission(method: &Method, path: &str) -> Permission {
+    POLICY
+        .iter()
+        .find(|(m, p, _, _)| m == method && *p == path)
+        .map(|(_, _, permission, _)| permission.clone())
+        .unwrap_or_else(|| {
+            warn!("No policy for {method} {path}");
+            ADMINISTRATOR
+        })
+}
+
+/// APIキーでエンドポイントを呼び出すのに必要なスコープを返す
+/// 一覧に無いものはAPIキーでは呼び出せない
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    POLICY
+        .iter()
+        .find(|(m, p, _, _)| m == method && *p == path)
+        .and_then(|(_, _, _, scope)| *scope)
+}
+
+/// トークン無しでも閲覧できるエンドポイントの一覧
+/// `PUBLIC_ENDPOINTS`に`/get_spot_list,/get_fixtures`のようにカンマ区切りで指定する
+fn public_endpoints() -> Vec<String> {
+    env::var("PUBLIC_ENDPOINTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// 呼び出した人の権限でエンドポイントを呼び出せるかを検査する
+/// - 有効なトークンが無い場合は`QrError::Authorized`
+/// - 権限が足りない場合は`QrError::Forbidden`
+pub fn check_permission(permission: &Permission, role: Option<&Role>, path: &str) -> Result<()> {
+    match (permission, role) {
+        (Permission::Public, _) => Ok(()),
+        (Permission::Role(required), Some(role)) if role.is_at_least(required) => Ok(()),
+        (Permission::Role(_), Some(_)) => Err(QrError::Forbidden),
+        (Permission::Role(Role::General), None) if public_endpoints().iter().any(|s| s == path) => {
+            Ok(())
+        }
+        (Permission::Role(_), None) => Err(QrError::Authorized),
+    }
+}
+
+/// APIキーのスコープでエンドポイントを呼び出せるかを検査する
+/// トークンの場合は`scopes`を`None`にするので、常に許可する
+pub fn check_scope(required: Option<Scope>, scopes: Option<&[Scope]>) -> Result<()> {
+    match (required, scopes) {
+        (_, None) => Ok(()),
+        (Some(required), Some(scopes)) if scopes.contains(&required) => Ok(()),
+        (_, Some(_)) => Err(QrError::Forbidden),
+    }
+}
+
+/// 呼び出せるエンドポイント
+/// `path`は`/v1/fixtures/:id`のようなバージョン付きのパスで、パスのパラメーターは`:`で始まる
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct Operation {
+    pub method: String,
+    pub path: String,
+}
+
+/// 権限とスコープで呼び出せるエンドポイントの一覧を返す
+/// トークン無しで呼び出せるものは含めない
+/// 従来のパスは非推奨なので、バージョン付きのパスで返す
+pub fn permitted_operations(role: &Role, scopes: Option<&[Scope]>) -> Vec<Operation> {
+    POLICY
+        .iter()
+        .filter(|(_, _, permission, scope)| match permission {
+            Permission::Public => false,
+            Permission::Role(required) => {
+                role.is_at_least(required) && check_scope(*scope, scopes).is_ok()
+            }
+        })
+        .map(|(method, path, _, _)| Operation {
+            method: method.to_string(),
+            path: format!("{API_PREFIX}{path}"),
+        })
+        .collect()
+}
+
+/// エンドポイントを呼び出した人の情報
+/// ミドルウェアがリクエストに付け加える
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    /// 有効なトークンが無い場合は`None`
+    pub role: Option<Role>,
+    /// APIキーで呼び出した場合はキーのスコープ
+    /// トークンで呼び出した場合や、トークンが無い場合は`None`
+    pub scopes: Option<Vec<Scope>>,
+    /// 呼び出した端末を識別する値
+    /// トークンはセッションかトークンのID、APIキーはキーのIDで、`Idempotency-Key`を分けるのに使う
+    /// 有効なトークンが無い場合は`None`
+    pub id: Option<String>,
+}
+
+/// トークンを検査し、一覧に従って呼び出しを許可するかを決めるミドルウェア
+pub async fn authorize<B>(
+    State(conn): State<Arc<Pool<Postgres>>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = match req.extensions().get::<MatchedPath>() {
+        Some(path) => strip_version(path.as_str()).to_string(),
+        None => strip_version(req.uri().path()).to_string(),
+    };
+    let method = req.method().clone();
+    let permission = required_permission(&method, &path);
+    let (role, scopes, id) = match req.headers().typed_get::<Authorization<Bearer>>() {
+        Some(Authorization(bearer)) if is_api_key(bearer.token()) => {
+            match authenticate_api_key(&*conn, bearer.token()).await {
+                Ok(api_key) => (
+                    Some(api_key.role),
+                    Some(api_key.scopes),
+                    Some(format!("api_key:{}", api_key.id)),
+                ),
+                Err(_) => (None, None, None),
+            }
+        }
+        Some(Authorization(bearer)) => match identify_passtoken(&*conn, bearer.token()).await {
+            Ok((role, id)) => (Some(role), None, Some(id)),
+            Err(_) => (None, None, None),
+        },
+        None => (None, None, None),
+    };
+    let res = check_permission(&permission, role.as_ref(), &path).and_then(|_| match permission {
+        Permission::Public => Ok(()),
+        Permission::Role(_) => check_scope(required_scope(&method, &path), scopes.as_deref()),
+    });
+    match res {
+        Ok(()) => {
+            req.extensions_mut().insert(Caller { role, scopes, id });
+            next.run(req).await
+        }
+        Err(e) => {
+            info!("Denied {method} {path}: {e}");
+            result_to_handler::<()>(&Err(e)).await.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::policy::{
+        check_permission, check_scope, permitted_operations, required_permission, required_scope,
+        Permission,
+    };
+    use crate::authentication::{api_key::Scope, Role};
+    use crate::error_handling::QrError;
+    use axum::http::Method;
+
+    #[test]
+    fn test_check_permission() {
+        let permission = required_permission(&Method::DELETE, "/delete_fixtures");
+        assert_eq!(permission, Permission::Role(Role::Administrator));
+        assert_eq!(
+            check_permission(&permission, Some(&Role::Administrator), "/delete_fixtures"),
+            Ok(())
+        );
+        assert_eq!(
+            check_permission(
+                &permission,
+                Some(&Role::EquipmentManager),
+                "/delete_fixtures"
+            ),
+            Err(QrError::Forbidden)
+        );
+        assert_eq!(
+            check_permission(&permission, None, "/delete_fixtures"),
+            Err(QrError::Authorized)
+        );
+
+        let permission = required_permission(&Method::GET, "/get_spot_list");
+        assert_eq!(
+            check_permission(&permission, Some(&Role::General), "/get_spot_list"),
+            Ok(())
+        );
+        assert_eq!(
+            check_permission(&permission, None, "/get_spot_list"),
+            Err(QrError::Authorized)
+        );
+
+        // 一覧に無いものや、メソッドが違うものは管理者のみ
+        assert_eq!(
+            required_permission(&Method::GET, "/unknown"),
+            Permission::Role(Role::Administrator)
+        );
+        assert_eq!(
+            required_permission(&Method::GET, "/insert_fixtures"),
+            Permission::Role(Role::Administrator)
+        );
+        assert_eq!(
+            required_permission(&Method::POST, "/gen_passtoken"),
+            Permission::Public
+        );
+    }
+
+    #[test]
+    fn test_check_scope() {
+        let kiosk = [Scope::Read, Scope::Lending];
+        let required = required_scope(&Method::POST, "/returned_lending");
+        assert_eq!(required, Some(Scope::Lending));
+        assert_eq!(check_scope(required, Some(&kiosk)), Ok(()));
+        assert_eq!(
+            check_scope(
+                required_scope(&Method::POST, "/update_fixtures"),
+                Some(&kiosk)
+            ),
+            Err(QrError::Forbidden)
+        );
+        assert_eq!(
+            check_scope(
+                required_scope(&Method::GET, "/get_lending_list"),
+                Some(&kiosk)
+            ),
+            Ok(())
+        );
+
+        // 管理用のエンドポイントはAPIキーでは呼び出せない
+        assert_eq!(required_scope(&Method::POST, "/insert_api_key"), None);
+        assert_eq!(check_scope(None, Some(&kiosk)), Err(QrError::Forbidden));
+        // トークンの場合はスコープを検査しない
+        assert_eq!(check_scope(None, None), Ok(()));
+
+        let operations = permitted_operations(&Role::EquipmentManager, Some(&kiosk));
+        assert!(operations
+            .iter()
+            .any(|o| o.method == "POST" && o.path == "/v1/returned_lending"));
+        assert!(!operations.iter().any(|o| o.path == "/v1/update_fixtures"));
+        assert!(!operations.iter().any(|o| o.path == "/v1/gen_passtoken"));
+    }
+}
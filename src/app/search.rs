@@ -0,0 +1,156 @@
+use crate::{
+    authentication::Role,
+    error_handling::{result_to_handler_with_log, QrError, Result, ReturnData},
+    search_engine::{
+        query::{MatchMode, SearchPage, SearchQuery, MAX_LIMIT},
+        FixturesDocument, SearchBackend, SearchContexts, SpotDocument,
+    },
+    Container, LendingView,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::*;
+use utoipa::ToSchema;
+
+/// まとめて検索するときに種類ごとに返す件数のデフォルト
+pub const SEARCH_ALL_DEFAULT_LIMIT: usize = 20;
+
+/// 検索できる情報の種類
+const SEARCH_TYPES: [&str; 4] = ["fixtures", "spot", "container", "lending"];
+
+/// 全ての種類の情報をまとめて検索した結果
+/// 検索しなかった種類は含まない
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchAllResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub fixtures: Option<SearchPage<FixturesDocument>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub spot: Option<SearchPage<SpotDocument>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub container: Option<SearchPage<Container>>,
+    /// 貸し出し中のものだけを含む
+    /// 物品管理者未満の権限では学籍番号を含めない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(inline)]
+    pub lending: Option<SearchPage<LendingView>>,
+}
+
+/// URLのクエリから検索語と組み合わせ方、ページ分割の指定を読む
+pub fn parse_keyword_query(query: &HashMap<String, String>) -> Result<SearchQuery> {
+    let mut search_query = SearchQuery {
+        keyword: query.get("keywords").cloned().unwrap_or_default(),
+        ..Default::default()
+    };
+    if let Some(mode) = query.get("mode") {
+        search_query.mode =
+            MatchMode::parse(mode).ok_or_else(|| QrError::InvalidQuery("mode".to_string()))?;
+    }
+    if let Some(offset) = query.get("offset") {
+        search_query.offset = offset
+            .parse()
+            .map_err(|_| QrError::InvalidQuery("offset".to_string()))?;
+    }
+    if let Some(limit) = query.get("limit") {
+        let limit: usize = limit
+            .parse()
+            .map_err(|_| QrError::InvalidQuery("limit".to_string()))?;
+        search_query.limit = limit.min(MAX_LIMIT);
+    }
+    Ok(search_query)
+}
+
+/// 検索する種類をカンマ区切りで読む
+/// 指定が無い場合は全ての種類を検索する
+fn parse_types(query: &HashMap<String, String>) -> Result<Vec<String>> {
+    match query.get("types") {
+        Some(types) => {
+            let types = types
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>();
+            if types.iter().any(|t| !SEARCH_TYPES.contains(&t.as_str())) {
+                Err(QrError::InvalidQuery("types".to_string()))
+            } else {
+                Ok(types)
+            }
+        }
+        None => Ok(SEARCH_TYPES.iter().map(|s| s.to_string()).collect()),
+    }
+}
+
+async fn search_all<B: SearchBackend>(
+    contexts: &SearchContexts<B>,
+    query: &SearchQuery,
+    types: &[String],
+    role: Option<&Role>,
+) -> Result<SearchAllResult> {
+    let is_target = |t: &str| types.iter().any(|s| s == t);
+    // 種類ごとの検索は互いに独立しているので同時に行う
+    let (fixtures, spot, container, lending) = tokio::join!(
+        async {
+            match is_target("fixtures") {
+                true => contexts.fixtures.search(query).await.map(Some),
+                false => Ok(None),
+            }
+        },
+        async {
+            match is_target("spot") {
+                true => contexts.spot.search(query).await.map(Some),
+                false => Ok(None),
+            }
+        },
+        async {
+            match is_target("container") {
+                true => contexts.container.search(query).await.map(Some),
+                false => Ok(None),
+            }
+        },
+        async {
+            match is_target("lending") {
+                true => contexts.lending.search(query).await.map(Some),
+                false => Ok(None),
+            }
+        },
+    );
+    Ok(SearchAllResult {
+        fixtures: fixtures?,
+        spot: spot?,
+        container: container?,
+        lending: lending?.map(|page| page.map(|lending| LendingView::new(lending, role))),
+    })
+}
+
+/// 物品・地点・コンテナ・貸し出し中の貸出情報をまとめて検索するエンドポイント
+/// - `keywords`, `mode`: `/search_fixtures`と同じ
+/// - `types`: 検索する種類をカンマ区切りで指定する（`fixtures`, `spot`, `container`, `lending`）
+/// - `offset`, `limit`: 種類ごとのページ分割。`limit`のデフォルトは20件
+pub async fn search<B: SearchBackend>(
+    role: Option<Role>,
+    query: HashMap<String, String>,
+    contexts: SearchContexts<B>,
+) -> ReturnData<SearchAllResult> {
+    let parsed = parse_types(&query).and_then(|types| {
+        let mut search_query = parse_keyword_query(&query)?;
+        if !query.contains_key("limit") {
+            search_query.limit = SEARCH_ALL_DEFAULT_LIMIT;
+        }
+        Ok((search_query, types))
+    });
+    match parsed {
+        Ok((search_query, types)) => {
+            info!("Try search all: {search_query:?} {types:?}");
+            let res = search_all(&contexts, &search_query, &types, role.as_ref()).await;
+            result_to_handler_with_log(
+                |_| Some(format!("Success search all[{search_query:?}]")),
+                |e| Some(format!("{e}[{search_query:?}]")),
+                &res,
+            )
+            .await
+        }
+        Err(e) => result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &Err(e)).await,
+    }
+}
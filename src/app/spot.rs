@@ -1,55 +1,97 @@
-use crate::authentication::{get_role, Role};
 use crate::{
-    error_handling::{result_to_handler, result_to_handler_with_log, QrError, ReturnData},
+    app::etag::{with_etag, ReturnWithETag},
+    error_handling::{result_to_handler_with_log, QrError, Result, ReturnData},
+    patch::{parse_patch, patch_fields, SpotPatch, SPOT_IMMUTABLE_FIELDS},
+    search_engine::{SearchBackend, SearchSpot, SpotDocument},
+    validation::validate,
     Spot,
 };
-use axum::{extract::Json, headers::authorization::Bearer};
+use axum::extract::Json;
 use sqlx::{pool::Pool, postgres::Postgres};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::*;
 
 /// 地点情報の登録を行うエンドポイント
-pub async fn insert_spot(
-    bearer: Bearer,
+pub async fn insert_spot<B: SearchBackend>(
     Json(spot): Json<Spot>,
     conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchSpot<B>>,
 ) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    info!("role: {role:?}");
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try insert spot: {spot:?}");
-        let res = crate::database::insert_spot::insert_spot(&*conn, spot.clone()).await;
-        result_to_handler_with_log(
-            |_| Some(format!("Success insert spot[{}]", &spot.name)),
-            |e| Some(format!("{e} spot[{}]", &spot.name)),
-            &res,
-        )
-        .await
-    } else {
-        result_to_handler(&Err(QrError::Authorized)).await
-    }
+    info!("Try insert spot: {spot:?}");
+    let res = match validate(&spot) {
+        Ok(()) => crate::database::insert_spot::insert_spot(&*conn, spot.clone()).await,
+        Err(e) => Err(e),
+    };
+    let res = match res {
+        Ok(()) => context.add_or_replace(&[spot.clone().into()]).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some(format!("Success insert spot[{}]", &spot.name)),
+        |e| Some(format!("{e} spot[{}]", &spot.name)),
+        &res,
+    )
+    .await
 }
 
 /// 地点情報の更新を行うエンドポイント
-pub async fn update_spot(
-    bearer: Bearer,
+/// `if_match`で版を指定した場合は、その版から変わっていない時だけ更新する
+pub async fn update_spot<B: SearchBackend>(
     Json(spot): Json<Spot>,
+    if_match: Result<Option<i64>>,
     conn: Arc<Pool<Postgres>>,
-) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::EquipmentManager) == role || Ok(Role::Administrator) == role {
-        info!("Try update spot: {spot:?}");
-        let res = crate::database::update_spot::update_spot(&*conn, spot.clone()).await;
-        result_to_handler_with_log(
-            |_| Some(format!("Success update spot[{}]", &spot.name)),
-            |e| Some(format!("{e} spot[{}]", &spot.name)),
-            &res,
-        )
-        .await
-    } else {
-        result_to_handler(&Err(QrError::Authorized)).await
+    context: Arc<SearchSpot<B>>,
+) -> ReturnWithETag<()> {
+    info!("Try update spot: {spot:?}");
+    let version = match if_match.and_then(|expected| validate(&spot).map(|_| expected)) {
+        Ok(expected) => {
+            crate::database::update_spot::update_spot(&*conn, spot.clone(), expected).await
+        }
+        Err(e) => Err(e),
+    };
+    let res = match &version {
+        Ok(_) => context.add_or_replace(&[spot.clone().into()]).await,
+        Err(e) => Err(e.clone()),
+    };
+    let ret = result_to_handler_with_log(
+        |_| Some(format!("Success update spot[{}]", &spot.name)),
+        |e| Some(format!("{e} spot[{}]", &spot.name)),
+        &res,
+    )
+    .await;
+    with_etag(ret, version.ok())
+}
+
+/// パスで指定した地点の情報のうち、本文に含まれる項目だけを更新するエンドポイント
+/// 更新後の地点の情報を返す
+pub async fn patch_spot<B: SearchBackend>(
+    name: String,
+    Json(body): Json<serde_json::Value>,
+    if_match: Result<Option<i64>>,
+    conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchSpot<B>>,
+) -> ReturnWithETag<Spot> {
+    info!("Try patch spot[{name}]: {:?}", patch_fields(&body));
+    let res = async {
+        let expected = if_match?;
+        let patch: SpotPatch = parse_patch(body, SPOT_IMMUTABLE_FIELDS)?;
+        validate(&patch)?;
+        let (spot, version) =
+            crate::database::patch_spot::patch_spot(&*conn, &name, &patch, expected).await?;
+        context.add_or_replace(&[spot.clone().into()]).await?;
+        Ok((spot, version))
     }
+    .await;
+    let version = res.as_ref().ok().map(|(_, version)| *version);
+    let res = res.map(|(spot, _)| spot);
+    let ret = result_to_handler_with_log(
+        |_| Some(format!("Success patch spot[{name}]")),
+        |e| Some(format!("{e} spot[{name}]")),
+        &res,
+    )
+    .await;
+    with_etag(ret, version)
 }
 
 /// 地点情報の取得を行うエンドポイント
@@ -88,30 +130,29 @@ pub async fn get_spot_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<Spot>> {
 }
 
 /// 地点情報の削除を行うエンドポイント
-pub async fn delte_spot(
-    bearer: Bearer,
+pub async fn delte_spot<B: SearchBackend>(
     query: HashMap<String, String>,
     conn: Arc<Pool<Postgres>>,
+    context: Arc<SearchSpot<B>>,
 ) -> ReturnData<()> {
-    let role = get_role(&*conn, bearer.token()).await;
-    if Ok(Role::Administrator) == role {
-        match query.get("name") {
-            Some(name) => {
-                info!("Try get one spot info: {name}");
-                let res = crate::database::delete_spot::delete_spot(&*conn, name).await;
-                result_to_handler_with_log(
-                    |_| Some(format!("Success delete spot[{name}]")),
-                    |e| Some(format!("{e} spot[{name}]")),
-                    &res,
-                )
-                .await
-            }
-            None => {
-                let err = Err(QrError::UrlQuery("name".to_string()));
-                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
-            }
+    match query.get("name") {
+        Some(name) => {
+            info!("Try get one spot info: {name}");
+            let res = crate::database::delete_spot::delete_spot(&*conn, name).await;
+            let res = match res {
+                Ok(()) => context.delete(&[SpotDocument::id(name)]).await,
+                Err(e) => Err(e),
+            };
+            result_to_handler_with_log(
+                |_| Some(format!("Success delete spot[{name}]")),
+                |e| Some(format!("{e} spot[{name}]")),
+                &res,
+            )
+            .await
+        }
+        None => {
+            let err = Err(QrError::UrlQuery("name".to_string()));
+            result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
         }
-    } else {
-        result_to_handler(&Err(QrError::Authorized)).await
     }
 }
@@ -0,0 +1,111 @@
+use crate::{
+    error_handling::{result_to_handler_with_log, QrError, ReturnData},
+    search_engine::{SearchBackend, SearchContexts},
+    Synonym,
+};
+use axum::extract::Json;
+use sqlx::{pool::Pool, postgres::Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::*;
+use uuid::Uuid;
+
+/// データベースにある同義語を読み込み直して検索エンジンの設定に反映する
+async fn reload_synonyms<B: SearchBackend>(
+    conn: &Pool<Postgres>,
+    contexts: &SearchContexts<B>,
+) -> crate::error_handling::Result<()> {
+    let synonyms = crate::database::get_synonym_list::get_synonym_list(conn).await?;
+    contexts.init(&synonyms).await
+}
+
+/// 同義語の登録を行うエンドポイント
+pub async fn insert_synonym<B: SearchBackend>(
+    Json(synonym): Json<Synonym>,
+    conn: Arc<Pool<Postgres>>,
+    contexts: SearchContexts<B>,
+) -> ReturnData<()> {
+    info!("Try insert synonym: {synonym:?}");
+    let res = crate::database::insert_synonym::insert_synonym(&*conn, synonym.clone()).await;
+
+    // DBの処理が成功した時の結果
+    let r1 = result_to_handler_with_log(
+        |_| Some(format!("Success insert synonym(DB)[{}]", synonym.id)),
+        |e| Some(format!("{e}[{}]", synonym.id)),
+        &res,
+    )
+    .await;
+
+    if res.is_ok() {
+        let res = reload_synonyms(&conn, &contexts).await;
+        result_to_handler_with_log(
+            |_| {
+                Some(format!(
+                    "Success insert synonym(Search Engine)[{}]",
+                    synonym.id
+                ))
+            },
+            |e| Some(format!("{e}[{}]", synonym.id)),
+            &res,
+        )
+        .await
+    } else {
+        r1
+    }
+}
+
+/// 同義語の一覧の取得を行うエンドポイント
+pub async fn get_synonym_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<Synonym>> {
+    info!("Try get synonym list");
+    let res = crate::database::get_synonym_list::get_synonym_list(&*conn).await;
+    result_to_handler_with_log(
+        |_| Some("Success get synonym list".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// 同義語の削除を行うエンドポイント
+pub async fn delete_synonym<B: SearchBackend>(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+    contexts: SearchContexts<B>,
+) -> ReturnData<()> {
+    match query.get("id") {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(uuid) => {
+                info!("Try delete synonym: {uuid}");
+                let res = crate::database::delete_synonym::delete_synonym(&*conn, uuid).await;
+
+                // DBの処理が成功した時の結果
+                let r1 = result_to_handler_with_log(
+                    |_| Some(format!("Success delete synonym(DB)[{uuid}]")),
+                    |e| Some(format!("{e}[{uuid}]")),
+                    &res,
+                )
+                .await;
+
+                if res.is_ok() {
+                    let res = reload_synonyms(&conn, &contexts).await;
+                    result_to_handler_with_log(
+                        |_| Some(format!("Success delete synonym(Search Engine)[{uuid}]")),
+                        |e| Some(format!("{e}[{uuid}]")),
+                        &res,
+                    )
+                    .await
+                } else {
+                    r1
+                }
+            }
+            Err(_) => {
+                let err = Err(QrError::BrokenUuid(id.to_string()));
+                result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+            }
+        },
+        None => {
+            let err = Err(QrError::UrlQuery("id".to_string()));
+            result_to_handler_with_log(|_| None, |e| Some(e.to_string()), &err).await
+        }
+    }
+}
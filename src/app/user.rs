@@ -0,0 +1,94 @@
+use crate::authentication::user::{self, NewUser, UpdateUser, User};
+use crate::error_handling::{result_to_handler_with_log, QrError, ReturnData};
+use axum::extract::Json;
+use sqlx::{pool::Pool, postgres::Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::*;
+use uuid::Uuid;
+
+/// URLのクエリにある`id`を読む
+fn parse_id_query(query: &HashMap<String, String>) -> crate::error_handling::Result<Uuid> {
+    match query.get("id") {
+        Some(id) => Uuid::parse_str(id).map_err(|_| QrError::BrokenUuid(id.to_string())),
+        None => Err(QrError::UrlQuery("id".to_string())),
+    }
+}
+
+/// アカウントの作成を行うエンドポイント
+pub async fn insert_user(
+    Json(new_user): Json<NewUser>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<User> {
+    info!("Try insert user: {}", new_user.name);
+    let res = user::insert_user(&*conn, &new_user).await;
+    result_to_handler_with_log(
+        |_| Some(format!("Success insert user[{}]", new_user.name)),
+        |e| Some(format!("{e}[{}]", new_user.name)),
+        &res,
+    )
+    .await
+}
+
+/// アカウントのパスワードや権限、有効かどうかの更新を行うエンドポイント
+pub async fn update_user(
+    Json(info): Json<UpdateUser>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<User> {
+    info!("Try update user: {}", info.id);
+    let res = user::update_user(&*conn, &info).await;
+    result_to_handler_with_log(
+        |_| Some(format!("Success update user[{}]", info.id)),
+        |e| Some(format!("{e}[{}]", info.id)),
+        &res,
+    )
+    .await
+}
+
+/// アカウントの一覧の取得を行うエンドポイント
+pub async fn get_user_list(conn: Arc<Pool<Postgres>>) -> ReturnData<Vec<User>> {
+    info!("Try get user list");
+    let res = user::get_user_list(&*conn).await;
+    result_to_handler_with_log(
+        |_| Some("Success get user list".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// IDで指定したアカウントの取得を行うエンドポイント
+pub async fn get_one_user(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<User> {
+    info!("Try get user: {:?}", query.get("id"));
+    let res = match parse_id_query(&query) {
+        Ok(id) => user::get_one_user(&*conn, id).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success get user".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
+
+/// アカウントの削除を行うエンドポイント
+pub async fn delete_user(
+    query: HashMap<String, String>,
+    conn: Arc<Pool<Postgres>>,
+) -> ReturnData<()> {
+    info!("Try delete user: {:?}", query.get("id"));
+    let res = match parse_id_query(&query) {
+        Ok(id) => user::delete_user(&*conn, id).await,
+        Err(e) => Err(e),
+    };
+    result_to_handler_with_log(
+        |_| Some("Success delete user".to_string()),
+        |e| Some(e.to_string()),
+        &res,
+    )
+    .await
+}
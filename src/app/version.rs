@@ -0,0 +1,104 @@
+//! APIのバージョンと、バージョンの付かない従来のパスの扱い
+//!
+//! 全てのエンドポイントを`/v1`の下に置き、将来互換性の無い変更を入れる時は`/v2`を追加する。
+//! フロントエンドとキオスク端末のアプリは同時に更新できないので、
+//! バージョンの付かない従来のパスも残し、非推奨であることを応答のヘッダーで知らせる。
+use axum::{
+    http::{HeaderValue, Request},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use std::env;
+
+pub use crate::{API_PREFIX, API_VERSION};
+
+/// 従来のパスで呼び出されたことを示すリクエストの拡張
+/// 応答の形を変えたエンドポイントは、これがあれば以前の形で返す
+#[derive(Debug, Clone, Copy)]
+pub struct Legacy;
+
+/// パスの先頭の`/v1`などのバージョンを取り除く
+/// 権限の一覧はバージョンの付かないパスで書いているので、検査の前に使う
+pub fn strip_version(path: &str) -> &str {
+    let Some(rest) = path.strip_prefix("/v") else {
+        return path;
+    };
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    match &rest[digits..] {
+        rest if digits > 0 && rest.starts_with('/') => rest,
+        "" if digits > 0 => "/",
+        _ => path,
+    }
+}
+
+/// エンドポイントを`/v1`の下に置き、従来のパスでは非推奨のヘッダーを付けて応答する
+pub fn versioned(api: Router) -> Router {
+    Router::new()
+        .nest(API_PREFIX, api.clone())
+        .merge(api.layer(middleware::from_fn(deprecate_legacy)))
+}
+
+/// 従来のパスへの応答に非推奨であることを示すヘッダーを付けるミドルウェア
+/// - `Deprecation: true`
+/// - `Link`で同じエンドポイントのバージョン付きのパス
+/// - 環境変数`LEGACY_API_SUNSET`が設定されていれば、廃止予定日時を`Sunset`で
+///
+/// ハンドラーが従来の形で応答できるよう、リクエストに[`Legacy`]を付ける
+async fn deprecate_legacy<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    req.extensions_mut().insert(Legacy);
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        API_PREFIX,
+        req.uri().path()
+    );
+    let mut res = next.run(req).await;
+    let headers = res.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert("link", link);
+    }
+    // docker-composeでは設定していなくても空の文字列として渡される
+    if let Some(sunset) = env::var("LEGACY_API_SUNSET")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .and_then(|s| HeaderValue::from_str(&s).ok())
+    {
+        headers.insert("sunset", sunset);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::app::version::{strip_version, versioned};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_strip_version() {
+        assert_eq!(strip_version("/v1/fixtures/:id"), "/fixtures/:id");
+        assert_eq!(strip_version("/v12/me"), "/me");
+        assert_eq!(strip_version("/v1"), "/");
+        assert_eq!(strip_version("/get_fixtures"), "/get_fixtures");
+        assert_eq!(strip_version("/version"), "/version");
+        assert_eq!(strip_version("/v/fixtures"), "/v/fixtures");
+    }
+
+    #[tokio::test]
+    async fn test_sunset() {
+        let app = versioned(Router::new().route("/ping", get(|| async { "pong" })));
+        let request = || Request::get("/ping").body(Body::empty()).unwrap();
+
+        // 空の文字列は設定されていないものとして扱う
+        std::env::set_var("LEGACY_API_SUNSET", "");
+        let res = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(res.headers()["deprecation"], "true");
+        assert!(res.headers().get("sunset").is_none());
+
+        std::env::set_var("LEGACY_API_SUNSET", "Sat, 01 Nov 2027 00:00:00 GMT");
+        let res = app.oneshot(request()).await.unwrap();
+        assert_eq!(res.headers()["sunset"], "Sat, 01 Nov 2027 00:00:00 GMT");
+        std::env::remove_var("LEGACY_API_SUNSET");
+    }
+}
@@ -5,13 +5,37 @@ use rand::{
     Rng,
 };
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::env;
+use subtle::ConstantTimeEq;
+use tracing::*;
 use unicode_normalization::UnicodeNormalization;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+/// 期限の無いスコープ付きのAPIキー
+pub mod api_key;
+/// トークンの発行での総当たり攻撃への対策
+pub mod login_attempt;
+/// OpenID Connectでのログイン
+pub mod oidc;
+/// アクセストークンを更新するリフレッシュトークン
+pub mod refresh;
+/// 個人ごとのアカウントの管理
+pub mod user;
+
+/// トークンの先頭に付ける照合用の部分の長さ
+const TOKEN_PREFIX_LENGTH: usize = 16;
+
+/// ハッシュに使うソルトの長さ
+const SALT_LENGTH: usize = 32;
+
+/// 生成したトークン
+/// トークンそのものは生成した時にしか分からず、データベースにはハッシュだけを保存する
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Passtoken {
     /// 認証用の一時トークン
+    /// `<照合用の部分>.<秘密の部分>`の形をしている
     pub token: String,
     /// トークンに付与された権限
     pub role: Role,
@@ -19,6 +43,87 @@ pub struct Passtoken {
     pub created_at: DateTime<Utc>,
     /// トークンの期限
     pub limit_days: i32,
+    /// トークンを一覧や失効の操作で指定するためのID
+    pub id: Uuid,
+    /// 失効させた日時
+    /// 失効していなければ`None`
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// トークンを発行したアカウントのID
+    /// 共有パスキーで発行したものは`None`
+    pub user_id: Option<Uuid>,
+    /// アクセストークンとしての期限
+    /// `None`の場合は`limit_days`だけで判断する
+    pub expires_at: Option<DateTime<Utc>>,
+    /// リフレッシュトークンと一緒に発行した場合はそのセッションのID
+    pub session_id: Option<Uuid>,
+}
+
+/// データベースに保存されているトークンの情報
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+struct StoredPasstoken {
+    role: Role,
+    created_at: DateTime<Utc>,
+    limit_days: i32,
+    id: Uuid,
+    revoked_at: Option<DateTime<Utc>>,
+    prefix: String,
+    salt: String,
+    token_hash: String,
+    /// 発行したアカウントが有効かどうか
+    /// 共有パスキーで発行したものは`None`
+    user_enabled: Option<bool>,
+    expires_at: Option<DateTime<Utc>>,
+    session_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    user_name: Option<String>,
+}
+
+impl StoredPasstoken {
+    /// 提示された秘密の部分がハッシュと一致するかを定数時間で比較する
+    fn verify(&self, secret: &str) -> bool {
+        let hash = hash_secret(&self.salt, secret);
+        hash.as_bytes().ct_eq(self.token_hash.as_bytes()).into()
+    }
+
+    /// 実際の期限
+    /// `limit_days`による期限とアクセストークンとしての期限の早い方
+    fn valid_until(&self) -> DateTime<Utc> {
+        let d = self.created_at + Duration::days(self.limit_days as i64);
+        self.expires_at.map_or(d, |e| e.min(d))
+    }
+
+    /// 有効期間内で、失効させられておらず、発行したアカウントも無効にされていないかを検査する
+    fn check_valid(&self) -> bool {
+        let now = Utc::now();
+        let d = self.created_at + Duration::days(self.limit_days as i64);
+        d > now
+            && self.expires_at.map_or(true, |e| e > now)
+            && self.revoked_at.is_none()
+            && self.user_enabled != Some(false)
+    }
+}
+
+/// 提示されたトークン自身の情報
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PasstokenIntrospection {
+    pub role: Role,
+    /// トークンが使えなくなる日時
+    pub expires_at: DateTime<Utc>,
+    /// アカウントで発行したものはそのアカウントのIDと名前
+    pub user_id: Option<Uuid>,
+    pub user_name: Option<String>,
+}
+
+/// 一覧に表示するためのトークンの情報
+/// トークンそのものは含めない
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, ToSchema)]
+pub struct PasstokenInfo {
+    pub id: Uuid,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub limit_days: i32,
+    pub user_id: Option<Uuid>,
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// トークンに与えられる権限情報
@@ -26,7 +131,7 @@ pub struct Passtoken {
 /// しかしsqlx v0.6以降できないらしく、DBにはtextで保存して変換をこちらで行うこととする。
 /// そのため、文字列に変換する`Display`トレイトと文字列から変換する`FromStr`トレイトを実装している。
 /// 参考：<https://github.com/launchbadge/sqlx/issues/1920>
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Role {
     /// 管理者権限 全ての操作ができる
@@ -37,10 +142,28 @@ pub enum Role {
     /// 一般ユーザー
     /// - 物品情報の閲覧
     /// - 貸し出し情報の閲覧
+    ///
     /// などの情報の閲覧のみ可能
     General,
 }
 
+impl Role {
+    /// 権限の強さ
+    /// 大きいほど多くの操作ができる
+    fn level(&self) -> u8 {
+        match self {
+            Role::Administrator => 2,
+            Role::EquipmentManager => 1,
+            Role::General => 0,
+        }
+    }
+
+    /// `required`以上の権限を持っているかどうか
+    pub fn is_at_least(&self, required: &Role) -> bool {
+        self.level() >= required.level()
+    }
+}
+
 pub fn str_to_role_opt(item: &str) -> Option<Role> {
     match item {
         "administrator" => Some(Role::Administrator),
@@ -79,67 +202,98 @@ impl Passtoken {
             role,
             created_at: now,
             limit_days: limit as i32,
+            id: Uuid::new_v4(),
+            revoked_at: None,
+            user_id: None,
+            expires_at: None,
+            session_id: None,
         }
     }
-    /// 有効期間内かどうかを検査する
+    /// アカウントに結びつける
+    pub fn with_user(mut self, user_id: Uuid) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+    /// 有効期間内で、失効させられていないかを検査する
     pub fn check_valid(&self) -> bool {
         let now = Utc::now();
         let d = self.created_at + Duration::days(self.limit_days as i64);
         // トークンの有効期限が現在時刻より大きければ有効
-        d > now
+        d > now && self.expires_at.map_or(true, |e| e > now) && self.revoked_at.is_none()
     }
 }
 
 /// Bearer認証用のトークンをランダムに生成する
+/// 先頭の照合用の部分と秘密の部分を`.`でつなげる
 fn gen_token() -> String {
     let mut rng = rand::thread_rng();
     let token_length: usize = rng.gen_range(200..300);
+    let prefix = Alphanumeric.sample_string(&mut rng, TOKEN_PREFIX_LENGTH);
     let uuid = Uuid::new_v4();
-    uuid.to_string() + &Alphanumeric.sample_string(&mut rng, token_length)
+    format!(
+        "{prefix}.{uuid}{}",
+        Alphanumeric.sample_string(&mut rng, token_length)
+    )
+}
+
+/// トークンを照合用の部分と秘密の部分に分ける
+fn split_token(token: &str) -> Option<(&str, &str)> {
+    token
+        .split_once('.')
+        .filter(|(prefix, secret)| prefix.len() == TOKEN_PREFIX_LENGTH && !secret.is_empty())
+}
+
+/// ソルトを付けて秘密の部分のハッシュを計算する
+fn hash_secret(salt: &str, secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(secret.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// 権限ごとの環境変数の名前の先頭部分
+fn env_prefix(role: &Role) -> &'static str {
+    match role {
+        Role::Administrator => "ADMINISTRATOR",
+        Role::EquipmentManager => "EQUIPMENT_MANAGER",
+        Role::General => "GENERAL",
+    }
+}
+
+/// 環境変数から数値を読む
+/// 設定されていない場合や空の場合、読めない場合はデフォルトの値を使う
+/// docker composeでは設定していない変数も空文字列で渡される
+pub(crate) fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(s) if !s.is_empty() => s.parse().unwrap_or_else(|_| {
+            warn!("Invalid value of {name}: {s}");
+            default
+        }),
+        _ => default,
+    }
+}
+
+/// 環境変数からトークンの有効期間を読み込む
+pub fn limit_days(role: &Role) -> Result<usize> {
+    let name = format!("{}_LIMIT_DAYS", env_prefix(role));
+    env::var(&name)
+        .map_err(|_| QrError::Environment(name.clone()))?
+        .parse::<usize>()
+        .map_err(|_| QrError::Environment(name))
 }
 
 /// 環境変数にあるマスターキーや有効期間などをもとに生成する
+/// アカウントを作るための最初の管理者用トークンの発行などに使う
 pub fn gen_passtoken(role: Role, key: &str) -> Result<Passtoken> {
-    match role {
-        Role::Administrator => {
-            let pass = env::var("ADMINISTRATOR_PASS_KEY")
-                .map_err(|_| QrError::Environment("ADMINISTRATOR_PASS_KEY".to_string()))?;
-            let limit_days = env::var("ADMINISTRATOR_LIMIT_DAYS")
-                .map_err(|_| QrError::Environment("ADMINISTRATOR_LIMIT_DAYS".to_string()))?
-                .parse::<usize>()
-                .map_err(|_| QrError::Environment("ADMINISTRATOR_LIMIT_DAYS".to_string()))?;
-            if key.nfc().collect::<String>() == pass.nfc().collect::<String>() {
-                Ok(Passtoken::new(role, limit_days))
-            } else {
-                Err(QrError::Authorized)
-            }
-        }
-        Role::EquipmentManager => {
-            let pass = env::var("EQUIPMENT_MANAGER_PASS_KEY")
-                .map_err(|_| QrError::Environment("EQUIPMENT_MANAGER_PASS_KEY".to_string()))?;
-            let limit_days = env::var("EQUIPMENT_MANAGER_LIMIT_DAYS")
-                .map_err(|_| QrError::Environment("EQUIPMENT_MANAGER_LIMIT_DAYS".to_string()))?
-                .parse::<usize>()
-                .map_err(|_| QrError::Environment("EQUIPMENT_MANAGER_LIMIT_DAYS".to_string()))?;
-            if key.nfc().collect::<String>() == pass.nfc().collect::<String>() {
-                Ok(Passtoken::new(role, limit_days))
-            } else {
-                Err(QrError::Authorized)
-            }
-        }
-        Role::General => {
-            let pass = env::var("GENERAL_PASS_KEY")
-                .map_err(|_| QrError::Environment("GENERAL_PASS_KEY".to_string()))?;
-            let limit_days = env::var("GENERAL_LIMIT_DAYS")
-                .map_err(|_| QrError::Environment("GENERAL_LIMIT_DAYS".to_string()))?
-                .parse::<usize>()
-                .map_err(|_| QrError::Environment("GENERAL_LIMIT_DAYS".to_string()))?;
-            if key.nfc().collect::<String>() == pass.nfc().collect::<String>() {
-                Ok(Passtoken::new(role, limit_days))
-            } else {
-                Err(QrError::Authorized)
-            }
-        }
+    let name = format!("{}_PASS_KEY", env_prefix(&role));
+    let pass = env::var(&name).map_err(|_| QrError::Environment(name))?;
+    let limit_days = limit_days(&role)?;
+    let key = key.nfc().collect::<String>();
+    let pass = pass.nfc().collect::<String>();
+    if bool::from(key.as_bytes().ct_eq(pass.as_bytes())) {
+        Ok(Passtoken::new(role, limit_days))
+    } else {
+        Err(QrError::Authorized)
     }
 }
 
@@ -152,20 +306,42 @@ where
         role,
         created_at,
         limit_days,
+        id,
+        revoked_at,
+        user_id,
+        expires_at,
+        session_id,
     } = passtoken;
+    let (prefix, secret) = split_token(token).ok_or(QrError::Authorized)?;
+    let salt = Alphanumeric.sample_string(&mut rand::thread_rng(), SALT_LENGTH);
+    let token_hash = hash_secret(&salt, secret);
 
     sqlx::query!(
         r#"
     INSERT INTO passtoken (
-        token,
+        prefix,
+        salt,
+        token_hash,
         role,
         created_at,
-        limit_days
-    ) VALUES ( $1, $2, $3, $4 )"#,
-        token,
+        limit_days,
+        id,
+        revoked_at,
+        user_id,
+        expires_at,
+        session_id
+    ) VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11 )"#,
+        prefix,
+        salt,
+        token_hash,
         role.to_string(),
         created_at,
-        limit_days
+        limit_days,
+        id,
+        *revoked_at,
+        *user_id,
+        *expires_at,
+        *session_id
     )
     .execute(conn)
     .await
@@ -174,23 +350,279 @@ where
     Ok(())
 }
 
+/// 提示されたトークンに対応する行を探す
+/// 照合用の部分で行を引いたうえで、秘密の部分をハッシュと比較する
+/// アカウントに結びついたトークンの権限は、アカウントの現在の権限を使う
+async fn find_passtoken<'a, E>(conn: E, token: &str) -> Result<StoredPasstoken>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let not_found = || QrError::DatabaseNotFound("passtoken".to_string());
+    let (prefix, secret) = split_token(token).ok_or_else(not_found)?;
+    let passtoken_opt = sqlx::query_as!(
+        StoredPasstoken,
+        r#"
+    SELECT
+        COALESCE(users.role, passtoken.role) AS "role!",
+        passtoken.created_at,
+        limit_days,
+        passtoken.id,
+        revoked_at,
+        prefix,
+        salt,
+        token_hash,
+        users.enabled AS "user_enabled?",
+        expires_at,
+        session_id,
+        passtoken.user_id,
+        users.name AS "user_name?"
+    FROM passtoken LEFT JOIN users ON passtoken.user_id = users.id
+    WHERE prefix = $1"#,
+        prefix
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("passtoken".to_string()))?;
+    match passtoken_opt {
+        Some(passtoken) if passtoken.verify(secret) => Ok(passtoken),
+        _ => Err(not_found()),
+    }
+}
+
 /// トークンを検査し、有効であればそれに結びついているロールを返す
 pub async fn get_role<'a, E>(conn: E, token: &str) -> Result<Role>
 where
     E: sqlx::Executor<'a, Database = sqlx::Postgres>,
 {
-    let passtoken_opt: Option<Passtoken> =
-        sqlx::query_as!(Passtoken, "SELECT * FROM passtoken WHERE token = $1", token)
-            .fetch_optional(conn)
+    let passtoken = find_passtoken(conn, token).await?;
+    if passtoken.check_valid() {
+        Ok(passtoken.role)
+    } else {
+        Err(QrError::Authorized)
+    }
+}
+
+/// トークンを検査し、有効であればロールと呼び出した端末を識別する値を返す
+/// リフレッシュでアクセストークンが置き換わっても同じ値になるよう、セッションがあればそのIDを使う
+pub async fn identify_passtoken<'a, E>(conn: E, token: &str) -> Result<(Role, String)>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let passtoken = find_passtoken(conn, token).await?;
+    if passtoken.check_valid() {
+        let caller = match passtoken.session_id {
+            Some(session_id) => format!("session:{session_id}"),
+            None => format!("token:{}", passtoken.id),
+        };
+        Ok((passtoken.role, caller))
+    } else {
+        Err(QrError::Authorized)
+    }
+}
+
+/// トークンを検査し、有効であればその権限や期限、発行したアカウントを返す
+pub async fn introspect_passtoken<'a, E>(conn: E, token: &str) -> Result<PasstokenIntrospection>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let passtoken = find_passtoken(conn, token).await?;
+    if passtoken.check_valid() {
+        Ok(PasstokenIntrospection {
+            expires_at: passtoken.valid_until(),
+            role: passtoken.role,
+            user_id: passtoken.user_id,
+            user_name: passtoken.user_name,
+        })
+    } else {
+        Err(QrError::Authorized)
+    }
+}
+
+/// 提示されたトークンを失効させる
+/// 有効なトークンが見つからなかった場合はエラーを返す
+pub async fn revoke_passtoken<'a, E>(conn: E, token: &str) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let passtoken = find_passtoken(conn.clone(), token)
+        .await
+        .map_err(|_| QrError::Authorized)?;
+    let res = sqlx::query!(
+        "UPDATE passtoken SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL",
+        passtoken.id
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("passtoken".to_string()))?;
+    if res.rows_affected() == 0 {
+        return Err(QrError::Authorized);
+    }
+    if let Some(session_id) = passtoken.session_id {
+        refresh::revoke_session(conn, session_id).await?;
+    }
+    Ok(())
+}
+
+/// IDで指定したトークンを失効させる
+/// リフレッシュトークンと一緒に発行したものは、そのセッションごと失効させる
+pub async fn revoke_passtoken_by_id<'a, E>(conn: E, id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let revoked = sqlx::query!(
+        r#"
+    UPDATE passtoken SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL
+    RETURNING session_id"#,
+        id
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("passtoken".to_string()))?
+    .ok_or_else(|| QrError::DatabaseNotFound(id.to_string()))?;
+    if let Some(session_id) = revoked.session_id {
+        refresh::revoke_session(conn, session_id).await?;
+    }
+    Ok(())
+}
+
+/// 有効なトークンをまとめて失効させ、失効させた数を返す
+/// - `role`を指定した場合はその権限のトークンだけを対象にする
+/// - `except`に指定したトークンと、そのセッションのリフレッシュトークンは失効させない
+pub async fn revoke_all_passtoken<'a, E>(conn: E, role: Option<Role>, except: &str) -> Result<u64>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let except = split_token(except).map(|(prefix, _)| prefix);
+    sqlx::query!(
+        r#"
+    UPDATE refresh_token SET revoked_at = now()
+    WHERE revoked_at IS NULL
+        AND ($1::text IS NULL OR role = $1)
+        AND session_id IS DISTINCT FROM (
+            SELECT session_id FROM passtoken WHERE prefix = $2
+        )"#,
+        role.as_ref().map(|r| r.to_string()),
+        except
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("refresh_token".to_string()))?;
+    let res = sqlx::query!(
+        r#"
+    UPDATE passtoken SET revoked_at = now()
+    WHERE revoked_at IS NULL
+        AND ($1::text IS NULL OR role = $1)
+        AND prefix IS DISTINCT FROM $2"#,
+        role.map(|r| r.to_string()),
+        except
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("passtoken".to_string()))?;
+    Ok(res.rows_affected())
+}
+
+/// 期限内で失効していないトークンの一覧を作成日時の新しい順に取得する
+/// `role`を指定した場合はその権限のトークンだけを返す
+pub async fn get_active_passtoken_list<'a, E>(
+    conn: E,
+    role: Option<Role>,
+) -> Result<Vec<PasstokenInfo>>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        PasstokenInfo,
+        r#"
+    SELECT id, role, created_at, limit_days, user_id, expires_at FROM passtoken
+    WHERE revoked_at IS NULL
+        AND created_at + make_interval(days => limit_days) > now()
+        AND (expires_at IS NULL OR expires_at > now())
+        AND ($1::text IS NULL OR role = $1)
+    ORDER BY created_at DESC"#,
+        role.map(|r| r.to_string())
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("passtoken".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::{
+        env_or, get_active_passtoken_list, get_role, insert_passtoken, revoke_all_passtoken,
+        revoke_passtoken, revoke_passtoken_by_id, Passtoken, Role,
+    };
+    use sqlx::{pool::Pool, Postgres};
+
+    #[test]
+    fn test_env_or() {
+        // docker composeで設定していない変数は空文字列になる
+        std::env::set_var("TEST_ENV_OR", "");
+        assert!(env_or("TEST_ENV_OR", true));
+        std::env::set_var("TEST_ENV_OR", "false");
+        assert!(!env_or("TEST_ENV_OR", true));
+        std::env::set_var("TEST_ENV_OR", "x");
+        assert!(env_or("TEST_ENV_OR", true));
+        std::env::remove_var("TEST_ENV_OR");
+        assert!(env_or("TEST_ENV_OR", true));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_passtoken_is_hashed(pool: Pool<Postgres>) {
+        let passtoken = Passtoken::new(Role::Administrator, 1);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        assert_eq!(
+            get_role(&pool, &passtoken.token).await,
+            Ok(Role::Administrator)
+        );
+
+        // データベースにはトークンそのものが残らない
+        let row: (String, String) = sqlx::query_as("SELECT prefix, token_hash FROM passtoken")
+            .fetch_one(&pool)
             .await
-            .map_err(|_| QrError::DatabaseGet("passtoken".to_string()))?;
-    if let Some(passtoken) = passtoken_opt {
-        if passtoken.check_valid() {
-            Ok(passtoken.role)
-        } else {
-            Err(QrError::Authorized)
+            .unwrap();
+        assert!(passtoken.token.starts_with(&format!("{}.", row.0)));
+        assert!(!passtoken.token.contains(&row.1));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_revoke_passtoken(pool: Pool<Postgres>) {
+        let admin = Passtoken::new(Role::Administrator, 1);
+        let manager1 = Passtoken::new(Role::EquipmentManager, 1);
+        let manager2 = Passtoken::new(Role::EquipmentManager, 1);
+        let general = Passtoken::new(Role::General, 1);
+        for passtoken in [&admin, &manager1, &manager2, &general] {
+            insert_passtoken(&pool, passtoken).await.unwrap();
         }
-    } else {
-        Err(QrError::DatabaseNotFound(token.to_string()))
+        let lst = get_active_passtoken_list(&pool, None).await.unwrap();
+        assert_eq!(lst.len(), 4);
+
+        // 照合用の部分が合っていても秘密の部分が違えば通らない
+        let (prefix, _) = admin.token.split_once('.').unwrap();
+        assert!(get_role(&pool, &format!("{prefix}.wrong")).await.is_err());
+        assert!(get_role(&pool, prefix).await.is_err());
+
+        // ログアウト
+        revoke_passtoken(&pool, &manager1.token).await.unwrap();
+        assert!(get_role(&pool, &manager1.token).await.is_err());
+        assert!(revoke_passtoken(&pool, &manager1.token).await.is_err());
+
+        revoke_passtoken_by_id(&pool, general.id).await.unwrap();
+        assert!(get_role(&pool, &general.token).await.is_err());
+
+        let lst = get_active_passtoken_list(&pool, Some(Role::EquipmentManager))
+            .await
+            .unwrap();
+        assert_eq!(lst.len(), 1);
+        assert_eq!(lst[0].id, manager2.id);
+
+        // 自分のトークン以外を全て失効させる
+        let n = revoke_all_passtoken(&pool, None, &admin.token)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(get_role(&pool, &admin.token).await, Ok(Role::Administrator));
+        assert!(get_role(&pool, &manager2.token).await.is_err());
     }
 }
@@ -0,0 +1,300 @@
+//! キオスク端末や連携スクリプト向けのAPIキー
+//!
+//! トークンと違って期限が無く、呼び出せる操作をスコープで制限する。
+//! ラベルを付けて管理し、不要になったら失効させる。
+use crate::authentication::{hash_secret, Role, SALT_LENGTH, TOKEN_PREFIX_LENGTH};
+use crate::error_handling::{QrError, Result};
+use chrono::{DateTime, Utc};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    Rng,
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// APIキーの先頭に付ける文字列
+/// トークンの照合用の部分は英数字だけなので、これで見分けられる
+pub const API_KEY_PREFIX: &str = "key_";
+
+/// APIキーで呼び出せる操作の範囲
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// 物品や地点、貸出情報などの閲覧
+    Read,
+    /// 貸し出しと返却の記録
+    Lending,
+    /// 物品や地点、コンテナ、同義語の登録と更新
+    Edit,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Scope::Read => write!(f, "read"),
+            Scope::Lending => write!(f, "lending"),
+            Scope::Edit => write!(f, "edit"),
+        }
+    }
+}
+
+pub fn str_to_scope_opt(item: &str) -> Option<Scope> {
+    match item {
+        "read" => Some(Scope::Read),
+        "lending" => Some(Scope::Lending),
+        "edit" => Some(Scope::Edit),
+        _ => None,
+    }
+}
+
+/// APIキーの情報
+/// キーそのものは含めない
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct ApiKey {
+    pub id: Uuid,
+    /// 用途がわかるように付ける名前
+    pub label: String,
+    /// キーに付与された権限
+    /// 管理者の権限は付与できない
+    pub role: Role,
+    /// キーで呼び出せる操作の範囲
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    /// 最後に使われた日時
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// 失効させた日時
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// APIキーを発行する時に受け取る情報
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct NewApiKey {
+    pub label: String,
+    pub role: Role,
+    pub scopes: Vec<Scope>,
+}
+
+/// 発行したAPIキー
+/// キーそのものは発行した時にしか分からない
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct IssuedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+/// データベースに保存されているAPIキー
+#[derive(Debug, Clone)]
+struct StoredApiKey {
+    id: Uuid,
+    label: String,
+    role: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+    salt: String,
+    key_hash: String,
+}
+
+impl StoredApiKey {
+    fn into_api_key(self) -> ApiKey {
+        ApiKey {
+            id: self.id,
+            label: self.label,
+            role: self.role.into(),
+            scopes: self
+                .scopes
+                .iter()
+                .filter_map(|s| str_to_scope_opt(s))
+                .collect(),
+            created_at: self.created_at,
+            last_used_at: self.last_used_at,
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+/// 提示された文字列がAPIキーの形をしているかどうか
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+/// APIキーを照合用の部分と秘密の部分に分ける
+fn split_api_key(key: &str) -> Option<(&str, &str)> {
+    key.split_once('.').filter(|(prefix, secret)| {
+        prefix.len() == API_KEY_PREFIX.len() + TOKEN_PREFIX_LENGTH
+            && prefix.starts_with(API_KEY_PREFIX)
+            && !secret.is_empty()
+    })
+}
+
+/// APIキーを発行する
+pub async fn insert_api_key<'a, E>(conn: E, new_key: &NewApiKey) -> Result<IssuedApiKey>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    if new_key.role == Role::Administrator {
+        return Err(QrError::Forbidden);
+    }
+    let (prefix, secret, salt) = {
+        let mut rng = rand::thread_rng();
+        let secret_length: usize = rng.gen_range(64..96);
+        (
+            format!(
+                "{API_KEY_PREFIX}{}",
+                Alphanumeric.sample_string(&mut rng, TOKEN_PREFIX_LENGTH)
+            ),
+            Alphanumeric.sample_string(&mut rng, secret_length),
+            Alphanumeric.sample_string(&mut rng, SALT_LENGTH),
+        )
+    };
+    let key_hash = hash_secret(&salt, &secret);
+    let mut scopes = new_key.scopes.clone();
+    scopes.dedup();
+    let stored = sqlx::query_as!(
+        StoredApiKey,
+        r#"
+    INSERT INTO api_key (id, label, prefix, salt, key_hash, role, scopes, created_at)
+    VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+    RETURNING id, label, role, scopes, created_at, last_used_at, revoked_at, salt, key_hash"#,
+        Uuid::new_v4(),
+        new_key.label,
+        prefix,
+        salt,
+        key_hash,
+        new_key.role.to_string(),
+        &scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+        Utc::now()
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|_| QrError::DatabaseAdd("api_key".to_string()))?;
+    Ok(IssuedApiKey {
+        key: format!("{prefix}.{secret}"),
+        api_key: stored.into_api_key(),
+    })
+}
+
+/// 提示されたAPIキーを検査し、有効であればその情報を返す
+/// 最後に使われた日時も更新する
+pub async fn authenticate_api_key<'a, E>(conn: E, key: &str) -> Result<ApiKey>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let (prefix, secret) = split_api_key(key).ok_or(QrError::Authorized)?;
+    let stored = sqlx::query_as!(
+        StoredApiKey,
+        r#"
+    SELECT id, label, role, scopes, created_at, last_used_at, revoked_at, salt, key_hash
+    FROM api_key WHERE prefix = $1"#,
+        prefix
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseGet("api_key".to_string()))?
+    .ok_or(QrError::Authorized)?;
+    let hash = hash_secret(&stored.salt, secret);
+    let matched: bool = hash.as_bytes().ct_eq(stored.key_hash.as_bytes()).into();
+    if !matched || stored.revoked_at.is_some() {
+        return Err(QrError::Authorized);
+    }
+    sqlx::query!(
+        "UPDATE api_key SET last_used_at = now() WHERE id = $1",
+        stored.id
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("api_key".to_string()))?;
+    Ok(stored.into_api_key())
+}
+
+/// APIキーの一覧を作成日時の新しい順に取得する
+/// 失効させたものも含む
+pub async fn get_api_key_list<'a, E>(conn: E) -> Result<Vec<ApiKey>>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let lst = sqlx::query_as!(
+        StoredApiKey,
+        r#"
+    SELECT id, label, role, scopes, created_at, last_used_at, revoked_at, salt, key_hash
+    FROM api_key ORDER BY created_at DESC"#
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("api_key".to_string()))?;
+    Ok(lst.into_iter().map(StoredApiKey::into_api_key).collect())
+}
+
+/// IDで指定したAPIキーを失効させる
+pub async fn revoke_api_key<'a, E>(conn: E, id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let res = sqlx::query!(
+        "UPDATE api_key SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL",
+        id
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("api_key".to_string()))?;
+    if res.rows_affected() == 0 {
+        Err(QrError::DatabaseNotFound(id.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::api_key::{
+        authenticate_api_key, get_api_key_list, insert_api_key, is_api_key, revoke_api_key,
+        NewApiKey, Scope,
+    };
+    use crate::authentication::Role;
+    use crate::error_handling::QrError;
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_api_key(pool: Pool<Postgres>) {
+        let new_key = NewApiKey {
+            label: "倉庫のキオスク".to_string(),
+            role: Role::EquipmentManager,
+            scopes: vec![Scope::Read, Scope::Lending],
+        };
+        let issued = insert_api_key(&pool, &new_key).await.unwrap();
+        assert!(is_api_key(&issued.key));
+        assert!(issued.api_key.last_used_at.is_none());
+
+        // 管理者の権限は付与できない
+        let res = insert_api_key(
+            &pool,
+            &NewApiKey {
+                role: Role::Administrator,
+                ..new_key.clone()
+            },
+        )
+        .await;
+        assert_eq!(res, Err(QrError::Forbidden));
+
+        let api_key = authenticate_api_key(&pool, &issued.key).await.unwrap();
+        assert_eq!(api_key.scopes, vec![Scope::Read, Scope::Lending]);
+        assert!(api_key.last_used_at.is_none());
+        let lst = get_api_key_list(&pool).await.unwrap();
+        assert!(lst[0].last_used_at.is_some());
+
+        let (prefix, _) = issued.key.split_once('.').unwrap();
+        assert!(authenticate_api_key(&pool, &format!("{prefix}.wrong"))
+            .await
+            .is_err());
+
+        revoke_api_key(&pool, api_key.id).await.unwrap();
+        assert_eq!(
+            authenticate_api_key(&pool, &issued.key).await,
+            Err(QrError::Authorized)
+        );
+        assert!(revoke_api_key(&pool, api_key.id).await.is_err());
+    }
+}
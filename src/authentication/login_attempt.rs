@@ -0,0 +1,268 @@
+//! トークンの発行での総当たり攻撃への対策
+//!
+//! 失敗した試行をIPアドレスごと、ユーザー名（権限の名前やアカウントの名前）ごとに数え、
+//! 連続した失敗が上限に達したら、失敗するたびに倍に伸びる時間だけ試行をロックする。
+//! 失敗した試行はすべて記録し、管理者が確認できるようにする。
+use crate::authentication::env_or;
+use crate::error_handling::{QrError, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// 試行のロックの設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockoutConfig {
+    /// 同じIPアドレスからの連続した失敗をこの回数まで許す
+    pub max_failures_per_ip: i32,
+    /// 同じユーザー名への連続した失敗をこの回数まで許す
+    /// 正規の利用者を締め出す嫌がらせに使われにくいよう、IPアドレスごとよりも大きくする
+    pub max_failures_per_name: i32,
+    /// 最初にロックする秒数
+    pub lockout_seconds: i64,
+    /// ロックする秒数の上限
+    pub max_lockout_seconds: i64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            max_failures_per_ip: 5,
+            max_failures_per_name: 20,
+            lockout_seconds: 30,
+            max_lockout_seconds: 3600,
+        }
+    }
+}
+
+impl LockoutConfig {
+    /// 環境変数から読み込む
+    /// - `LOGIN_MAX_FAILURES_PER_IP`
+    /// - `LOGIN_MAX_FAILURES_PER_NAME`
+    /// - `LOGIN_LOCKOUT_SECONDS`
+    /// - `LOGIN_MAX_LOCKOUT_SECONDS`
+    pub fn from_env() -> Self {
+        let default = LockoutConfig::default();
+        LockoutConfig {
+            max_failures_per_ip: env_or("LOGIN_MAX_FAILURES_PER_IP", default.max_failures_per_ip),
+            max_failures_per_name: env_or(
+                "LOGIN_MAX_FAILURES_PER_NAME",
+                default.max_failures_per_name,
+            ),
+            lockout_seconds: env_or("LOGIN_LOCKOUT_SECONDS", default.lockout_seconds),
+            max_lockout_seconds: env_or("LOGIN_MAX_LOCKOUT_SECONDS", default.max_lockout_seconds),
+        }
+    }
+
+    /// 連続した失敗の回数からロックする秒数を計算する
+    /// 上限に達するまではロックしない
+    pub fn lockout_duration(&self, failures: i32, max_failures: i32) -> Option<i64> {
+        if failures < max_failures {
+            return None;
+        }
+        let exponent = (failures - max_failures).min(32) as u32;
+        Some(
+            self.lockout_seconds
+                .saturating_mul(2_i64.saturating_pow(exponent))
+                .min(self.max_lockout_seconds),
+        )
+    }
+}
+
+/// 失敗した試行の記録
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, ToSchema)]
+pub struct LoginFailure {
+    pub id: Uuid,
+    /// 試行したクライアントのIPアドレス
+    pub ip: String,
+    /// Basic認証で送られたユーザー名
+    pub name: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{ip}")
+}
+
+fn name_key(name: &str) -> String {
+    format!("name:{name}")
+}
+
+/// IPアドレスとユーザー名のどちらかがロックされていれば`QrError::TooManyAttempts`を返す
+pub async fn check_lockout<'a, E>(conn: E, ip: &str, name: &str) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let locked_until = sqlx::query_scalar!(
+        r#"
+    SELECT MAX(locked_until) FROM login_lockout
+    WHERE key = ANY($1) AND locked_until > now()"#,
+        &[ip_key(ip), name_key(name)]
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("login_lockout".to_string()))?;
+    match locked_until {
+        Some(locked_until) => {
+            let seconds = (locked_until - Utc::now()).num_seconds() + 1;
+            Err(QrError::TooManyAttempts(seconds.max(1)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// 失敗した試行を記録し、連続した失敗の回数に応じてロックする
+pub async fn record_failure<'a, E>(
+    conn: E,
+    config: &LockoutConfig,
+    ip: &str,
+    name: &str,
+) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    sqlx::query!(
+        r#"
+    INSERT INTO login_failure (id, ip, name, attempted_at)
+    VALUES ( $1, $2, $3, $4 )"#,
+        Uuid::new_v4(),
+        ip,
+        name,
+        Utc::now()
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseAdd("login_failure".to_string()))?;
+
+    let keys = [
+        (ip_key(ip), config.max_failures_per_ip),
+        (name_key(name), config.max_failures_per_name),
+    ];
+    for (key, max_failures) in keys {
+        let failures = sqlx::query_scalar!(
+            r#"
+    INSERT INTO login_lockout (key, failures) VALUES ( $1, 1 )
+    ON CONFLICT (key) DO UPDATE SET failures = login_lockout.failures + 1
+    RETURNING failures"#,
+            key
+        )
+        .fetch_one(conn.clone())
+        .await
+        .map_err(|_| QrError::DatabaseUpdate("login_lockout".to_string()))?;
+        if let Some(seconds) = config.lockout_duration(failures, max_failures) {
+            warn!("Lock out {key} for {seconds} seconds after {failures} failures");
+            sqlx::query!(
+                "UPDATE login_lockout SET locked_until = $2 WHERE key = $1",
+                key,
+                Utc::now() + Duration::seconds(seconds)
+            )
+            .execute(conn.clone())
+            .await
+            .map_err(|_| QrError::DatabaseUpdate("login_lockout".to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// 成功した試行のIPアドレスとユーザー名の失敗の回数を戻す
+pub async fn record_success<'a, E>(conn: E, ip: &str, name: &str) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "DELETE FROM login_lockout WHERE key = ANY($1)",
+        &[ip_key(ip), name_key(name)]
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseDelete("login_lockout".to_string()))?;
+    Ok(())
+}
+
+/// 失敗した試行の記録を新しい順に`limit`件取得する
+pub async fn get_login_failure_list<'a, E>(conn: E, limit: i64) -> Result<Vec<LoginFailure>>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        LoginFailure,
+        r#"
+    SELECT id, ip, name, attempted_at FROM login_failure
+    ORDER BY attempted_at DESC LIMIT $1"#,
+        limit
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("login_failure".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::login_attempt::{
+        check_lockout, get_login_failure_list, record_failure, record_success, LockoutConfig,
+    };
+    use crate::error_handling::QrError;
+    use sqlx::{pool::Pool, Postgres};
+
+    #[test]
+    fn test_lockout_duration() {
+        let config = LockoutConfig::default();
+        assert_eq!(config.lockout_duration(4, 5), None);
+        assert_eq!(config.lockout_duration(5, 5), Some(30));
+        assert_eq!(config.lockout_duration(6, 5), Some(60));
+        assert_eq!(config.lockout_duration(8, 5), Some(240));
+        assert_eq!(config.lockout_duration(100, 5), Some(3600));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_login_attempt(pool: Pool<Postgres>) {
+        let config = LockoutConfig {
+            max_failures_per_ip: 2,
+            max_failures_per_name: 3,
+            ..LockoutConfig::default()
+        };
+        record_failure(&pool, &config, "192.0.2.1", "administrator")
+            .await
+            .unwrap();
+        assert_eq!(
+            check_lockout(&pool, "192.0.2.1", "administrator").await,
+            Ok(())
+        );
+        record_failure(&pool, &config, "192.0.2.1", "administrator")
+            .await
+            .unwrap();
+        assert!(matches!(
+            check_lockout(&pool, "192.0.2.1", "general").await,
+            Err(QrError::TooManyAttempts(_))
+        ));
+
+        // 別のIPアドレスからでも、同じユーザー名への失敗が続けばロックされる
+        assert_eq!(
+            check_lockout(&pool, "192.0.2.2", "administrator").await,
+            Ok(())
+        );
+        record_failure(&pool, &config, "192.0.2.2", "administrator")
+            .await
+            .unwrap();
+        assert!(matches!(
+            check_lockout(&pool, "192.0.2.2", "administrator").await,
+            Err(QrError::TooManyAttempts(_))
+        ));
+        assert_eq!(check_lockout(&pool, "192.0.2.2", "general").await, Ok(()));
+
+        let failures = get_login_failure_list(&pool, 2).await.unwrap();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].ip, "192.0.2.2");
+
+        record_success(&pool, "192.0.2.1", "administrator")
+            .await
+            .unwrap();
+        assert_eq!(
+            check_lockout(&pool, "192.0.2.1", "administrator").await,
+            Ok(())
+        );
+        // 失敗の記録は残る
+        assert_eq!(get_login_failure_list(&pool, 100).await.unwrap().len(), 3);
+    }
+}
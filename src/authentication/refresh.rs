@@ -0,0 +1,305 @@
+//! アクセストークンを更新するリフレッシュトークン
+//!
+//! ログインすると、数分で切れるアクセストークンとリフレッシュトークンの組を発行する。
+//! リフレッシュトークンは一度しか使えず、使うたびに新しい組に置き換える。
+//! 一度のログインで発行した組は同じセッションとしてまとめ、
+//! 使用済みのリフレッシュトークンが再び提示された場合は盗まれたものとみなしてセッションごと失効させる。
+use crate::authentication::{
+    env_or, env_prefix, gen_token, hash_secret, insert_passtoken, split_token, Passtoken, Role,
+    SALT_LENGTH,
+};
+use crate::error_handling::{QrError, Result};
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::*;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// アクセストークンの有効期間のデフォルトの分数
+const DEFAULT_ACCESS_TOKEN_MINUTES: i64 = 15;
+
+/// リフレッシュトークンを使わずに放置できる時間のデフォルトの分数
+const DEFAULT_IDLE_MINUTES: i64 = 720;
+
+/// ログインやリフレッシュで発行するトークンの組
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct TokenPair {
+    /// `Authorization: Bearer`で送るアクセストークン
+    pub token: String,
+    /// 新しい組を発行するためのリフレッシュトークン
+    pub refresh_token: String,
+    /// アクセストークンの期限
+    pub expires_at: DateTime<Utc>,
+}
+
+/// リフレッシュトークンでの更新に受け取る情報
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// データベースに保存されているリフレッシュトークン
+#[derive(Debug, Clone)]
+struct StoredRefreshToken {
+    id: Uuid,
+    session_id: Uuid,
+    salt: String,
+    token_hash: String,
+    role: Role,
+    user_id: Option<Uuid>,
+    user_enabled: Option<bool>,
+    session_started_at: DateTime<Utc>,
+    limit_days: i32,
+    created_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+/// 環境変数`ACCESS_TOKEN_MINUTES`からアクセストークンの有効期間を読み込む
+pub fn access_token_minutes() -> i64 {
+    env_or("ACCESS_TOKEN_MINUTES", DEFAULT_ACCESS_TOKEN_MINUTES)
+}
+
+/// 環境変数`ADMINISTRATOR_IDLE_MINUTES`などから、
+/// リフレッシュトークンを使わずに放置できる時間を読み込む
+pub fn idle_minutes(role: &Role) -> i64 {
+    env_or(
+        &format!("{}_IDLE_MINUTES", env_prefix(role)),
+        DEFAULT_IDLE_MINUTES,
+    )
+}
+
+/// セッションの中で新しいトークンの組を発行する
+/// アクセストークンの期限はセッションの期限を超えない
+async fn issue<'a, E>(
+    conn: E,
+    mut passtoken: Passtoken,
+    session_id: Uuid,
+    session_started_at: DateTime<Utc>,
+) -> Result<TokenPair>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let session_end = session_started_at + Duration::days(passtoken.limit_days as i64);
+    let expires_at =
+        (passtoken.created_at + Duration::minutes(access_token_minutes())).min(session_end);
+    passtoken.expires_at = Some(expires_at);
+    passtoken.session_id = Some(session_id);
+    insert_passtoken(conn.clone(), &passtoken).await?;
+
+    let refresh_token = gen_token();
+    let (prefix, secret) = split_token(&refresh_token).ok_or(QrError::Authorized)?;
+    let salt = Alphanumeric.sample_string(&mut rand::thread_rng(), SALT_LENGTH);
+    sqlx::query!(
+        r#"
+    INSERT INTO refresh_token (
+        id,
+        session_id,
+        prefix,
+        salt,
+        token_hash,
+        role,
+        user_id,
+        session_started_at,
+        limit_days,
+        created_at
+    ) VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10 )"#,
+        Uuid::new_v4(),
+        session_id,
+        prefix,
+        salt,
+        hash_secret(&salt, secret),
+        passtoken.role.to_string(),
+        passtoken.user_id,
+        session_started_at,
+        passtoken.limit_days,
+        passtoken.created_at
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseAdd("refresh_token".to_string()))?;
+
+    Ok(TokenPair {
+        token: passtoken.token,
+        refresh_token,
+        expires_at,
+    })
+}
+
+/// ログインしたときに新しいセッションを始め、最初のトークンの組を発行する
+/// `passtoken`の`limit_days`がセッション全体の有効期間になる
+pub async fn start_session<'a, E>(conn: E, passtoken: Passtoken) -> Result<TokenPair>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let session_started_at = passtoken.created_at;
+    issue(conn, passtoken, Uuid::new_v4(), session_started_at).await
+}
+
+/// リフレッシュトークンを新しいトークンの組に置き換える
+/// - 使用済みのものが提示された場合は、セッションごと失効させる
+/// - 放置できる時間やセッションの期限を過ぎたもの、アカウントが無効にされたものは受け付けない
+pub async fn refresh<'a, E>(conn: E, refresh_token: &str) -> Result<TokenPair>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let (prefix, secret) = split_token(refresh_token).ok_or(QrError::Authorized)?;
+    let stored = sqlx::query_as!(
+        StoredRefreshToken,
+        r#"
+    SELECT
+        refresh_token.id,
+        session_id,
+        salt,
+        token_hash,
+        COALESCE(users.role, refresh_token.role) AS "role!",
+        user_id,
+        users.enabled AS "user_enabled?",
+        session_started_at,
+        limit_days,
+        refresh_token.created_at,
+        used_at,
+        revoked_at
+    FROM refresh_token LEFT JOIN users ON refresh_token.user_id = users.id
+    WHERE prefix = $1"#,
+        prefix
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseGet("refresh_token".to_string()))?
+    .ok_or(QrError::Authorized)?;
+    let hash = hash_secret(&stored.salt, secret);
+    if !bool::from(hash.as_bytes().ct_eq(stored.token_hash.as_bytes())) {
+        return Err(QrError::Authorized);
+    }
+    if stored.revoked_at.is_some() {
+        return Err(QrError::Authorized);
+    }
+    if stored.used_at.is_some() {
+        warn!("Reuse of refresh token in session {}", stored.session_id);
+        revoke_session(conn, stored.session_id).await?;
+        return Err(QrError::Authorized);
+    }
+    let now = Utc::now();
+    let idle_limit = stored.created_at + Duration::minutes(idle_minutes(&stored.role));
+    let session_end = stored.session_started_at + Duration::days(stored.limit_days as i64);
+    if idle_limit <= now || session_end <= now || stored.user_enabled == Some(false) {
+        return Err(QrError::Authorized);
+    }
+
+    // 同時に使われた場合に片方だけが通るよう、未使用のときだけ使用済みにする
+    let res = sqlx::query!(
+        "UPDATE refresh_token SET used_at = now() WHERE id = $1 AND used_at IS NULL",
+        stored.id
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("refresh_token".to_string()))?;
+    if res.rows_affected() == 0 {
+        warn!("Reuse of refresh token in session {}", stored.session_id);
+        revoke_session(conn, stored.session_id).await?;
+        return Err(QrError::Authorized);
+    }
+
+    let passtoken = Passtoken::new(stored.role, stored.limit_days as usize);
+    let passtoken = match stored.user_id {
+        Some(user_id) => passtoken.with_user(user_id),
+        None => passtoken,
+    };
+    issue(
+        conn,
+        passtoken,
+        stored.session_id,
+        stored.session_started_at,
+    )
+    .await
+}
+
+/// セッションのリフレッシュトークンとアクセストークンを全て失効させる
+pub async fn revoke_session<'a, E>(conn: E, session_id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    sqlx::query!(
+        "UPDATE refresh_token SET revoked_at = now() WHERE session_id = $1 AND revoked_at IS NULL",
+        session_id
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("refresh_token".to_string()))?;
+    sqlx::query!(
+        "UPDATE passtoken SET revoked_at = now() WHERE session_id = $1 AND revoked_at IS NULL",
+        session_id
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("passtoken".to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::refresh::{refresh, start_session};
+    use crate::authentication::{get_role, revoke_passtoken, Passtoken, Role};
+    use crate::error_handling::QrError;
+    use chrono::{Duration, Utc};
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_refresh_token(pool: Pool<Postgres>) {
+        let pair = start_session(&pool, Passtoken::new(Role::EquipmentManager, 1))
+            .await
+            .unwrap();
+        assert!(pair.expires_at <= Utc::now() + Duration::minutes(15));
+        assert_eq!(
+            get_role(&pool, &pair.token).await,
+            Ok(Role::EquipmentManager)
+        );
+
+        // 使うたびに新しい組に置き換わる
+        let pair2 = refresh(&pool, &pair.refresh_token).await.unwrap();
+        assert_ne!(pair2.refresh_token, pair.refresh_token);
+        assert_eq!(
+            get_role(&pool, &pair2.token).await,
+            Ok(Role::EquipmentManager)
+        );
+        let pair3 = refresh(&pool, &pair2.refresh_token).await.unwrap();
+
+        // 使用済みのものを使うとセッションごと失効する
+        assert_eq!(
+            refresh(&pool, &pair.refresh_token).await,
+            Err(QrError::Authorized)
+        );
+        assert!(get_role(&pool, &pair3.token).await.is_err());
+        assert_eq!(
+            refresh(&pool, &pair3.refresh_token).await,
+            Err(QrError::Authorized)
+        );
+
+        // ログアウトするとリフレッシュトークンも使えなくなる
+        let pair = start_session(&pool, Passtoken::new(Role::General, 1))
+            .await
+            .unwrap();
+        revoke_passtoken(&pool, &pair.token).await.unwrap();
+        assert_eq!(
+            refresh(&pool, &pair.refresh_token).await,
+            Err(QrError::Authorized)
+        );
+
+        // 放置できる時間を過ぎたものは使えない
+        let pair = start_session(&pool, Passtoken::new(Role::General, 1))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE refresh_token SET created_at = now() - interval '1 day'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            refresh(&pool, &pair.refresh_token).await,
+            Err(QrError::Authorized)
+        );
+        assert!(refresh(&pool, "unknown.token").await.is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! 個人ごとのアカウント
+//!
+//! 権限ごとに共有するパスキーの代わりに、一人ひとりに名前とパスワードを割り当てる。
+//! パスワードはargon2でハッシュ化したものだけをデータベースに保存する。
+use crate::authentication::{str_to_role_opt, Role};
+use crate::error_handling::{QrError, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// アカウントの情報
+/// パスワードのハッシュは含めない
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, ToSchema)]
+pub struct User {
+    pub id: Uuid,
+    /// ログインに使う名前
+    pub name: String,
+    /// アカウントに割り当てられた権限
+    pub role: Role,
+    /// 無効にされたアカウントはログインできず、発行済みのトークンも使えなくなる
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// アカウントを作成する時に受け取る情報
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct NewUser {
+    pub name: String,
+    pub password: String,
+    pub role: Role,
+}
+
+/// アカウントを更新する時に受け取る情報
+/// 指定されなかった項目は変更しない
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct UpdateUser {
+    pub id: Uuid,
+    pub password: Option<String>,
+    pub role: Option<Role>,
+    pub enabled: Option<bool>,
+}
+
+/// パスワードをargon2でハッシュ化する
+/// 入力方法による違いが出ないように、NFCで正規化してからハッシュを計算する
+/// 計算に時間がかかるので、他のリクエストを止めないようにブロッキング用のスレッドで行う
+async fn hash_password(password: &str) -> Result<String> {
+    let password = password.nfc().collect::<String>();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| QrError::PasswordHash)
+    })
+    .await
+    .map_err(|_| QrError::PasswordHash)?
+}
+
+/// パスワードがハッシュと一致するかを検査する
+/// ハッシュ化と同じく、ブロッキング用のスレッドで計算する
+async fn verify_password(password: &str, hash: &str) -> bool {
+    let password = password.nfc().collect::<String>();
+    let hash = hash.to_string();
+    tokio::task::spawn_blocking(move || match PasswordHash::new(&hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// ログインに使う名前として使えるかを検査する
+/// 権限の名前は共有パスキーでのトークンの発行に使うので、アカウントの名前には使えない
+fn check_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() || name.contains(':') || str_to_role_opt(name).is_some() {
+        Err(QrError::UserName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// アカウントを作成する
+pub async fn insert_user<'a, E>(conn: E, new_user: &NewUser) -> Result<User>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    check_name(&new_user.name)?;
+    let password_hash = hash_password(&new_user.password).await?;
+    sqlx::query_as!(
+        User,
+        r#"
+    INSERT INTO users (id, name, password_hash, role, created_at)
+    VALUES ( $1, $2, $3, $4, $5 )
+    RETURNING id, name, role, enabled, created_at"#,
+        Uuid::new_v4(),
+        new_user.name,
+        password_hash,
+        new_user.role.to_string(),
+        Utc::now()
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|_| QrError::DatabaseAdd("users".to_string()))
+}
+
+/// アカウントの一覧を作成日時の順に取得する
+pub async fn get_user_list<'a, E>(conn: E) -> Result<Vec<User>>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        User,
+        "SELECT id, name, role, enabled, created_at FROM users ORDER BY created_at"
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("users".to_string()))
+}
+
+/// IDで指定したアカウントを取得する
+pub async fn get_one_user<'a, E>(conn: E, id: Uuid) -> Result<User>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query_as!(
+        User,
+        "SELECT id, name, role, enabled, created_at FROM users WHERE id = $1",
+        id
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("users".to_string()))?
+    .ok_or_else(|| QrError::DatabaseNotFound(id.to_string()))
+}
+
+/// アカウントのパスワードや権限、有効かどうかを更新する
+pub async fn update_user<'a, E>(conn: E, info: &UpdateUser) -> Result<User>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let password_hash = match &info.password {
+        Some(password) => Some(hash_password(password).await?),
+        None => None,
+    };
+    sqlx::query_as!(
+        User,
+        r#"
+    UPDATE users SET
+        password_hash = COALESCE($2, password_hash),
+        role = COALESCE($3, role),
+        enabled = COALESCE($4, enabled)
+    WHERE id = $1
+    RETURNING id, name, role, enabled, created_at"#,
+        info.id,
+        password_hash,
+        info.role.as_ref().map(|r| r.to_string()),
+        info.enabled
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("users".to_string()))?
+    .ok_or_else(|| QrError::DatabaseNotFound(info.id.to_string()))
+}
+
+/// アカウントを削除する
+/// そのアカウントで発行したトークンも一緒に削除される
+pub async fn delete_user<'a, E>(conn: E, id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let res = sqlx::query!("DELETE FROM users WHERE id = $1", id)
+        .execute(conn)
+        .await
+        .map_err(|_| QrError::DatabaseDelete("users".to_string()))?;
+    if res.rows_affected() == 0 {
+        Err(QrError::DatabaseNotFound(id.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// 名前とパスワードを検査し、有効なアカウントであればその情報を返す
+pub async fn authenticate_user<'a, E>(conn: E, name: &str, password: &str) -> Result<User>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let row = sqlx::query!(
+        r#"
+    SELECT id, name, role, enabled, created_at, password_hash
+    FROM users WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("users".to_string()))?;
+    match row {
+        Some(row) if row.enabled && verify_password(password, &row.password_hash).await => {
+            Ok(User {
+                id: row.id,
+                name: row.name,
+                role: row.role.into(),
+                enabled: row.enabled,
+                created_at: row.created_at,
+            })
+        }
+        _ => Err(QrError::Authorized),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::user::{
+        authenticate_user, delete_user, get_user_list, insert_user, update_user, NewUser,
+        UpdateUser,
+    };
+    use crate::authentication::{get_role, insert_passtoken, Passtoken, Role};
+    use crate::error_handling::QrError;
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_user(pool: Pool<Postgres>) {
+        let new_user = NewUser {
+            name: "yamada".to_string(),
+            password: "ﾊﾟｽﾜｰﾄﾞ".to_string(),
+            role: Role::EquipmentManager,
+        };
+        let user = insert_user(&pool, &new_user).await.unwrap();
+        assert!(user.enabled);
+
+        // 権限の名前はアカウントの名前に使えない
+        let res = insert_user(
+            &pool,
+            &NewUser {
+                name: "administrator".to_string(),
+                ..new_user.clone()
+            },
+        )
+        .await;
+        assert_eq!(res, Err(QrError::UserName("administrator".to_string())));
+        assert!(insert_user(&pool, &new_user).await.is_err());
+
+        // パスワードそのものは保存されない
+        let (hash,): (String,) = sqlx::query_as("SELECT password_hash FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(!hash.contains(&new_user.password));
+
+        assert_eq!(
+            authenticate_user(&pool, "yamada", "ﾊﾟｽﾜｰﾄﾞ").await,
+            Ok(user.clone())
+        );
+        assert!(authenticate_user(&pool, "yamada", "wrong").await.is_err());
+        assert!(authenticate_user(&pool, "tanaka", "ﾊﾟｽﾜｰﾄﾞ").await.is_err());
+
+        let passtoken = Passtoken::new(user.role.clone(), 1).with_user(user.id);
+        insert_passtoken(&pool, &passtoken).await.unwrap();
+        assert_eq!(
+            get_role(&pool, &passtoken.token).await,
+            Ok(Role::EquipmentManager)
+        );
+
+        // 権限の変更は発行済みのトークンにも反映される
+        let info = UpdateUser {
+            id: user.id,
+            password: None,
+            role: Some(Role::General),
+            enabled: None,
+        };
+        let updated = update_user(&pool, &info).await.unwrap();
+        assert_eq!(updated.role, Role::General);
+        assert_eq!(get_role(&pool, &passtoken.token).await, Ok(Role::General));
+
+        // 無効にするとログインもトークンの利用もできなくなる
+        let info = UpdateUser {
+            enabled: Some(false),
+            role: None,
+            ..info
+        };
+        update_user(&pool, &info).await.unwrap();
+        assert!(authenticate_user(&pool, "yamada", "ﾊﾟｽﾜｰﾄﾞ").await.is_err());
+        assert!(get_role(&pool, &passtoken.token).await.is_err());
+
+        delete_user(&pool, user.id).await.unwrap();
+        assert!(get_user_list(&pool).await.unwrap().is_empty());
+        assert!(delete_user(&pool, user.id).await.is_err());
+    }
+}
@@ -12,14 +12,22 @@
 use crate::error_handling::{QrError, Result};
 use sqlx::{pool::Pool, postgres::PgPool, Postgres};
 
+/// コンテナの削除を行う関数を提供する
+pub mod delete_container;
 /// 物品削除を行う関数を提供する
 pub mod delete_fixtures;
 /// 場所情報削除を行う関数を提供する
 pub mod delete_spot;
+/// 同義語の削除を行う関数を提供する
+pub mod delete_synonym;
+/// コンテナの一覧を取得する関数を提供する
+pub mod get_container_list;
 /// 物品の一覧を取得する関数を提供する
 pub mod get_fixtures_list;
 /// 貸し出し中の物品の情報を取得する
 pub mod get_lending_list;
+/// コンテナの取得を行う関数を提供する
+pub mod get_one_container;
 /// 物品の取得を行う関数を提供する
 pub mod get_one_fixtures;
 /// 物品の貸し出しについての情報を取得する
@@ -28,6 +36,12 @@ pub mod get_one_lending;
 pub mod get_one_spot;
 /// 地点情報の一覧を取得を行う関数を提供する
 pub mod get_spot_list;
+/// 同義語の一覧を取得する関数を提供する
+pub mod get_synonym_list;
+/// 行の版を取得する関数を提供する
+pub mod get_version;
+/// 冪等キーと最初の応答を保存する関数を提供する
+pub mod idempotency_key;
 /// コンテナの登録を行う関数を提供する
 pub mod insert_container;
 /// 物品登録を行う関数を提供する
@@ -36,8 +50,20 @@ pub mod insert_fixtures;
 pub mod insert_lending;
 /// 地点登録を行う関数を提供する
 pub mod insert_spot;
+/// 同義語の登録を行う関数を提供する
+pub mod insert_synonym;
+/// 物品情報の一部の項目だけを更新する関数を提供する
+pub mod patch_fixtures;
+/// 貸出情報の一部の項目だけを更新する関数を提供する
+pub mod patch_lending;
+/// 地点情報の一部の項目だけを更新する関数を提供する
+pub mod patch_spot;
+/// 期限が切れたトークンなどの古いデータを定期的に削除する
+pub mod purge_expired;
 /// 返却処理を行う関数を提供する
 pub mod returned_lending;
+/// コンテナの情報の更新を行う関数を提供する
+pub mod update_container;
 /// 物品情報の更新をする関数を提供する
 pub mod update_fixtures;
 /// 貸出情報の更新を行う関数を提供する
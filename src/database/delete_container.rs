@@ -0,0 +1,41 @@
+use crate::error_handling::{QrError, Result};
+use uuid::Uuid;
+
+/// コンテナを削除する
+pub async fn delete_container<'a, E>(conn: E, id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query!("DELETE FROM container WHERE id = $1", id)
+        .execute(conn)
+        .await
+        .map_err(|_| QrError::DatabaseDelete("container".to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::delete_container::delete_container;
+    use crate::database::get_container_list::get_container_list;
+    use crate::database::insert_container::insert_container;
+    use sqlx::{pool::Pool, Postgres};
+    use uuid::uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_delete_container(pool: Pool<Postgres>) {
+        let id = uuid!("550e8400-e29b-41d4-a716-446655440000");
+        let info = serde_json::from_value(serde_json::json!({
+          "id": id,
+          "qr_id": "test",
+          "qr_color": "red",
+          "storage": "room101",
+          "description": "test"
+        }))
+        .unwrap();
+        insert_container(&pool, info).await.unwrap();
+
+        delete_container(&pool, id).await.unwrap();
+        assert!(get_container_list(&pool).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+use crate::error_handling::{QrError, Result};
+use uuid::Uuid;
+
+/// 同義語のグループを削除する
+pub async fn delete_synonym<'a, E>(conn: E, id: Uuid) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query!("DELETE FROM search_synonym WHERE id = $1", id)
+        .execute(conn)
+        .await
+        .map_err(|_| QrError::DatabaseDelete("search_synonym".to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::delete_synonym::delete_synonym;
+    use crate::database::get_synonym_list::get_synonym_list;
+    use crate::database::insert_synonym::insert_synonym;
+    use crate::Synonym;
+    use sqlx::{pool::Pool, Postgres};
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_delete_synonym_sql(pool: Pool<Postgres>) {
+        let id = Uuid::new_v4();
+        let info = Synonym {
+            id,
+            words: vec!["プロジェクター".to_string(), "projector".to_string()],
+        };
+        insert_synonym(&pool, info.clone()).await.unwrap();
+        let lst = get_synonym_list(&pool).await.unwrap();
+        assert_eq!(lst, vec![info]);
+
+        delete_synonym(&pool, id).await.unwrap();
+        let lst = get_synonym_list(&pool).await.unwrap();
+        assert!(lst.is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{
+    error_handling::{QrError, Result},
+    Container,
+};
+
+/// コンテナの一覧を取得する
+pub async fn get_container_list<'a, E>(conn: E) -> Result<Vec<Container>>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let list = sqlx::query_as!(
+        Container,
+        "SELECT id, qr_id, qr_color, storage, description FROM container"
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("container".to_string()))?;
+
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::get_container_list::get_container_list;
+    use crate::database::insert_container::insert_container;
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_get_container_list(pool: Pool<Postgres>) {
+        let info = serde_json::from_value(serde_json::json!({
+          "id": "550e8400-e29b-41d4-a716-446655440000",
+          "qr_id": "test",
+          "qr_color": "red",
+          "storage": "room101",
+          "description": "test"
+        }))
+        .unwrap();
+        insert_container(&pool, info).await.unwrap();
+
+        let result = get_container_list(&pool).await.unwrap();
+        assert_eq!(result.len(), 1);
+    }
+}
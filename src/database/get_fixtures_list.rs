@@ -7,9 +7,17 @@ pub async fn get_fixtures_list<'a, E>(conn: E) -> Result<Vec<Fixtures>>
 where
     E: sqlx::Executor<'a, Database = sqlx::Postgres>,
 {
-    let fixtures_lst = sqlx::query_as!(Fixtures, "SELECT * FROM fixtures")
-        .fetch_all(conn)
-        .await
-        .map_err(|_| QrError::DatabaseGet("fixtures".to_string()))?;
+    let fixtures_lst = sqlx::query_as!(
+        Fixtures,
+        r#"
+    SELECT
+        id, created_at, qr_id, qr_color, name, description,
+        model_number, storage, usage, usage_season, note, parent_id
+    FROM fixtures
+    "#
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("fixtures".to_string()))?;
     Ok(fixtures_lst)
 }
@@ -8,10 +8,18 @@ pub async fn get_lending_list<'a, E>(conn: E) -> Result<Vec<Lending>>
 where
     E: sqlx::Executor<'a, Database = sqlx::Postgres>,
 {
-    let list = sqlx::query_as!(Lending, "SELECT * FROM lending WHERE returned_at IS NULL")
-        .fetch_all(conn)
-        .await
-        .map_err(|_| QrError::DatabaseGet("lending".to_string()))?;
+    let list = sqlx::query_as!(
+        Lending,
+        r#"
+    SELECT
+        id, fixtures_id, fixtures_qr_id, spot_name, lending_at,
+        returned_at, borrower_name, borrower_number, borrower_org
+    FROM lending WHERE returned_at IS NULL
+    "#
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("lending".to_string()))?;
 
     Ok(list)
 }
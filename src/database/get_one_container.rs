@@ -0,0 +1,80 @@
+use crate::{
+    error_handling::{QrError, Result},
+    Container,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdType {
+    ContainerId(Uuid),
+    QrId(String),
+}
+
+/// コンテナをIDかQRコードのIDで取得する
+pub async fn get_one_container<'a, E>(conn: E, id: IdType) -> Result<Container>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let container_opt = match &id {
+        IdType::ContainerId(id) => {
+            sqlx::query_as!(
+                Container,
+                "SELECT id, qr_id, qr_color, storage, description FROM container WHERE id = $1",
+                id
+            )
+            .fetch_optional(conn)
+            .await
+        }
+        IdType::QrId(qr_id) => {
+            sqlx::query_as!(
+                Container,
+                "SELECT id, qr_id, qr_color, storage, description FROM container WHERE qr_id = $1",
+                qr_id
+            )
+            .fetch_optional(conn)
+            .await
+        }
+    }
+    .map_err(|_| QrError::DatabaseGet("container".to_string()))?;
+    match (container_opt, id) {
+        (Some(container), _) => Ok(container),
+        (None, IdType::ContainerId(id)) => Err(QrError::DatabaseNotFound(id.to_string())),
+        (None, IdType::QrId(qr_id)) => Err(QrError::DatabaseNotFound(qr_id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::get_one_container::{get_one_container, IdType::*};
+    use crate::database::insert_container::insert_container;
+    use crate::Container;
+    use sqlx::{pool::Pool, Postgres};
+    use uuid::uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_get_one_container(pool: Pool<Postgres>) {
+        let id = uuid!("550e8400-e29b-41d4-a716-446655440000");
+        let info: Container = serde_json::from_value(serde_json::json!({
+          "id": id,
+          "qr_id": "test",
+          "qr_color": "red",
+          "storage": "room101",
+          "description": "test"
+        }))
+        .unwrap();
+        insert_container(&pool, info.clone()).await.unwrap();
+
+        assert_eq!(
+            get_one_container(&pool, ContainerId(id)).await,
+            Ok(info.clone())
+        );
+        assert_eq!(
+            get_one_container(&pool, QrId("test".to_string())).await,
+            Ok(info)
+        );
+        assert!(get_one_container(&pool, QrId("unknown".to_string()))
+            .await
+            .is_err());
+    }
+}
@@ -17,11 +17,19 @@ where
 {
     match id {
         IdType::FixturesId(id) => {
-            let fixtures_opt =
-                sqlx::query_as!(Fixtures, "SELECT * FROM fixtures WHERE id = $1", id)
-                    .fetch_optional(conn)
-                    .await
-                    .map_err(|_| QrError::DatabaseGet("fixtures".to_string()))?;
+            let fixtures_opt = sqlx::query_as!(
+                Fixtures,
+                r#"
+    SELECT
+        id, created_at, qr_id, qr_color, name, description,
+        model_number, storage, usage, usage_season, note, parent_id
+    FROM fixtures WHERE id = $1
+    "#,
+                id
+            )
+            .fetch_optional(conn)
+            .await
+            .map_err(|_| QrError::DatabaseGet("fixtures".to_string()))?;
             if let Some(fixtures) = fixtures_opt {
                 Ok(fixtures)
             } else {
@@ -29,11 +37,19 @@ where
             }
         }
         IdType::QrId(id) => {
-            let fixtures_opt =
-                sqlx::query_as!(Fixtures, "SELECT * FROM fixtures WHERE qr_id = $1", id)
-                    .fetch_optional(conn)
-                    .await
-                    .map_err(|_| QrError::DatabaseGet("fixtures".to_string()))?;
+            let fixtures_opt = sqlx::query_as!(
+                Fixtures,
+                r#"
+    SELECT
+        id, created_at, qr_id, qr_color, name, description,
+        model_number, storage, usage, usage_season, note, parent_id
+    FROM fixtures WHERE qr_id = $1
+    "#,
+                id
+            )
+            .fetch_optional(conn)
+            .await
+            .map_err(|_| QrError::DatabaseGet("fixtures".to_string()))?;
             if let Some(fixtures) = fixtures_opt {
                 Ok(fixtures)
             } else {
@@ -20,7 +20,12 @@ where
         IdType::LendingId(id) => {
             let lending_opt = sqlx::query_as!(
                 Lending,
-                "SELECT * FROM lending WHERE id = $1 AND returned_at IS NULL",
+                r#"
+    SELECT
+        id, fixtures_id, fixtures_qr_id, spot_name, lending_at,
+        returned_at, borrower_name, borrower_number, borrower_org
+    FROM lending WHERE id = $1 AND returned_at IS NULL
+    "#,
                 id
             )
             .fetch_optional(conn)
@@ -35,7 +40,12 @@ where
         IdType::FixturesId(id) => {
             let lending_opt = sqlx::query_as!(
                 Lending,
-                "SELECT * FROM lending WHERE fixtures_id = $1 AND returned_at IS NULL",
+                r#"
+    SELECT
+        id, fixtures_id, fixtures_qr_id, spot_name, lending_at,
+        returned_at, borrower_name, borrower_number, borrower_org
+    FROM lending WHERE fixtures_id = $1 AND returned_at IS NULL
+    "#,
                 id
             )
             .fetch_optional(conn)
@@ -50,7 +60,12 @@ where
         IdType::QrId(id) => {
             let lending_opt = sqlx::query_as!(
                 Lending,
-                "SELECT * FROM lending WHERE fixtures_qr_id = $1 AND returned_at IS NULL",
+                r#"
+    SELECT
+        id, fixtures_id, fixtures_qr_id, spot_name, lending_at,
+        returned_at, borrower_name, borrower_number, borrower_org
+    FROM lending WHERE fixtures_qr_id = $1 AND returned_at IS NULL
+    "#,
                 id
             )
             .fetch_optional(conn)
@@ -7,10 +7,14 @@ pub async fn get_one_spot<'a, E>(conn: E, name: &str) -> Result<Spot>
 where
     E: sqlx::Executor<'a, Database = sqlx::Postgres>,
 {
-    let spot_opt = sqlx::query_as!(Spot, "SELECT * FROM spot WHERE name = $1", name)
-        .fetch_optional(conn)
-        .await
-        .map_err(|_| QrError::DatabaseGet("spot".to_string()))?;
+    let spot_opt = sqlx::query_as!(
+        Spot,
+        "SELECT name, area, building, floor, room, note FROM spot WHERE name = $1",
+        name
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("spot".to_string()))?;
     if let Some(spot) = spot_opt {
         Ok(spot)
     } else {
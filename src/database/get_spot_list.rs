@@ -7,10 +7,13 @@ pub async fn get_spot_list<'a, E>(conn: E) -> Result<Vec<Spot>>
 where
     E: sqlx::Executor<'a, Database = sqlx::Postgres>,
 {
-    let spot_opt = sqlx::query_as!(Spot, "SELECT * FROM spot")
-        .fetch_all(conn)
-        .await
-        .map_err(|_| QrError::DatabaseGet("spot".to_string()))?;
+    let spot_opt = sqlx::query_as!(
+        Spot,
+        "SELECT name, area, building, floor, room, note FROM spot"
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("spot".to_string()))?;
 
     Ok(spot_opt)
 }
@@ -0,0 +1,17 @@
+use crate::{
+    error_handling::{QrError, Result},
+    Synonym,
+};
+
+/// 登録されている同義語のグループを全て取得する
+pub async fn get_synonym_list<'a, E>(conn: E) -> Result<Vec<Synonym>>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let lst = sqlx::query_as!(Synonym, "SELECT * FROM search_synonym")
+        .fetch_all(conn)
+        .await
+        .map_err(|_| QrError::DatabaseGet("search_synonym".to_string()))?;
+
+    Ok(lst)
+}
@@ -0,0 +1,120 @@
+use crate::error_handling::{QrError, Result};
+use uuid::Uuid;
+
+/// 版を取得する行の指定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionKey {
+    FixturesId(Uuid),
+    FixturesQrId(String),
+    LendingId(Uuid),
+    Spot(String),
+    ContainerId(Uuid),
+    ContainerQrId(String),
+}
+
+impl std::fmt::Display for VersionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VersionKey::FixturesId(id)
+            | VersionKey::LendingId(id)
+            | VersionKey::ContainerId(id) => {
+                write!(f, "{id}")
+            }
+            VersionKey::FixturesQrId(s) | VersionKey::Spot(s) | VersionKey::ContainerQrId(s) => {
+                write!(f, "{s}")
+            }
+        }
+    }
+}
+
+/// 行の現在の版を取得する
+/// 更新するたびに一つずつ増える
+pub async fn get_version<'a, E>(conn: E, key: &VersionKey) -> Result<i64>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let version = match key {
+        VersionKey::FixturesId(id) => {
+            sqlx::query_scalar!("SELECT version FROM fixtures WHERE id = $1", id)
+                .fetch_optional(conn)
+                .await
+        }
+        VersionKey::FixturesQrId(qr_id) => {
+            sqlx::query_scalar!("SELECT version FROM fixtures WHERE qr_id = $1", qr_id)
+                .fetch_optional(conn)
+                .await
+        }
+        VersionKey::LendingId(id) => {
+            sqlx::query_scalar!("SELECT version FROM lending WHERE id = $1", id)
+                .fetch_optional(conn)
+                .await
+        }
+        VersionKey::Spot(name) => {
+            sqlx::query_scalar!("SELECT version FROM spot WHERE name = $1", name)
+                .fetch_optional(conn)
+                .await
+        }
+        VersionKey::ContainerId(id) => {
+            sqlx::query_scalar!("SELECT version FROM container WHERE id = $1", id)
+                .fetch_optional(conn)
+                .await
+        }
+        VersionKey::ContainerQrId(qr_id) => {
+            sqlx::query_scalar!("SELECT version FROM container WHERE qr_id = $1", qr_id)
+                .fetch_optional(conn)
+                .await
+        }
+    };
+    version
+        .map_err(|_| QrError::DatabaseGet("version".to_string()))?
+        .ok_or_else(|| QrError::DatabaseNotFound(key.to_string()))
+}
+
+/// 版を指定した更新で対象の行が無かった時のエラーを返す
+/// 行はあるが版が違う場合は`QrError::PreconditionFailed`、行が無い場合は`QrError::DatabaseNotFound`
+pub async fn not_updated<'a, E>(conn: E, key: &VersionKey) -> QrError
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    match get_version(conn, key).await {
+        Ok(_) => QrError::PreconditionFailed,
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::get_version::{get_version, VersionKey};
+    use crate::database::insert_spot::insert_spot;
+    use crate::database::update_spot::update_spot;
+    use crate::error_handling::QrError;
+    use crate::{Area, Spot};
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_get_version(pool: Pool<Postgres>) {
+        let info = Spot {
+            name: "test".to_string(),
+            area: Area::Area3,
+            building: None,
+            floor: None,
+            room: None,
+            note: None,
+        };
+        insert_spot(&pool, info.clone()).await.unwrap();
+        let key = VersionKey::Spot("test".to_string());
+        assert_eq!(get_version(&pool, &key).await, Ok(1));
+
+        // 更新するたびに版が増え、古い版を指定した更新は失敗する
+        assert_eq!(update_spot(&pool, info.clone(), Some(1)).await, Ok(2));
+        assert_eq!(get_version(&pool, &key).await, Ok(2));
+        assert_eq!(
+            update_spot(&pool, info.clone(), Some(1)).await,
+            Err(QrError::PreconditionFailed)
+        );
+        assert_eq!(update_spot(&pool, info, None).await, Ok(3));
+        assert!(get_version(&pool, &VersionKey::Spot("unknown".to_string()))
+            .await
+            .is_err());
+    }
+}
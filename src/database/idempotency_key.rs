@@ -0,0 +1,208 @@
+use crate::authentication::env_or;
+use crate::error_handling::{QrError, Result};
+use chrono::{DateTime, Duration, Utc};
+
+/// 冪等キーを覚えておく時間のデフォルト
+pub const DEFAULT_IDEMPOTENCY_KEY_HOURS: i64 = 24;
+
+/// 処理中のまま残った予約を放棄されたものとみなすまでの秒数
+/// 処理の途中でサーバーが停止した場合でも、再送できなくなり続けることが無いようにする
+pub const IN_PROGRESS_TIMEOUT_SECONDS: i64 = 60;
+
+/// 環境変数`IDEMPOTENCY_KEY_HOURS`から冪等キーを覚えておく時間を読み込む
+/// この時間の間に同じキーで再送されたリクエストには最初の応答を返す
+pub fn idempotency_key_hours() -> i64 {
+    env_or("IDEMPOTENCY_KEY_HOURS", DEFAULT_IDEMPOTENCY_KEY_HOURS)
+}
+
+/// 保存した最初の応答
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    pub status_code: i32,
+    /// `Content-Type`や`ETag`などの再送時にも返すヘッダー
+    pub headers: serde_json::Value,
+    pub body: Vec<u8>,
+}
+
+/// 冪等キーを予約した結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reservation {
+    /// 初めて使われたキーなので、リクエストを処理する
+    Reserved,
+    /// 同じキーのリクエストを処理している途中
+    InProgress,
+    /// 同じキーで内容の違うリクエストが送られた
+    Mismatch,
+    /// 同じリクエストを処理済みなので、保存した応答を返す
+    Completed(StoredResponse),
+}
+
+/// 冪等キーを予約する
+/// キーは呼び出した人（`caller`）ごとに分け、他の人が同じキーを使っていても別のキーとして扱う
+/// `window`より前に使われたキーは期限切れとして削除し、新しく予約し直す
+pub async fn reserve<'a, E>(
+    conn: E,
+    caller: &str,
+    key: &str,
+    request_hash: &str,
+    now: DateTime<Utc>,
+    window: Duration,
+) -> Result<Reservation>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    sqlx::query!(
+        r#"
+    DELETE FROM idempotency_key
+    WHERE caller = $1
+        AND key = $2
+        AND (created_at < $3 OR (status_code IS NULL AND created_at < $4))"#,
+        caller,
+        key,
+        now - window,
+        now - Duration::seconds(IN_PROGRESS_TIMEOUT_SECONDS)
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseDelete("idempotency_key".to_string()))?;
+
+    let inserted = sqlx::query!(
+        r#"
+    INSERT INTO idempotency_key (caller, key, request_hash, created_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (caller, key) DO NOTHING"#,
+        caller,
+        key,
+        request_hash,
+        now
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseAdd("idempotency_key".to_string()))?
+    .rows_affected();
+    if inserted == 1 {
+        return Ok(Reservation::Reserved);
+    }
+
+    let row = sqlx::query!(
+        r#"
+    SELECT request_hash, status_code, headers, body
+    FROM idempotency_key
+    WHERE caller = $1 AND key = $2"#,
+        caller,
+        key
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|_| QrError::DatabaseGet("idempotency_key".to_string()))?
+    .ok_or_else(|| QrError::DatabaseNotFound(key.to_string()))?;
+    if row.request_hash != request_hash {
+        return Ok(Reservation::Mismatch);
+    }
+    match row.status_code {
+        Some(status_code) => Ok(Reservation::Completed(StoredResponse {
+            status_code,
+            headers: row.headers.unwrap_or_default(),
+            body: row.body.unwrap_or_default(),
+        })),
+        None => Ok(Reservation::InProgress),
+    }
+}
+
+/// 予約したキーに最初の応答を保存する
+pub async fn complete<'a, E>(
+    conn: E,
+    caller: &str,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        r#"
+    UPDATE idempotency_key SET status_code = $3, headers = $4, body = $5
+    WHERE caller = $1 AND key = $2"#,
+        caller,
+        key,
+        response.status_code,
+        response.headers,
+        response.body
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("idempotency_key".to_string()))?;
+    Ok(())
+}
+
+/// 予約したキーを取り消す
+/// サーバー側の失敗などで応答を保存しない場合に、同じキーで再送できるようにする
+pub async fn release<'a, E>(conn: E, caller: &str, key: &str) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        "DELETE FROM idempotency_key WHERE caller = $1 AND key = $2",
+        caller,
+        key
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseDelete("idempotency_key".to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::idempotency_key::{complete, reserve, Reservation, StoredResponse};
+    use chrono::{Duration, Utc};
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_reserve(pool: Pool<Postgres>) {
+        let now = Utc::now();
+        let window = Duration::hours(24);
+        assert_eq!(
+            reserve(&pool, "token:1", "key", "hash", now, window).await,
+            Ok(Reservation::Reserved)
+        );
+        assert_eq!(
+            reserve(&pool, "token:1", "key", "hash", now, window).await,
+            Ok(Reservation::InProgress)
+        );
+        // 他の人が同じキーを使っても別のキーになる
+        assert_eq!(
+            reserve(&pool, "token:2", "key", "other", now, window).await,
+            Ok(Reservation::Reserved)
+        );
+
+        let response = StoredResponse {
+            status_code: 200,
+            headers: serde_json::json!({"content-type": "application/json"}),
+            body: b"{}".to_vec(),
+        };
+        complete(&pool, "token:1", "key", &response).await.unwrap();
+        assert_eq!(
+            reserve(&pool, "token:1", "key", "hash", now, window).await,
+            Ok(Reservation::Completed(response))
+        );
+        assert_eq!(
+            reserve(&pool, "token:1", "key", "other", now, window).await,
+            Ok(Reservation::Mismatch)
+        );
+
+        // 期限が過ぎたキーは新しく予約できる
+        assert_eq!(
+            reserve(
+                &pool,
+                "token:1",
+                "key",
+                "other",
+                now + Duration::hours(25),
+                window
+            )
+            .await,
+            Ok(Reservation::Reserved)
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{
+    error_handling::{QrError, Result},
+    Synonym,
+};
+
+/// 同義語のグループを登録する
+pub async fn insert_synonym<'a, E>(conn: E, info: Synonym) -> Result<()>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let Synonym { id, words } = info;
+
+    sqlx::query!(
+        "INSERT INTO search_synonym (id, words) VALUES ( $1, $2 )",
+        id,
+        &words
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseAdd("search_synonym".to_string()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::insert_synonym::insert_synonym;
+    use crate::Synonym;
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_insert_synonym_sql(pool: Pool<Postgres>) {
+        let info: Synonym = serde_json::from_value(serde_json::json!({
+          "id": "550e8400-e29b-41d4-a716-446655440000",
+          "words": ["プロジェクター", "projector"]
+        }))
+        .unwrap();
+        let res = insert_synonym(&pool, info).await;
+        assert!(res.is_ok());
+    }
+}
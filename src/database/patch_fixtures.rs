@@ -0,0 +1,142 @@
+use crate::{
+    database::get_version::{not_updated, VersionKey},
+    error_handling::{QrError, Result},
+    patch::{nullable_param, FixturesPatch},
+    Fixtures,
+};
+use uuid::Uuid;
+
+/// 物品情報のうち、指定された項目だけを更新する
+/// 読み込んでから書き戻すのではなく一つのUPDATEで行うので、同時に別の項目を更新しても上書きし合わない
+/// `expected_version`を指定した場合は版が一致する時だけ更新する
+/// 更新後の物品情報と版を返す
+pub async fn patch_fixtures<'a, E>(
+    conn: E,
+    id: Uuid,
+    patch: &FixturesPatch,
+    expected_version: Option<i64>,
+) -> Result<(Fixtures, i64)>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let (set_description, description) = nullable_param(&patch.description);
+    let (set_model_number, model_number) = nullable_param(&patch.model_number);
+    let (set_usage, usage) = nullable_param(&patch.usage);
+    let (set_usage_season, usage_season) = nullable_param(&patch.usage_season);
+    let row = sqlx::query!(
+        r#"
+    UPDATE fixtures SET
+        qr_id = COALESCE($2, qr_id),
+        qr_color = COALESCE($3, qr_color),
+        name = COALESCE($4, name),
+        description = CASE WHEN $5 THEN $6 ELSE description END,
+        model_number = CASE WHEN $7 THEN $8 ELSE model_number END,
+        storage = COALESCE($9, storage),
+        usage = CASE WHEN $10 THEN $11 ELSE usage END,
+        usage_season = CASE WHEN $12 THEN $13 ELSE usage_season END,
+        note = COALESCE($14, note),
+        parent_id = COALESCE($15, parent_id)
+    WHERE id = $1 AND ($16::bigint IS NULL OR version = $16)
+    RETURNING
+        id, created_at, qr_id, qr_color, name, description, model_number,
+        storage, usage, usage_season, note, parent_id, version"#,
+        id,
+        patch.qr_id,
+        patch.qr_color.as_ref().map(|c| c.to_string()),
+        patch.name,
+        set_description,
+        description,
+        set_model_number,
+        model_number,
+        patch.storage.as_ref().map(|s| s.to_string()),
+        set_usage,
+        usage,
+        set_usage_season,
+        usage_season,
+        patch.note,
+        patch.parent_id,
+        expected_version
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("fixtures".to_string()))?;
+    match row {
+        Some(row) => Ok((
+            Fixtures {
+                id: row.id,
+                created_at: row.created_at,
+                qr_id: row.qr_id,
+                qr_color: row.qr_color.into(),
+                name: row.name,
+                description: row.description,
+                model_number: row.model_number,
+                storage: row.storage.into(),
+                usage: row.usage,
+                usage_season: row.usage_season,
+                note: row.note,
+                parent_id: row.parent_id,
+            },
+            row.version,
+        )),
+        None => Err(not_updated(conn, &VersionKey::FixturesId(id)).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::insert_fixtures::insert_fixtures;
+    use crate::database::patch_fixtures::patch_fixtures;
+    use crate::error_handling::QrError;
+    use crate::patch::FixturesPatch;
+    use crate::Fixtures;
+    use sqlx::{pool::Pool, Postgres};
+    use uuid::uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_patch_fixtures(pool: Pool<Postgres>) {
+        let uuid = uuid!("550e8400-e29b-41d4-a716-446655440000");
+        let info: Fixtures = serde_json::from_value(serde_json::json!({
+          "id": uuid,
+          "qr_id": "test",
+          "created_at": "2023-08-07 15:56:35 UTC",
+          "qr_color":"red",
+          "name":"テスト物品",
+          "description":"テスト説明",
+          "storage": "room101",
+          "usage": "無い",
+          "note": "DBを確認",
+          "parent_id": "null"
+        }))
+        .unwrap();
+        insert_fixtures(&pool, info.clone()).await.unwrap();
+
+        // 別々の項目の更新は上書きし合わない
+        let patch = FixturesPatch {
+            name: Some("ドラム".to_string()),
+            ..Default::default()
+        };
+        patch_fixtures(&pool, uuid, &patch, None).await.unwrap();
+        let patch = FixturesPatch {
+            description: Some(None),
+            usage: Some(Some("演奏".to_string())),
+            ..Default::default()
+        };
+        let (result, version) = patch_fixtures(&pool, uuid, &patch, Some(2)).await.unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(
+            result,
+            Fixtures {
+                name: "ドラム".to_string(),
+                description: None,
+                usage: Some("演奏".to_string()),
+                ..info
+            }
+        );
+
+        let unknown = uuid!("550e8400-e29b-41d4-a716-446655440001");
+        assert_eq!(
+            patch_fixtures(&pool, unknown, &patch, None).await,
+            Err(QrError::DatabaseNotFound(unknown.to_string()))
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{
+    database::get_version::{not_updated, VersionKey},
+    error_handling::{QrError, Result},
+    patch::{nullable_param, LendingPatch},
+    Lending,
+};
+use uuid::Uuid;
+
+/// 貸出情報のうち、指定された項目だけを更新する
+/// `expected_version`を指定した場合は版が一致する時だけ更新する
+/// 更新後の貸出情報と版を返す
+pub async fn patch_lending<'a, E>(
+    conn: E,
+    id: Uuid,
+    patch: &LendingPatch,
+    expected_version: Option<i64>,
+) -> Result<(Lending, i64)>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let (set_returned_at, returned_at) = nullable_param(&patch.returned_at);
+    let (set_borrower_org, borrower_org) = nullable_param(&patch.borrower_org);
+    let row = sqlx::query!(
+        r#"
+    UPDATE lending SET
+        fixtures_id = COALESCE($2, fixtures_id),
+        fixtures_qr_id = COALESCE($3, fixtures_qr_id),
+        spot_name = COALESCE($4, spot_name),
+        returned_at = CASE WHEN $5 THEN $6 ELSE returned_at END,
+        borrower_name = COALESCE($7, borrower_name),
+        borrower_number = COALESCE($8, borrower_number),
+        borrower_org = CASE WHEN $9 THEN $10 ELSE borrower_org END
+    WHERE id = $1 AND ($11::bigint IS NULL OR version = $11)
+    RETURNING
+        id, fixtures_id, fixtures_qr_id, spot_name, lending_at, returned_at,
+        borrower_name, borrower_number, borrower_org, version"#,
+        id,
+        patch.fixtures_id,
+        patch.fixtures_qr_id,
+        patch.spot_name,
+        set_returned_at,
+        returned_at,
+        patch.borrower_name,
+        patch.borrower_number,
+        set_borrower_org,
+        borrower_org,
+        expected_version
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("lending".to_string()))?;
+    match row {
+        Some(row) => Ok((
+            Lending {
+                id: row.id,
+                fixtures_id: row.fixtures_id,
+                fixtures_qr_id: row.fixtures_qr_id,
+                spot_name: row.spot_name,
+                lending_at: row.lending_at,
+                returned_at: row.returned_at,
+                borrower_name: row.borrower_name,
+                borrower_number: row.borrower_number,
+                borrower_org: row.borrower_org,
+            },
+            row.version,
+        )),
+        None => Err(not_updated(conn, &VersionKey::LendingId(id)).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::insert_lending::insert_lending;
+    use crate::database::patch_lending::patch_lending;
+    use crate::patch::LendingPatch;
+    use sqlx::{pool::Pool, Postgres};
+    use uuid::uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_patch_lending(pool: Pool<Postgres>) {
+        let id = uuid!("550e8400-e29b-41d4-a716-446655440000");
+        let info = serde_json::from_value(serde_json::json!({
+          "id": id,
+          "fixtures_id": "550e8400-e29b-41d4-a716-446655440001",
+          "fixtures_qr_id": "x234",
+          "spot_name": "test",
+          "lending_at": "2023-08-07 15:56:35 UTC",
+          "borrower_name": "test",
+          "borrower_number": 202200000,
+          "borrower_org": "jsys"
+        }))
+        .unwrap();
+        insert_lending(&pool, info).await.unwrap();
+
+        let patch = LendingPatch {
+            spot_name: Some("test2".to_string()),
+            borrower_org: Some(None),
+            ..Default::default()
+        };
+        let (result, _) = patch_lending(&pool, id, &patch, None).await.unwrap();
+        assert_eq!(result.spot_name, "test2");
+        assert_eq!(result.borrower_org, None);
+        assert_eq!(result.borrower_name, "test");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{
+    database::get_version::{not_updated, VersionKey},
+    error_handling::{QrError, Result},
+    patch::{nullable_param, SpotPatch},
+    Spot,
+};
+
+/// 地点情報のうち、指定された項目だけを更新する
+/// `expected_version`を指定した場合は版が一致する時だけ更新する
+/// 更新後の地点情報と版を返す
+pub async fn patch_spot<'a, E>(
+    conn: E,
+    name: &str,
+    patch: &SpotPatch,
+    expected_version: Option<i64>,
+) -> Result<(Spot, i64)>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let (set_building, building) = nullable_param(&patch.building);
+    let (set_floor, floor) = nullable_param(&patch.floor);
+    let (set_room, room) = nullable_param(&patch.room);
+    let (set_note, note) = nullable_param(&patch.note);
+    let row = sqlx::query!(
+        r#"
+    UPDATE spot SET
+        area = COALESCE($2, area),
+        building = CASE WHEN $3 THEN $4 ELSE building END,
+        floor = CASE WHEN $5 THEN $6 ELSE floor END,
+        room = CASE WHEN $7 THEN $8 ELSE room END,
+        note = CASE WHEN $9 THEN $10 ELSE note END
+    WHERE name = $1 AND ($11::bigint IS NULL OR version = $11)
+    RETURNING name, area, building, floor, room, note, version"#,
+        name,
+        patch.area.as_ref().map(|a| a.to_string()),
+        set_building,
+        building,
+        set_floor,
+        floor,
+        set_room,
+        room,
+        set_note,
+        note,
+        expected_version
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("spot".to_string()))?;
+    match row {
+        Some(row) => Ok((
+            Spot {
+                name: row.name,
+                area: row.area.into(),
+                building: row.building,
+                floor: row.floor,
+                room: row.room,
+                note: row.note,
+            },
+            row.version,
+        )),
+        None => Err(not_updated(conn, &VersionKey::Spot(name.to_string())).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::insert_spot::insert_spot;
+    use crate::database::patch_spot::patch_spot;
+    use crate::patch::SpotPatch;
+    use crate::{Area, Spot};
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_patch_spot(pool: Pool<Postgres>) {
+        let info = Spot {
+            name: "test".to_string(),
+            area: Area::Area3,
+            building: Some("3C".to_string()),
+            floor: Some(2),
+            room: Some("coinsラウンジ".to_string()),
+            note: None,
+        };
+        insert_spot(&pool, info).await.unwrap();
+
+        let patch = SpotPatch {
+            floor: Some(None),
+            note: Some(Some("机あり".to_string())),
+            ..Default::default()
+        };
+        let (result, _) = patch_spot(&pool, "test", &patch, None).await.unwrap();
+        assert_eq!(result.floor, None);
+        assert_eq!(result.note, Some("机あり".to_string()));
+        assert_eq!(result.building, Some("3C".to_string()));
+        assert!(patch_spot(&pool, "unknown", &patch, None).await.is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! 期限が切れたトークンなどの古いデータを定期的に削除する
+//!
+//! `/gen_passtoken`のたびに行が増えるので、放っておくとテーブルが大きくなり続ける。
+//! 保持期間を過ぎた行をまとめて削除し、削除した件数をログと`/get_purge_report`で確認できるようにする。
+use crate::authentication::{env_or, oidc::LOGIN_LIMIT_MINUTES};
+use crate::database::idempotency_key::{idempotency_key_hours, DEFAULT_IDEMPOTENCY_KEY_HOURS};
+use crate::error_handling::{QrError, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{pool::Pool, postgres::Postgres};
+use std::sync::{Arc, Mutex};
+use tracing::*;
+use utoipa::ToSchema;
+
+/// 古いデータを削除する間隔と保持期間
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// 削除を行う間隔（分）
+    pub interval_minutes: i64,
+    /// 期限切れや失効したトークンとリフレッシュトークンを残しておく日数
+    pub token_retention_days: i64,
+    /// 失敗したログインの試行の記録を残しておく日数
+    pub login_failure_retention_days: i64,
+    /// 冪等キーと保存した応答を残しておく時間
+    pub idempotency_key_hours: i64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            interval_minutes: 60,
+            token_retention_days: 7,
+            login_failure_retention_days: 30,
+            idempotency_key_hours: DEFAULT_IDEMPOTENCY_KEY_HOURS,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// 環境変数から読み込む
+    /// - `PURGE_INTERVAL_MINUTES`
+    /// - `TOKEN_RETENTION_DAYS`
+    /// - `LOGIN_FAILURE_RETENTION_DAYS`
+    /// - `IDEMPOTENCY_KEY_HOURS`
+    pub fn from_env() -> Self {
+        let default = RetentionPolicy::default();
+        RetentionPolicy {
+            interval_minutes: env_or("PURGE_INTERVAL_MINUTES", default.interval_minutes),
+            token_retention_days: env_or("TOKEN_RETENTION_DAYS", default.token_retention_days),
+            login_failure_retention_days: env_or(
+                "LOGIN_FAILURE_RETENTION_DAYS",
+                default.login_failure_retention_days,
+            ),
+            idempotency_key_hours: idempotency_key_hours(),
+        }
+    }
+}
+
+/// テーブルごとの削除した行の数
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct PurgeCounts {
+    pub passtoken: u64,
+    pub refresh_token: u64,
+    pub oidc_login: u64,
+    pub login_failure: u64,
+    pub login_lockout: u64,
+    pub idempotency_key: u64,
+}
+
+impl PurgeCounts {
+    const ZERO: PurgeCounts = PurgeCounts {
+        passtoken: 0,
+        refresh_token: 0,
+        oidc_login: 0,
+        login_failure: 0,
+        login_lockout: 0,
+        idempotency_key: 0,
+    };
+
+    pub fn total(&self) -> u64 {
+        self.passtoken
+            + self.refresh_token
+            + self.oidc_login
+            + self.login_failure
+            + self.login_lockout
+            + self.idempotency_key
+    }
+
+    fn add(&mut self, other: &PurgeCounts) {
+        self.passtoken += other.passtoken;
+        self.refresh_token += other.refresh_token;
+        self.oidc_login += other.oidc_login;
+        self.login_failure += other.login_failure;
+        self.login_lockout += other.login_lockout;
+        self.idempotency_key += other.idempotency_key;
+    }
+}
+
+/// 削除の実行結果の集計
+/// サーバーを起動してからの累計と、最後に実行した時の結果を持つ
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct PurgeReport {
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last: PurgeCounts,
+    pub total: PurgeCounts,
+    /// 失敗した回数
+    pub failures: u64,
+}
+
+static REPORT: Mutex<PurgeReport> = Mutex::new(PurgeReport {
+    last_run_at: None,
+    last: PurgeCounts::ZERO,
+    total: PurgeCounts::ZERO,
+    failures: 0,
+});
+
+/// これまでの削除の実行結果を取得する
+pub fn purge_report() -> PurgeReport {
+    REPORT.lock().unwrap().clone()
+}
+
+/// 保持期間を過ぎたデータを削除し、削除した行の数を返す
+pub async fn purge_expired<'a, E>(conn: E, policy: &RetentionPolicy) -> Result<PurgeCounts>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let now = Utc::now();
+    let token_cutoff = now - Duration::days(policy.token_retention_days);
+
+    let passtoken = sqlx::query!(
+        r#"
+    DELETE FROM passtoken
+    WHERE revoked_at < $1
+        OR COALESCE(expires_at, created_at + make_interval(days => limit_days)) < $1"#,
+        token_cutoff
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseDelete("passtoken".to_string()))?
+    .rows_affected();
+
+    // 使用済みのものは再利用の検知に使うので、セッションが続いている間は残しておく
+    let refresh_token = sqlx::query!(
+        r#"
+    DELETE FROM refresh_token
+    WHERE revoked_at < $1
+        OR session_started_at + make_interval(days => limit_days) < $1"#,
+        token_cutoff
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseDelete("refresh_token".to_string()))?
+    .rows_affected();
+
+    let oidc_login = sqlx::query!(
+        "DELETE FROM oidc_login WHERE created_at < $1",
+        now - Duration::minutes(LOGIN_LIMIT_MINUTES)
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseDelete("oidc_login".to_string()))?
+    .rows_affected();
+
+    let login_failure = sqlx::query!(
+        "DELETE FROM login_failure WHERE attempted_at < $1",
+        now - Duration::days(policy.login_failure_retention_days)
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseDelete("login_failure".to_string()))?
+    .rows_affected();
+
+    let login_lockout = sqlx::query!(
+        "DELETE FROM login_lockout WHERE locked_until < $1",
+        token_cutoff
+    )
+    .execute(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseDelete("login_lockout".to_string()))?
+    .rows_affected();
+
+    let idempotency_key = sqlx::query!(
+        "DELETE FROM idempotency_key WHERE created_at < $1",
+        now - Duration::hours(policy.idempotency_key_hours)
+    )
+    .execute(conn)
+    .await
+    .map_err(|_| QrError::DatabaseDelete("idempotency_key".to_string()))?
+    .rows_affected();
+
+    Ok(PurgeCounts {
+        passtoken,
+        refresh_token,
+        oidc_login,
+        login_failure,
+        login_lockout,
+        idempotency_key,
+    })
+}
+
+/// 一度削除を行い、結果をログと集計に残す
+async fn run_once(conn: &Pool<Postgres>, policy: &RetentionPolicy) {
+    let res = purge_expired(conn, policy).await;
+    let mut report = REPORT.lock().unwrap();
+    report.last_run_at = Some(Utc::now());
+    match res {
+        Ok(counts) => {
+            info!(
+                "Purged {} expired rows: passtoken={}, refresh_token={}, oidc_login={}, login_failure={}, login_lockout={}, idempotency_key={}",
+                counts.total(),
+                counts.passtoken,
+                counts.refresh_token,
+                counts.oidc_login,
+                counts.login_failure,
+                counts.login_lockout,
+                counts.idempotency_key
+            );
+            report.last = counts;
+            report.total.add(&counts);
+        }
+        Err(e) => {
+            error!("Failed to purge expired rows: {e}");
+            report.failures += 1;
+        }
+    }
+}
+
+/// 一定の間隔で削除を行うバックグラウンドのタスクを起動する
+pub fn spawn_purge_job(conn: Arc<Pool<Postgres>>, policy: RetentionPolicy) {
+    info!("Start purge job: {policy:?}");
+    let period = std::time::Duration::from_secs(policy.interval_minutes.max(1) as u64 * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            run_once(&conn, &policy).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authentication::{get_role, insert_passtoken, refresh::start_session};
+    use crate::authentication::{Passtoken, Role};
+    use crate::database::purge_expired::{purge_expired, RetentionPolicy};
+    use chrono::{Duration, Utc};
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_purge_expired(pool: Pool<Postgres>) {
+        let policy = RetentionPolicy::default();
+        let active = Passtoken::new(Role::Administrator, 1);
+        insert_passtoken(&pool, &active).await.unwrap();
+        let mut old = Passtoken::new(Role::General, 1);
+        old.created_at = Utc::now() - Duration::days(10);
+        insert_passtoken(&pool, &old).await.unwrap();
+        let pair = start_session(&pool, Passtoken::new(Role::General, 1))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE refresh_token SET used_at = now() - interval '8 days'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO oidc_login (state, nonce, created_at) VALUES ('s', 'n', now() - interval '1 hour')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let counts = purge_expired(&pool, &policy).await.unwrap();
+        assert_eq!(counts.passtoken, 1);
+        // 使用済みでもセッションが続いている間は再利用の検知に使うので残す
+        assert_eq!(counts.refresh_token, 0);
+        assert_eq!(counts.oidc_login, 1);
+        assert_eq!(counts.total(), 2);
+
+        // 有効なものは残る
+        assert_eq!(
+            get_role(&pool, &active.token).await,
+            Ok(Role::Administrator)
+        );
+        assert_eq!(get_role(&pool, &pair.token).await, Ok(Role::General));
+        assert_eq!(purge_expired(&pool, &policy).await.unwrap().total(), 0);
+
+        // セッションの期限から保持期間が過ぎれば削除する
+        sqlx::query(
+            "UPDATE refresh_token SET session_started_at = now() - make_interval(days => limit_days + 8)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let counts = purge_expired(&pool, &policy).await.unwrap();
+        assert_eq!(counts.refresh_token, 1);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{
+    database::get_version::{not_updated, VersionKey},
+    error_handling::{QrError, Result},
+    Container,
+};
+
+/// コンテナの情報のアップデートを行う
+/// `expected_version`を指定した場合は版が一致する時だけ更新し、更新後の版を返す
+pub async fn update_container<'a, E>(
+    conn: E,
+    new_info: Container,
+    expected_version: Option<i64>,
+) -> Result<i64>
+where
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
+{
+    let Container {
+        id,
+        qr_id,
+        qr_color,
+        storage,
+        description,
+    } = new_info;
+    let version = sqlx::query_scalar!(
+        r#"
+    UPDATE container SET qr_id=$2, qr_color=$3, storage=$4, description=$5
+    WHERE id=$1 AND ($6::bigint IS NULL OR version=$6)
+    RETURNING version"#,
+        id,
+        qr_id,
+        qr_color.to_string(),
+        storage.to_string(),
+        description,
+        expected_version
+    )
+    .fetch_optional(conn.clone())
+    .await
+    .map_err(|_| QrError::DatabaseUpdate("container".to_string()))?;
+
+    match version {
+        Some(version) => Ok(version),
+        None => Err(not_updated(conn, &VersionKey::ContainerId(id)).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::get_one_container::{get_one_container, IdType};
+    use crate::database::insert_container::insert_container;
+    use crate::database::update_container::update_container;
+    use crate::Container;
+    use sqlx::{pool::Pool, Postgres};
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_update_container(pool: Pool<Postgres>) {
+        let info: Container = serde_json::from_value(serde_json::json!({
+          "id": "550e8400-e29b-41d4-a716-446655440000",
+          "qr_id": "test",
+          "qr_color": "red",
+          "storage": "room101",
+          "description": "test"
+        }))
+        .unwrap();
+        insert_container(&pool, info.clone()).await.unwrap();
+
+        let new_info = Container {
+            description: "updated".to_string(),
+            ..info
+        };
+        update_container(&pool, new_info.clone(), None)
+            .await
+            .unwrap();
+        let result = get_one_container(&pool, IdType::ContainerId(new_info.id)).await;
+        assert_eq!(result, Ok(new_info));
+    }
+}
@@ -1,15 +1,23 @@
 use crate::{
+    database::get_version::{not_updated, VersionKey},
     error_handling::{QrError, Result},
     Fixtures,
 };
 
-pub async fn update_fixtures<'a, E>(conn: E, new_info: Fixtures) -> Result<()>
+/// 物品情報のアップデートを行う
+/// 登録日時は変更できないので、受け取った値は無視する
+/// `expected_version`を指定した場合は版が一致する時だけ更新し、更新後の版を返す
+pub async fn update_fixtures<'a, E>(
+    conn: E,
+    new_info: Fixtures,
+    expected_version: Option<i64>,
+) -> Result<i64>
 where
-    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
 {
     let Fixtures {
         id,
-        created_at,
+        created_at: _,
         qr_id,
         qr_color,
         name,
@@ -22,23 +30,22 @@ where
         parent_id,
     } = new_info;
 
-    sqlx::query!(
+    let version = sqlx::query_scalar!(
         r#"
     UPDATE fixtures SET
-        created_at=$2,
-        qr_id=$3,
-        qr_color=$4,
-        name=$5,
-        description=$6,
-        model_number=$7,
-        storage=$8,
-        usage=$9,
-        usage_season=$10,
-        note=$11,
-        parent_id=$12
-    WHERE id=$1"#,
+        qr_id=$2,
+        qr_color=$3,
+        name=$4,
+        description=$5,
+        model_number=$6,
+        storage=$7,
+        usage=$8,
+        usage_season=$9,
+        note=$10,
+        parent_id=$11
+    WHERE id=$1 AND ($12::bigint IS NULL OR version=$12)
+    RETURNING version"#,
         id,
-        created_at,
         qr_id,
         qr_color.to_string(),
         name,
@@ -49,12 +56,16 @@ where
         usage_season,
         note,
         parent_id,
+        expected_version
     )
-    .execute(conn)
+    .fetch_optional(conn.clone())
     .await
     .map_err(|_| QrError::DatabaseUpdate("fixtures".to_string()))?;
 
-    Ok(())
+    match version {
+        Some(version) => Ok(version),
+        None => Err(not_updated(conn, &VersionKey::FixturesId(id)).await),
+    }
 }
 
 #[cfg(test)]
@@ -62,6 +73,7 @@ mod tests {
     use crate::database::get_one_fixtures::{get_one_fixtures, IdType};
     use crate::database::insert_fixtures::insert_fixtures;
     use crate::database::update_fixtures::update_fixtures;
+    use crate::error_handling::QrError;
     use crate::Fixtures;
     use sqlx::{pool::Pool, Postgres};
     use uuid::uuid;
@@ -87,7 +99,7 @@ mod tests {
         let new_info: Fixtures = serde_json::from_value(serde_json::json!({
           "id": uuid,
           "qr_id": "test2",
-          "created_at": "2023-08-07 15:56:35 UTC",
+          "created_at": "2024-01-01 00:00:00 UTC",
           "qr_color":"red",
           "name":"テスト物品",
           "description":"テスト説明",
@@ -98,11 +110,19 @@ mod tests {
         }))
         .unwrap();
 
-        update_fixtures(&pool, new_info).await.unwrap();
+        assert_eq!(
+            update_fixtures(&pool, new_info.clone(), Some(1)).await,
+            Ok(2)
+        );
+        assert_eq!(
+            update_fixtures(&pool, new_info, Some(1)).await,
+            Err(QrError::PreconditionFailed)
+        );
 
         let result = get_one_fixtures(&pool, IdType::FixturesId(uuid))
             .await
             .unwrap();
-        assert_eq!(result.qr_id, "test2".to_string())
+        assert_eq!(result.qr_id, "test2".to_string());
+        assert_eq!(result.created_at.to_string(), "2023-08-07 15:56:35 UTC");
     }
 }
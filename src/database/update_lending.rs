@@ -1,50 +1,60 @@
 use crate::{
+    database::get_version::{not_updated, VersionKey},
     error_handling::{QrError, Result},
     Lending,
 };
 
 /// 貸し出し情報のアップデートを行う
-pub async fn update_lending<'a, E>(conn: E, new_info: Lending) -> Result<()>
+/// 貸し出し日時は変更できないので、受け取った値は無視する
+/// `expected_version`を指定した場合は版が一致する時だけ更新し、更新後の版を返す
+pub async fn update_lending<'a, E>(
+    conn: E,
+    new_info: Lending,
+    expected_version: Option<i64>,
+) -> Result<i64>
 where
-    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
 {
     let Lending {
         id,
         fixtures_id,
         fixtures_qr_id,
         spot_name,
-        lending_at,
+        lending_at: _,
         returned_at,
         borrower_name,
         borrower_number,
         borrower_org,
     } = new_info;
-    sqlx::query!(
+    let version = sqlx::query_scalar!(
         r#"UPDATE lending SET
             fixtures_id=$2,
             fixtures_qr_id=$3,
             spot_name=$4,
-            lending_at=$5,
-            returned_at=$6,
-            borrower_name=$7,
-            borrower_number=$8,
-            borrower_org=$9
-          WHERE id=$1"#,
+            returned_at=$5,
+            borrower_name=$6,
+            borrower_number=$7,
+            borrower_org=$8
+          WHERE id=$1 AND ($9::bigint IS NULL OR version=$9)
+          RETURNING version"#,
         id,
         fixtures_id,
         fixtures_qr_id,
         spot_name,
-        lending_at,
         returned_at,
         borrower_name,
         borrower_number,
-        borrower_org
+        borrower_org,
+        expected_version
     )
-    .execute(conn)
+    .fetch_optional(conn.clone())
     .await
     .map_err(|_| QrError::DatabaseUpdate("lending".to_string()))?;
 
-    Ok(())
+    match version {
+        Some(version) => Ok(version),
+        None => Err(not_updated(conn, &VersionKey::LendingId(id)).await),
+    }
 }
 
 #[cfg(test)]
@@ -82,7 +92,7 @@ mod tests {
           "borrower_org": "jsys"
         }))
         .unwrap();
-        let res = update_lending(&pool, new_info).await;
+        let res = update_lending(&pool, new_info, None).await;
         assert!(res.is_ok());
     }
 }
@@ -1,12 +1,18 @@
 use crate::{
+    database::get_version::{not_updated, VersionKey},
     error_handling::{QrError, Result},
     Spot,
 };
 
 /// 情報のアップデートを行う
-pub async fn update_spot<'a, E>(conn: E, new_info: Spot) -> Result<()>
+/// `expected_version`を指定した場合は版が一致する時だけ更新し、更新後の版を返す
+pub async fn update_spot<'a, E>(
+    conn: E,
+    new_info: Spot,
+    expected_version: Option<i64>,
+) -> Result<i64>
 where
-    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    E: sqlx::Executor<'a, Database = sqlx::Postgres> + Clone,
 {
     let Spot {
         name,
@@ -16,20 +22,27 @@ where
         room,
         note,
     } = new_info;
-    sqlx::query!(
-        r#"UPDATE spot SET area=$2, building=$3, floor=$4, room=$5, note=$6 WHERE name=$1"#,
+    let version = sqlx::query_scalar!(
+        r#"
+    UPDATE spot SET area=$2, building=$3, floor=$4, room=$5, note=$6
+    WHERE name=$1 AND ($7::bigint IS NULL OR version=$7)
+    RETURNING version"#,
         name,
         area.to_string(),
         building,
         floor.map(|i| i as i32),
         room,
-        note
+        note,
+        expected_version
     )
-    .execute(conn)
+    .fetch_optional(conn.clone())
     .await
     .map_err(|_| QrError::DatabaseUpdate("spot".to_string()))?;
 
-    Ok(())
+    match version {
+        Some(version) => Ok(version),
+        None => Err(not_updated(conn, &VersionKey::Spot(name)).await),
+    }
 }
 
 #[cfg(test)]
@@ -60,7 +73,7 @@ mod tests {
             room: Some("coins計算機室".to_string()),
             note: None,
         };
-        let res = update_spot(&pool, new_info).await;
+        let res = update_spot(&pool, new_info, None).await;
         assert!(res.is_ok());
     }
 }
@@ -1,4 +1,9 @@
-use axum::{extract::Json, http::StatusCode};
+use crate::validation::FieldError;
+use crate::{API_PREFIX, API_VERSION};
+use axum::{
+    extract::Json,
+    http::{header::LOCATION, HeaderMap, HeaderValue, StatusCode},
+};
 use serde::Serialize;
 use thiserror::Error;
 use tracing::*;
@@ -13,6 +18,8 @@ pub enum QrError {
     SearchEngineDelete(String),
     #[error("Couldn't search {} from search engine", .0)]
     SearchEngineSearch(String),
+    #[error("Couldn't apply settings of {} to search engine", .0)]
+    SearchEngineSettings(String),
     #[error("Couldn't add {} to database", .0)]
     DatabaseAdd(String),
     #[error("Couldn't update {} to database", .0)]
@@ -23,10 +30,51 @@ pub enum QrError {
     DatabaseGet(String),
     #[error("Couldn't found {} query in the url", .0)]
     UrlQuery(String),
+    #[error("{} is invalid query", .0)]
+    InvalidQuery(String),
     #[error("Unauthorized")]
     Authorized,
+    /// トークンは有効だが、操作に必要な権限が足りない状況
+    #[error("Forbidden")]
+    Forbidden,
+    /// 失敗した試行が続いたため、指定した秒数だけトークンの発行をロックしている状況
+    #[error("Too many failed attempts, retry after {} seconds", .0)]
+    TooManyAttempts(i64),
     #[error("{} is broken UUID", .0)]
     BrokenUuid(String),
+    /// IDや作成日時などの変更できない項目を変更しようとした状況
+    #[error("{} can't be changed", .0)]
+    ImmutableField(String),
+    /// `If-Match`で指定した版が現在の版と異なる状況
+    #[error("The resource has been modified since the version in If-Match")]
+    PreconditionFailed,
+    /// 更新に必要な`If-Match`が無い状況
+    #[error("If-Match header is required")]
+    PreconditionRequired,
+    /// `Idempotency-Key`として使えない値が送られた状況
+    #[error("{} can't be used as Idempotency-Key", .0)]
+    InvalidIdempotencyKey(String),
+    /// 同じ`Idempotency-Key`で内容の違うリクエストが送られた状況
+    #[error("Idempotency-Key {} was used for a different request", .0)]
+    IdempotencyKeyMismatch(String),
+    /// 同じ`Idempotency-Key`のリクエストを処理している途中の状況
+    #[error("Request with Idempotency-Key {} is in progress", .0)]
+    IdempotencyKeyInProgress(String),
+    /// 本文の項目が入力値の規則を満たさない状況
+    #[error(
+        "Invalid value in {}",
+        .0.iter().map(|e| e.field.as_str()).collect::<Vec<_>>().join(", ")
+    )]
+    Validation(Vec<FieldError>),
+    /// リクエストの本文を読み込めなかった状況
+    #[error("Invalid request body: {}", .0)]
+    InvalidBody(String),
+    #[error("{} can't be used as user name", .0)]
+    UserName(String),
+    #[error("Failed to hash password")]
+    PasswordHash,
+    #[error("Failed to communicate with the OpenID provider: {}", .0)]
+    Oidc(String),
     // 外部から投げられたidなどが間違っていて
     // データが見つけられなかった状況
     #[error("Couldn't find {} from database", .0)]
@@ -48,16 +96,38 @@ pub struct Msg<T>
 where
     T: Serialize,
 {
+    /// 応答の形のバージョン
+    /// 形を変える時はAPIのバージョンと一緒に上げる
+    version: u32,
     ok: bool,
     data: Option<T>,
     error_type: Option<String>,
     error_message: Option<String>,
+    /// 入力値が不正な場合の項目ごとの理由
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>,
 }
 
 pub type Result<T> = std::result::Result<T, QrError>;
 
 pub type ReturnData<T> = (StatusCode, Json<Msg<T>>);
 
+/// リソースを作成するエンドポイントの応答
+pub type ReturnCreated<T> = (StatusCode, HeaderMap, Json<Msg<T>>);
+
+/// 成功した応答を`201 Created`にし、作成したリソースのパスを`Location`で返す
+/// `path`にはバージョンの付かないパスを渡す
+pub fn created<T: Serialize>((status, json): ReturnData<T>, path: &str) -> ReturnCreated<T> {
+    let mut headers = HeaderMap::new();
+    if status != StatusCode::OK {
+        return (status, headers, json);
+    }
+    if let Ok(location) = HeaderValue::from_str(&format!("{API_PREFIX}{path}")) {
+        headers.insert(LOCATION, location);
+    }
+    (StatusCode::CREATED, headers, json)
+}
+
 pub async fn result_to_handler<T>(res: &Result<T>) -> ReturnData<T>
 where
     T: Serialize + Clone,
@@ -66,10 +136,12 @@ where
         Ok(t) => (
             StatusCode::OK,
             Json(Msg {
+                version: API_VERSION,
                 ok: true,
                 data: Some(t.clone()),
                 error_type: None,
                 error_message: None,
+                errors: None,
             }),
         ),
 
@@ -83,13 +155,30 @@ where
                 ),
                 SearchEngineDelete(_) => (StatusCode::INTERNAL_SERVER_ERROR, "SearchEngineDelete"),
                 SearchEngineSearch(_) => (StatusCode::INTERNAL_SERVER_ERROR, "SearchEngineSearch"),
+                SearchEngineSettings(_) => {
+                    (StatusCode::INTERNAL_SERVER_ERROR, "SearchEngineSettings")
+                }
                 DatabaseAdd(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DatabaseAdd"),
                 DatabaseUpdate(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DatabaseUpdate"),
                 DatabaseDelete(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DatabaseDelete"),
                 DatabaseGet(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DatabaseGet"),
                 UrlQuery(_) => (StatusCode::BAD_REQUEST, "UrlQuery"),
+                InvalidQuery(_) => (StatusCode::BAD_REQUEST, "InvalidQuery"),
                 Authorized => (StatusCode::UNAUTHORIZED, "Authorized"),
+                Forbidden => (StatusCode::FORBIDDEN, "Forbidden"),
+                TooManyAttempts(_) => (StatusCode::TOO_MANY_REQUESTS, "TooManyAttempts"),
                 BrokenUuid(_) => (StatusCode::BAD_REQUEST, "BrokenUuid"),
+                ImmutableField(_) => (StatusCode::BAD_REQUEST, "ImmutableField"),
+                InvalidBody(_) => (StatusCode::BAD_REQUEST, "InvalidBody"),
+                Validation(_) => (StatusCode::UNPROCESSABLE_ENTITY, "Validation"),
+                PreconditionFailed => (StatusCode::PRECONDITION_FAILED, "PreconditionFailed"),
+                PreconditionRequired => (StatusCode::PRECONDITION_REQUIRED, "PreconditionRequired"),
+                InvalidIdempotencyKey(_) => (StatusCode::BAD_REQUEST, "InvalidIdempotencyKey"),
+                IdempotencyKeyMismatch(_) => (StatusCode::CONFLICT, "IdempotencyKeyMismatch"),
+                IdempotencyKeyInProgress(_) => (StatusCode::CONFLICT, "IdempotencyKeyInProgress"),
+                UserName(_) => (StatusCode::BAD_REQUEST, "UserName"),
+                PasswordHash => (StatusCode::INTERNAL_SERVER_ERROR, "PasswordHash"),
+                Oidc(_) => (StatusCode::BAD_GATEWAY, "Oidc"),
                 DatabaseNotFound(_) => (StatusCode::BAD_REQUEST, "DatabaseNotFound"),
                 TokioRuntime => (StatusCode::INTERNAL_SERVER_ERROR, "TokioRutime"),
                 ConnectionPool => (StatusCode::INTERNAL_SERVER_ERROR, "ConnectionPool"),
@@ -100,10 +189,15 @@ where
             (
                 code,
                 Json(Msg {
+                    version: API_VERSION,
                     ok: false,
                     data: None,
                     error_type: Some(error_type.to_string()),
                     error_message: Some(e.to_string()),
+                    errors: match e {
+                        Validation(errors) => Some(errors.clone()),
+                        _ => None,
+                    },
                 }),
             )
         }
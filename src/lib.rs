@@ -5,6 +5,7 @@ use core::panic;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 // とりあえず後で実装しそうなものをちょっとだけ用意しておく
@@ -16,15 +17,26 @@ pub mod authentication;
 pub mod database;
 /// エラーハンドリング周り
 pub mod error_handling;
+/// 部分的な更新の内容
+pub mod patch;
 /// 検索エンジン周りのモジュール
 pub mod search_engine;
+/// 入力値の検証
+pub mod validation;
+
+/// 現在のAPIのバージョン
+/// 応答の`Msg`の`version`にも入る
+pub const API_VERSION: u32 = 1;
+
+/// 現在のバージョンのエンドポイントを置くパス
+pub const API_PREFIX: &str = "/v1";
 
 /// 備品情報のデータ。
 /// 必要な構成要素はこちらを参照<https://scrapbox.io/jsys/QR_2023_Design_Doc>
 ///
 /// 具体的なデータはこれ:
 /// <https://docs.google.com/spreadsheets/d/1PttDAxejyimvIQp-RKmAnYzVVEUaBb611Zgp4bUiO0I/edit#gid=0>
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, ToSchema)]
 pub struct Fixtures {
     /// 備品を識別する一意のID
     pub id: Uuid,
@@ -54,12 +66,48 @@ pub struct Fixtures {
     pub parent_id: String,
 }
 
+/// 物品を登録する時に送る情報
+/// IDと作成日時は端末の時計に左右されないよう、サーバーで決める
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct NewFixtures {
+    pub qr_id: String,
+    pub qr_color: QrColor,
+    pub name: String,
+    pub description: Option<String>,
+    pub model_number: Option<String>,
+    pub storage: Stroge,
+    pub usage: Option<String>,
+    pub usage_season: Option<String>,
+    pub note: String,
+    pub parent_id: String,
+}
+
+impl Fixtures {
+    /// 新しいIDと現在の日時を振って物品の情報を作る
+    pub fn new(fixtures: NewFixtures) -> Self {
+        Fixtures {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            qr_id: fixtures.qr_id,
+            qr_color: fixtures.qr_color,
+            name: fixtures.name,
+            description: fixtures.description,
+            model_number: fixtures.model_number,
+            storage: fixtures.storage,
+            usage: fixtures.usage,
+            usage_season: fixtures.usage_season,
+            note: fixtures.note,
+            parent_id: fixtures.parent_id,
+        }
+    }
+}
+
 /// QRコードに貼られている色
 /// 本来はqr_colorを`CREATE TYPE qr_color AS ENUM`などの形で定義したい。
 /// しかしsqlx v0.6以降できないらしく、DBにはtextで保存して変換をこちらで行うこととする。
 /// そのため、文字列に変換する`Display`トレイトと文字列から変換する`FromStr`トレイトを実装している。
 /// 参考：<https://github.com/launchbadge/sqlx/issues/1920>
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QrColor {
     Red,
@@ -112,7 +160,7 @@ impl From<std::string::String> for QrColor {
 /// しかしsqlx v0.6以降できないらしく、DBにはtextで保存して変換をこちらで行うこととする。
 /// そのため、文字列に変換する`Display`トレイトと文字列から変換する`FromStr`トレイトを実装している。
 /// 参考：<https://github.com/launchbadge/sqlx/issues/1920>
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Stroge {
     /// 101という部屋
@@ -147,7 +195,7 @@ impl From<std::string::String> for Stroge {
 
 /// 貸し出した物品を持っていく地点などの情報
 /// DBに保管して参照できるようにする
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct Spot {
     /// 人が聞いて認識できるような場所につけられた名前。
     /// 入力で使われることを想定。
@@ -170,7 +218,7 @@ pub struct Spot {
 
 /// 大まかな範囲を与える区分。
 /// 学内の使われる範囲を細かすぎず網羅的にカバーできるべき。
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Area {
     /// 第一エリア
@@ -245,7 +293,7 @@ impl From<std::string::String> for Area {
 
 /// 貸し出した物品を持っていく地点などの情報
 /// DBに保管して参照できるようにする
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct Lending {
     /// 貸し出しに振る一意のID
     pub id: Uuid,
@@ -269,8 +317,71 @@ pub struct Lending {
     pub borrower_org: Option<String>,
 }
 
+/// 物品を貸し出す時に送る情報
+/// IDと貸し出し日時は端末の時計に左右されないよう、サーバーで決める
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewLending {
+    pub fixtures_id: Uuid,
+    pub fixtures_qr_id: String,
+    pub spot_name: String,
+    pub borrower_name: String,
+    pub borrower_number: i32,
+    pub borrower_org: Option<String>,
+}
+
+impl Lending {
+    /// 新しいIDと現在の日時を振って貸出情報を作る
+    pub fn new(lending: NewLending) -> Self {
+        Lending {
+            id: Uuid::new_v4(),
+            fixtures_id: lending.fixtures_id,
+            fixtures_qr_id: lending.fixtures_qr_id,
+            spot_name: lending.spot_name,
+            lending_at: Utc::now(),
+            returned_at: None,
+            borrower_name: lending.borrower_name,
+            borrower_number: lending.borrower_number,
+            borrower_org: lending.borrower_org,
+        }
+    }
+}
+
+/// 閲覧者の権限に応じて返す貸出情報
+/// 物品管理者未満の権限やトークン無しでの閲覧では学籍番号を含めない
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LendingView {
+    pub id: Uuid,
+    pub fixtures_id: Uuid,
+    pub fixtures_qr_id: String,
+    pub spot_name: String,
+    pub lending_at: DateTime<Utc>,
+    pub returned_at: Option<DateTime<Utc>>,
+    pub borrower_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub borrower_number: Option<i32>,
+    pub borrower_org: Option<String>,
+}
+
+impl LendingView {
+    pub fn new(lending: Lending, role: Option<&authentication::Role>) -> Self {
+        let can_see_number =
+            role.is_some_and(|r| r.is_at_least(&authentication::Role::EquipmentManager));
+        LendingView {
+            id: lending.id,
+            fixtures_id: lending.fixtures_id,
+            fixtures_qr_id: lending.fixtures_qr_id,
+            spot_name: lending.spot_name,
+            lending_at: lending.lending_at,
+            returned_at: lending.returned_at,
+            borrower_name: lending.borrower_name,
+            borrower_number: can_see_number.then_some(lending.borrower_number),
+            borrower_org: lending.borrower_org,
+        }
+    }
+}
+
 /// 物品を保管しているコンテナの情報
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, ToSchema)]
 pub struct Container {
     /// コンテナに振る一意のID
     pub id: Uuid,
@@ -283,3 +394,13 @@ pub struct Container {
     /// 見た目や分類などを説明するテキスト
     pub description: String,
 }
+
+/// 検索で同じ意味として扱う単語のグループ
+/// 例えば`プロジェクター`と`projector`を登録すると、どちらで検索しても両方が引っかかる
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow, ToSchema)]
+pub struct Synonym {
+    /// グループに振る一意のID
+    pub id: Uuid,
+    /// 同じ意味として扱う単語
+    pub words: Vec<String>,
+}
@@ -0,0 +1,180 @@
+//! 物品・貸出情報・地点の部分的な更新
+//!
+//! 本文に含まれる項目だけを変更し、含まれない項目はデータベースの値をそのまま残す。
+//! `Option`の項目は`null`を明示すると値を消す。
+//! IDや作成日時などの変更できない項目が含まれている場合は受け付けない。
+use crate::error_handling::{QrError, Result};
+use crate::{Area, QrColor, Stroge};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// 物品情報の変更できない項目
+pub const FIXTURES_IMMUTABLE_FIELDS: &[&str] = &["id", "created_at"];
+
+/// 貸出情報の変更できない項目
+pub const LENDING_IMMUTABLE_FIELDS: &[&str] = &["id", "lending_at"];
+
+/// 地点情報の変更できない項目
+/// 名前は地点を指定するのに使うので変更できない
+pub const SPOT_IMMUTABLE_FIELDS: &[&str] = &["name"];
+
+/// 項目が無い場合は`None`、`null`の場合は`Some(None)`にする
+/// `#[serde(default)]`と一緒に使う
+fn nullable<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// 項目が無い場合は`None`にし、`null`は受け付けない
+/// `Option`でない項目に使う
+fn non_null<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// `null`を許す項目をSQLに渡す形にする
+/// 一つ目は項目が本文に含まれていたかどうか
+pub fn nullable_param<T: Clone>(field: &Option<Option<T>>) -> (bool, Option<T>) {
+    (field.is_some(), field.clone().flatten())
+}
+
+/// ログに残すための、本文に含まれる項目の名前
+/// 学籍番号などの値はログに残さない
+pub fn patch_fields(body: &serde_json::Value) -> Vec<&str> {
+    body.as_object()
+        .map(|object| object.keys().map(String::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// リクエストの本文を部分的な更新の内容として読み込む
+/// - 変更できない項目が含まれている場合は`QrError::ImmutableField`
+/// - 知らない項目が含まれていたり型が違ったりする場合は`QrError::InvalidBody`
+pub fn parse_patch<T: DeserializeOwned>(
+    body: serde_json::Value,
+    immutable_fields: &[&str],
+) -> Result<T> {
+    if let Some(object) = body.as_object() {
+        if let Some(field) = immutable_fields.iter().find(|f| object.contains_key(**f)) {
+            return Err(QrError::ImmutableField(field.to_string()));
+        }
+    }
+    serde_json::from_value(body).map_err(|e| QrError::InvalidBody(e.to_string()))
+}
+
+/// 物品情報の部分的な更新
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FixturesPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub qr_id: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub qr_color: Option<QrColor>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub model_number: Option<Option<String>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub storage: Option<Stroge>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub usage: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub usage_season: Option<Option<String>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub note: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub parent_id: Option<String>,
+}
+
+/// 貸出情報の部分的な更新
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LendingPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub fixtures_id: Option<Uuid>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub fixtures_qr_id: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub spot_name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub returned_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub borrower_name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub borrower_number: Option<i32>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub borrower_org: Option<Option<String>>,
+}
+
+/// 地点情報の部分的な更新
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SpotPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub area: Option<Area>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub building: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub floor: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub room: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub note: Option<Option<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error_handling::QrError;
+    use crate::patch::{parse_patch, patch_fields, FixturesPatch, FIXTURES_IMMUTABLE_FIELDS};
+
+    #[test]
+    fn test_parse_patch() {
+        assert_eq!(
+            patch_fields(&serde_json::json!({"borrower_number": 20200000, "note": "x"})),
+            vec!["borrower_number", "note"]
+        );
+        assert!(patch_fields(&serde_json::json!([1])).is_empty());
+
+        let patch: FixturesPatch = parse_patch(
+            serde_json::json!({"name": "ドラム", "description": null}),
+            FIXTURES_IMMUTABLE_FIELDS,
+        )
+        .unwrap();
+        assert_eq!(patch.name, Some("ドラム".to_string()));
+        // nullは値を消し、含まれない項目は変更しない
+        assert_eq!(patch.description, Some(None));
+        assert_eq!(patch.usage, None);
+
+        assert_eq!(
+            parse_patch::<FixturesPatch>(
+                serde_json::json!({"created_at": "2023-08-07 15:56:35 UTC"}),
+                FIXTURES_IMMUTABLE_FIELDS
+            ),
+            Err(QrError::ImmutableField("created_at".to_string()))
+        );
+        assert!(matches!(
+            parse_patch::<FixturesPatch>(
+                serde_json::json!({"nmae": "x"}),
+                FIXTURES_IMMUTABLE_FIELDS
+            ),
+            Err(QrError::InvalidBody(_))
+        ));
+        // Optionでない項目は消せない
+        assert!(matches!(
+            parse_patch::<FixturesPatch>(
+                serde_json::json!({"name": null}),
+                FIXTURES_IMMUTABLE_FIELDS
+            ),
+            Err(QrError::InvalidBody(_))
+        ));
+    }
+}
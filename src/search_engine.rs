@@ -1,20 +1,27 @@
 use crate::{
     error_handling::{QrError, Result},
-    Fixtures,
+    Container, Fixtures, Lending, Spot, Synonym,
 };
-use meilisearch_sdk::client::*;
+use async_trait::async_trait;
+use query::{IndexSettings, SearchPage, SearchQuery};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::env;
+use std::marker::PhantomData;
+use std::sync::Arc;
 use tracing::*;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-/// 抽象化された検索コンテキスト
-#[derive(Clone)]
-pub struct Context {
-    client: Client,
-    index: String,
-    primary_key: String,
-}
+/// Meilisearchを使う検索バックエンド
+pub mod meilisearch;
+/// プロセス内で完結する検索バックエンド
+pub mod memory;
+/// 表記ゆれを吸収する正規化と同義語の辞書
+pub mod normalize;
+/// PostgreSQLを使う検索バックエンド
+pub mod postgres;
+/// 検索条件と検索結果の定義
+pub mod query;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult<T> {
@@ -22,92 +29,171 @@ pub struct SearchResult<T> {
     pub ranking: Option<f64>,
 }
 
-impl Context {
-    /// コンテキストを新しく作成
-    pub async fn new(index: &str, primary_key: &str) -> Result<Self> {
-        let master_key = env::var("MEILI_MASTER_KEY")
-            .map_err(|_| QrError::Environment("MEILI_MASTER_KEY".to_string()))?;
-        let url =
-            env::var("MEILI_URL").map_err(|_| QrError::Environment("MEILI_URL".to_string()))?;
-        let client = Client::new(&url, Some(master_key));
-        info!("Create meilisearch client: {url} / {index}, {primary_key}");
-        Ok(Context {
-            client,
-            index: index.to_string(),
-            primary_key: primary_key.to_string(),
-        })
-    }
-
+/// 検索エンジンの実体を差し替えるためのトレイト
+/// 一つの値が一つのインデックスに対応する
+#[async_trait]
+pub trait SearchBackend: Send + Sync {
     /// 情報を追加または更新
-    pub async fn add_or_replace_documents<T: Serialize>(&self, documents: &[T]) -> Result<()> {
-        let client = &self.client;
-        let index = client.index(&self.index);
-        let task = index
-            .add_documents(documents, Some(&self.primary_key))
-            .await
-            .map_err(|_| QrError::SearchEngineAddOrReplace(self.index.clone()))?;
-        client
-            .wait_for_task(task, None, None)
-            .await
-            .map_err(|_| QrError::SearchEngineAddOrReplace(self.index.clone()))?;
+    async fn add_or_replace_documents<T>(&self, documents: &[T]) -> Result<()>
+    where
+        T: Serialize + Send + Sync;
+
+    /// 削除する
+    async fn delete_documents<K>(&self, keys: &[K]) -> Result<()>
+    where
+        K: std::fmt::Display + Serialize + std::fmt::Debug + Send + Sync;
+
+    /// 検索条件に従って検索をし、結果とランキングスコアのペアを返す
+    async fn search<T>(&self, query: &SearchQuery) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send;
+
+    /// 絞り込みや並び替えに使う属性などをインデックスに設定する
+    /// 設定を持たないバックエンドでは何もしない
+    async fn apply_settings(&self, _settings: &IndexSettings) -> Result<()> {
         Ok(())
     }
+}
 
-    /// 削除する
-    pub async fn delete_documents<T>(&self, keys: &[T]) -> Result<()>
+/// 使用する検索バックエンドの種類
+/// `SEARCH_BACKEND`環境変数で指定する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Meilisearchを使う
+    Meilisearch,
+    /// PostgreSQLの`pg_trgm`を使う
+    Postgres,
+    /// プロセス内のメモリにインデックスを持つ
+    Memory,
+}
+
+impl BackendKind {
+    fn from_str_opt(item: &str) -> Option<Self> {
+        match item {
+            "meilisearch" => Some(BackendKind::Meilisearch),
+            "postgres" => Some(BackendKind::Postgres),
+            "memory" => Some(BackendKind::Memory),
+            _ => None,
+        }
+    }
+
+    /// 検索バックエンドを環境変数から読み込む
+    /// 指定が無い場合はMeilisearchを使う
+    pub fn from_env() -> Result<Self> {
+        match env::var("SEARCH_BACKEND").ok().filter(|s| !s.is_empty()) {
+            Some(s) => BackendKind::from_str_opt(&s)
+                .ok_or_else(|| QrError::Environment("SEARCH_BACKEND".to_string())),
+            None => Ok(BackendKind::Meilisearch),
+        }
+    }
+
+    /// 主となる検索バックエンドが使えないときの代替を環境変数から読み込む
+    /// `SEARCH_FALLBACK`が指定されていなければ代替は使わない
+    pub fn fallback_from_env() -> Result<Option<Self>> {
+        match env::var("SEARCH_FALLBACK").ok().filter(|s| !s.is_empty()) {
+            Some(s) => BackendKind::from_str_opt(&s)
+                .map(Some)
+                .ok_or_else(|| QrError::Environment("SEARCH_FALLBACK".to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// 検索に失敗したときに代わりのバックエンドで検索し直すコンテキスト
+/// 追加や削除は主となるバックエンドと代替の両方に行う
+pub struct FallbackContext<P: SearchBackend, S: SearchBackend> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P: SearchBackend, S: SearchBackend> FallbackContext<P, S> {
+    pub fn new(primary: P, secondary: S) -> Self {
+        FallbackContext { primary, secondary }
+    }
+}
+
+#[async_trait]
+impl<P: SearchBackend, S: SearchBackend> SearchBackend for FallbackContext<P, S> {
+    async fn add_or_replace_documents<T>(&self, documents: &[T]) -> Result<()>
     where
-        T: std::fmt::Display + Serialize + std::fmt::Debug,
+        T: Serialize + Send + Sync,
     {
-        let client = &self.client;
-        let index = client.index(&self.index);
-        let task = index
-            .delete_documents(keys)
-            .await
-            .map_err(|_| QrError::SearchEngineDelete(self.index.clone()))?;
-        client
-            .wait_for_task(task, None, None)
-            .await
-            .map_err(|_| QrError::SearchEngineDelete(self.index.clone()))?;
-        Ok(())
+        self.secondary.add_or_replace_documents(documents).await?;
+        self.primary.add_or_replace_documents(documents).await
+    }
+
+    async fn delete_documents<K>(&self, keys: &[K]) -> Result<()>
+    where
+        K: std::fmt::Display + Serialize + std::fmt::Debug + Send + Sync,
+    {
+        self.secondary.delete_documents(keys).await?;
+        self.primary.delete_documents(keys).await
     }
 
-    /// 単語に対して検索をし、結果とランキングスコアのペアを返す
-    pub async fn search<T: DeserializeOwned + 'static + Clone>(
-        &self,
-        keyword: &str,
-    ) -> Result<Vec<SearchResult<T>>> {
-        let index = self.client.index(&self.index);
-        let list = index
-            .search()
-            .with_query(keyword)
-            .with_limit(1000)
-            .execute::<T>()
-            .await
-            .map_err(|_| QrError::SearchEngineSearch(self.index.clone()))?
-            .hits
-            .iter()
-            .map(|res| SearchResult {
-                data: res.result.clone(),
-                ranking: res.ranking_score,
-            })
-            .collect::<Vec<_>>();
-        Ok(list)
+    async fn search<T>(&self, query: &SearchQuery) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send,
+    {
+        match self.primary.search(query).await {
+            Ok(page) => Ok(page),
+            Err(e) => {
+                warn!("{e}: use fallback search backend");
+                self.secondary.search(query).await
+            }
+        }
+    }
+
+    async fn apply_settings(&self, settings: &IndexSettings) -> Result<()> {
+        self.secondary.apply_settings(settings).await?;
+        self.primary.apply_settings(settings).await
     }
 }
 
+/// 物品情報の絞り込みに使える属性
+pub const FIXTURES_FILTERABLE_ATTRIBUTES: [&str; 4] =
+    ["storage", "qr_color", "parent_id", "is_lending"];
+
+/// 物品情報の並び替えに使える属性
+pub const FIXTURES_SORTABLE_ATTRIBUTES: [&str; 3] = ["name", "qr_id", "created_at"];
+
+/// 検索エンジンに登録する物品情報
+/// 貸し出し中かどうかで絞り込めるように貸し出し状況を持たせる
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct FixturesDocument {
+    #[serde(flatten)]
+    pub fixtures: Fixtures,
+    /// 貸し出し中かどうか
+    pub is_lending: bool,
+}
+
 /// 物品情報についての検索コンテキストなど
-pub struct SearchFixtures {
-    context: Context,
+pub struct SearchFixtures<B: SearchBackend> {
+    context: B,
 }
 
-#[allow(clippy::new_without_default)]
-impl SearchFixtures {
-    pub async fn new() -> Result<Self> {
-        Ok(SearchFixtures {
-            context: Context::new("fixtures", "id").await?,
-        })
+impl<B: SearchBackend> SearchFixtures<B> {
+    pub fn new(context: B) -> Self {
+        SearchFixtures { context }
+    }
+
+    /// 絞り込みや並び替えに使う属性と同義語をインデックスに設定する
+    /// 同義語の登録や削除の後にも呼び出して設定を反映し直す
+    pub async fn init(&self, synonyms: &[Synonym]) -> Result<()> {
+        let settings = IndexSettings {
+            filterable_attributes: FIXTURES_FILTERABLE_ATTRIBUTES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            sortable_attributes: FIXTURES_SORTABLE_ATTRIBUTES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            synonyms: normalize::synonym_map(synonyms),
+        };
+        self.context.apply_settings(&settings).await
     }
-    pub async fn add_or_replace(&self, lst: &[Fixtures]) -> Result<()> {
+
+    pub async fn add_or_replace(&self, lst: &[FixturesDocument]) -> Result<()> {
         self.context.add_or_replace_documents(lst).await
     }
 
@@ -116,16 +202,137 @@ impl SearchFixtures {
         self.context.delete_documents(keys).await
     }
 
-    /// 複数の単語について検索
-    /// くっつけて重複削除
-    pub async fn search(&self, keywords: &[String]) -> Result<Vec<SearchResult<Fixtures>>> {
-        let mut lst = Vec::new();
-        for keyword in keywords.iter() {
-            let mut res = self.context.search::<Fixtures>(keyword).await?;
-            lst.append(&mut res);
+    /// 検索する
+    /// 複数の検索語の組み合わせ方や除外する語の扱いはバックエンドに任せる
+    pub async fn search(&self, query: &SearchQuery) -> Result<SearchPage<FixturesDocument>> {
+        self.context.search(query).await
+    }
+}
+
+/// 地点情報の検索対象の属性
+pub const SPOT_SEARCHABLE_ATTRIBUTES: [&str; 4] = ["name", "building", "room", "note"];
+
+/// コンテナの情報の検索対象の属性
+pub const CONTAINER_SEARCHABLE_ATTRIBUTES: [&str; 2] = ["description", "qr_id"];
+
+/// 貸出情報の検索対象の属性
+pub const LENDING_SEARCHABLE_ATTRIBUTES: [&str; 2] = ["borrower_name", "borrower_org"];
+
+/// 検索エンジンに登録する地点情報
+/// 地点の名前は日本語を含むのでMeilisearchの主キーにできず、名前から作ったIDを持たせる
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SpotDocument {
+    /// 名前をUTF-8で16進数にしたもの
+    pub id: String,
+    #[serde(flatten)]
+    pub spot: Spot,
+}
+
+impl SpotDocument {
+    /// 地点の名前から検索エンジンでのIDを作る
+    /// PostgreSQLの`encode(convert_to(name, 'UTF8'), 'hex')`と同じ値になる
+    pub fn id(name: &str) -> String {
+        name.bytes().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl From<Spot> for SpotDocument {
+    fn from(spot: Spot) -> Self {
+        SpotDocument {
+            id: SpotDocument::id(&spot.name),
+            spot,
+        }
+    }
+}
+
+/// 物品以外の情報についての検索コンテキスト
+/// 一つの値が一つのインデックスに対応し、`T`はそこに登録する文書の型
+pub struct SearchDocuments<B: SearchBackend, T> {
+    context: B,
+    _document: PhantomData<fn() -> T>,
+}
+
+/// 地点情報についての検索コンテキスト
+pub type SearchSpot<B> = SearchDocuments<B, SpotDocument>;
+/// コンテナの情報についての検索コンテキスト
+pub type SearchContainer<B> = SearchDocuments<B, Container>;
+/// 貸し出し中の貸出情報についての検索コンテキスト
+pub type SearchLending<B> = SearchDocuments<B, Lending>;
+
+impl<B, T> SearchDocuments<B, T>
+where
+    B: SearchBackend,
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    pub fn new(context: B) -> Self {
+        SearchDocuments {
+            context,
+            _document: PhantomData,
+        }
+    }
+
+    /// 同義語をインデックスに設定する
+    pub async fn init(&self, synonyms: &[Synonym]) -> Result<()> {
+        let settings = IndexSettings {
+            synonyms: normalize::synonym_map(synonyms),
+            ..Default::default()
+        };
+        self.context.apply_settings(&settings).await
+    }
+
+    pub async fn add_or_replace(&self, lst: &[T]) -> Result<()> {
+        self.context.add_or_replace_documents(lst).await
+    }
+
+    /// 削除する
+    pub async fn delete<K>(&self, keys: &[K]) -> Result<()>
+    where
+        K: std::fmt::Display + Serialize + std::fmt::Debug + Send + Sync,
+    {
+        self.context.delete_documents(keys).await
+    }
+
+    /// 検索する
+    pub async fn search(&self, query: &SearchQuery) -> Result<SearchPage<T>> {
+        self.context.search(query).await
+    }
+}
+
+/// 全てのインデックスの検索コンテキストをまとめたもの
+pub struct SearchContexts<B: SearchBackend> {
+    pub fixtures: Arc<SearchFixtures<B>>,
+    pub spot: Arc<SearchSpot<B>>,
+    pub container: Arc<SearchContainer<B>>,
+    pub lending: Arc<SearchLending<B>>,
+}
+
+impl<B: SearchBackend> Clone for SearchContexts<B> {
+    fn clone(&self) -> Self {
+        SearchContexts {
+            fixtures: Arc::clone(&self.fixtures),
+            spot: Arc::clone(&self.spot),
+            container: Arc::clone(&self.container),
+            lending: Arc::clone(&self.lending),
         }
-        lst.sort_by_key(|f| f.data.id);
-        lst.dedup_by_key(|f| f.data.id);
-        Ok(lst)
+    }
+}
+
+impl<B: SearchBackend> SearchContexts<B> {
+    pub fn new(fixtures: B, spot: B, container: B, lending: B) -> Self {
+        SearchContexts {
+            fixtures: Arc::new(SearchFixtures::new(fixtures)),
+            spot: Arc::new(SearchDocuments::new(spot)),
+            container: Arc::new(SearchDocuments::new(container)),
+            lending: Arc::new(SearchDocuments::new(lending)),
+        }
+    }
+
+    /// 全てのインデックスの設定を行う
+    /// 同義語の登録や削除の後にも呼び出して設定を反映し直す
+    pub async fn init(&self, synonyms: &[Synonym]) -> Result<()> {
+        self.fixtures.init(synonyms).await?;
+        self.spot.init(synonyms).await?;
+        self.container.init(synonyms).await?;
+        self.lending.init(synonyms).await
     }
 }
@@ -0,0 +1,329 @@
+use crate::{
+    error_handling::{QrError, Result},
+    search_engine::{
+        normalize::searchable_strings,
+        query::{self, IndexSettings, Keywords, MatchMode, SearchPage, SearchQuery, MAX_LIMIT},
+        SearchBackend, SearchResult,
+    },
+};
+use async_trait::async_trait;
+use meilisearch_sdk::{
+    client::*,
+    search::{MatchingStrategies, SearchQuery as MeiliSearchQuery, Selectors},
+    settings::Settings,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::env;
+use tracing::*;
+
+/// Meilisearchのインデックス一つに対応する検索コンテキスト
+#[derive(Clone)]
+pub struct MeilisearchContext {
+    client: Client,
+    index: String,
+    primary_key: String,
+    /// 検索対象の属性
+    /// 空の場合は全ての属性を対象にする
+    searchable_attributes: Vec<String>,
+}
+
+impl MeilisearchContext {
+    /// コンテキストを新しく作成
+    /// 接続先は`MEILI_URL`と`MEILI_MASTER_KEY`から読み込む
+    pub async fn new(index: &str, primary_key: &str) -> Result<Self> {
+        let master_key = env::var("MEILI_MASTER_KEY")
+            .map_err(|_| QrError::Environment("MEILI_MASTER_KEY".to_string()))?;
+        let url =
+            env::var("MEILI_URL").map_err(|_| QrError::Environment("MEILI_URL".to_string()))?;
+        let client = Client::new(&url, Some(master_key));
+        info!("Create meilisearch client: {url} / {index}, {primary_key}");
+        Ok(MeilisearchContext {
+            client,
+            index: index.to_string(),
+            primary_key: primary_key.to_string(),
+            searchable_attributes: Vec::new(),
+        })
+    }
+
+    /// 検索対象の属性を指定する
+    pub fn with_searchable_attributes(mut self, attributes: &[&str]) -> Self {
+        self.searchable_attributes = attributes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+}
+
+/// 正規化した文字列を入れておくフィールドの名前
+/// 検索語も正規化してから渡すので、表記ゆれがあってもこのフィールドで一致する
+pub const NORMALIZED_FIELD: &str = "normalized_text";
+
+/// 文書に検索対象の属性を正規化した文字列のフィールドを付け加える
+fn with_normalized_text(mut document: Value, attributes: &[String]) -> Value {
+    let text = searchable_strings(&document, attributes).join("\n");
+    if let Value::Object(map) = &mut document {
+        map.insert(NORMALIZED_FIELD.to_string(), Value::String(text));
+    }
+    document
+}
+
+/// フィルタ式の中で使う文字列をエスケープする
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// 検索語をMeilisearchに渡す文字列にする
+/// フレーズは`"`で囲むとMeilisearch側でもフレーズとして扱われる
+fn query_string<'a>(terms: impl IntoIterator<Item = &'a query::Term>) -> String {
+    terms
+        .into_iter()
+        .map(|term| {
+            if term.phrase {
+                format!("\"{}\"", term.text)
+            } else {
+                term.text.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 絞り込みの条件をMeilisearchのフィルタ式にする
+fn filter_expression(query: &SearchQuery) -> Option<String> {
+    let lst = query
+        .filters
+        .iter()
+        .filter(|(_, values)| !values.is_empty())
+        .map(|(attribute, values)| {
+            let values = values
+                .iter()
+                .map(|v| format!("{attribute} = {}", quote(v)))
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            format!("({values})")
+        })
+        .collect::<Vec<_>>();
+    if lst.is_empty() {
+        None
+    } else {
+        Some(lst.join(" AND "))
+    }
+}
+
+impl MeilisearchContext {
+    /// 一度の検索で済む場合はMeilisearchの絞り込みやページ分割をそのまま使う
+    async fn search_native<T>(
+        &self,
+        query: &SearchQuery,
+        keywords: &Keywords,
+    ) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send,
+    {
+        let index = self.client.index(&self.index);
+        let filter = filter_expression(query);
+        let facets = query.facets.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        let sort = query
+            .sort
+            .iter()
+            .map(|sort| format!("{}:{}", sort.attribute, sort.order))
+            .collect::<Vec<_>>();
+        let sort = sort.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+
+        let keyword = query_string(&keywords.terms);
+        let mut search = index.search();
+        search
+            .with_query(&keyword)
+            .with_offset(query.offset)
+            .with_limit(query.limit.min(MAX_LIMIT))
+            .with_show_ranking_score(true);
+        if query.mode == MatchMode::And {
+            search.with_matching_strategy(MatchingStrategies::ALL);
+        }
+        if let Some(filter) = &filter {
+            search.with_filter(filter);
+        }
+        if !facets.is_empty() {
+            search.with_facets(Selectors::Some(&facets));
+        }
+        if !sort.is_empty() {
+            search.with_sort(&sort);
+        }
+        let res = search
+            .execute::<T>()
+            .await
+            .map_err(|_| QrError::SearchEngineSearch(self.index.clone()))?;
+
+        let hits = res
+            .hits
+            .iter()
+            .map(|res| SearchResult {
+                data: res.result.clone(),
+                ranking: res.ranking_score,
+            })
+            .collect::<Vec<_>>();
+        let total = res.estimated_total_hits.unwrap_or(hits.len());
+        let facets = res
+            .facet_distribution
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(attribute, counts)| (attribute, counts.into_iter().collect()))
+            .collect();
+        Ok(SearchPage {
+            hits,
+            total,
+            offset: query.offset,
+            limit: query.limit,
+            facets,
+        })
+    }
+
+    /// ORでの検索や除外する語がある場合
+    /// 検索語ごとの問い合わせをマルチサーチで一度に送り、スコアを合算して並べ直す
+    /// 除外する語は正規化した文字列のフィールドを見て取り除く
+    async fn search_merged<T>(
+        &self,
+        query: &SearchQuery,
+        keywords: &Keywords,
+    ) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send,
+    {
+        let err = || QrError::SearchEngineSearch(self.index.clone());
+        let index = self.client.index(&self.index);
+        let filter = filter_expression(query);
+        let keywords_lst = match query.mode {
+            MatchMode::Or if !keywords.terms.is_empty() => keywords
+                .terms
+                .iter()
+                .map(|term| query_string([term]))
+                .collect::<Vec<_>>(),
+            _ => vec![query_string(&keywords.terms)],
+        };
+
+        let mut multi_search = self.client.multi_search();
+        for keyword in keywords_lst.iter() {
+            let mut search = MeiliSearchQuery::new(&index);
+            search
+                .with_query(keyword)
+                .with_limit(MAX_LIMIT)
+                .with_show_ranking_score(true);
+            if query.mode == MatchMode::And {
+                search.with_matching_strategy(MatchingStrategies::ALL);
+            }
+            if let Some(filter) = &filter {
+                search.with_filter(filter);
+            }
+            multi_search.with_search_query(search);
+        }
+        let res = multi_search.execute::<Value>().await.map_err(|_| err())?;
+
+        let results = res
+            .results
+            .into_iter()
+            .map(|res| {
+                res.hits
+                    .into_iter()
+                    .map(|hit| (hit.result, hit.ranking_score.unwrap_or_default()))
+                    .collect()
+            })
+            .collect();
+        let hits = query::merge_hits(&self.primary_key, results)
+            .into_iter()
+            .filter_map(|(mut document, ranking)| {
+                let text = match &mut document {
+                    Value::Object(map) => map.remove(NORMALIZED_FIELD),
+                    _ => None,
+                };
+                let text = text.as_ref().and_then(Value::as_str).unwrap_or_default();
+                let excluded = keywords
+                    .excludes
+                    .iter()
+                    .any(|term| text.contains(&term.text));
+                (!excluded).then_some((document, ranking))
+            })
+            .collect();
+        query::evaluate(&self.index, query, hits)
+    }
+}
+
+#[async_trait]
+impl SearchBackend for MeilisearchContext {
+    async fn add_or_replace_documents<T>(&self, documents: &[T]) -> Result<()>
+    where
+        T: Serialize + Send + Sync,
+    {
+        let documents = documents
+            .iter()
+            .map(|document| {
+                serde_json::to_value(document)
+                    .map(|value| with_normalized_text(value, &self.searchable_attributes))
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| QrError::SearchEngineAddOrReplace(self.index.clone()))?;
+        let client = &self.client;
+        let index = client.index(&self.index);
+        let task = index
+            .add_documents(&documents, Some(&self.primary_key))
+            .await
+            .map_err(|_| QrError::SearchEngineAddOrReplace(self.index.clone()))?;
+        client
+            .wait_for_task(task, None, None)
+            .await
+            .map_err(|_| QrError::SearchEngineAddOrReplace(self.index.clone()))?;
+        Ok(())
+    }
+
+    async fn delete_documents<K>(&self, keys: &[K]) -> Result<()>
+    where
+        K: std::fmt::Display + Serialize + std::fmt::Debug + Send + Sync,
+    {
+        let client = &self.client;
+        let index = client.index(&self.index);
+        let task = index
+            .delete_documents(keys)
+            .await
+            .map_err(|_| QrError::SearchEngineDelete(self.index.clone()))?;
+        client
+            .wait_for_task(task, None, None)
+            .await
+            .map_err(|_| QrError::SearchEngineDelete(self.index.clone()))?;
+        Ok(())
+    }
+
+    async fn search<T>(&self, query: &SearchQuery) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send,
+    {
+        let keywords = Keywords::parse(&query.keyword);
+        if keywords.excludes.is_empty()
+            && (query.mode == MatchMode::And || keywords.terms.len() <= 1)
+        {
+            self.search_native(query, &keywords).await
+        } else {
+            self.search_merged(query, &keywords).await
+        }
+    }
+
+    async fn apply_settings(&self, settings: &IndexSettings) -> Result<()> {
+        let client = &self.client;
+        let index = client.index(&self.index);
+        let mut meili_settings = Settings::new()
+            .with_filterable_attributes(&settings.filterable_attributes)
+            .with_sortable_attributes(&settings.sortable_attributes)
+            .with_synonyms(settings.synonyms.clone().into_iter().collect());
+        if !self.searchable_attributes.is_empty() {
+            let mut attributes = self.searchable_attributes.clone();
+            attributes.push(NORMALIZED_FIELD.to_string());
+            meili_settings = meili_settings.with_searchable_attributes(&attributes);
+        }
+        let task = index
+            .set_settings(&meili_settings)
+            .await
+            .map_err(|_| QrError::SearchEngineSettings(self.index.clone()))?;
+        client
+            .wait_for_task(task, None, None)
+            .await
+            .map_err(|_| QrError::SearchEngineSettings(self.index.clone()))?;
+        Ok(())
+    }
+}
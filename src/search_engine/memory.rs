@@ -0,0 +1,381 @@
+//! 外部の検索エンジンを使わずにプロセス内で完結する検索バックエンド
+//!
+//! テストや小規模な運用での利用を想定している。
+//! 文書中の文字列を正規化したうえで文字bigramの転置インデックスを作り、
+//! 検索語やフレーズを部分文字列として含むかどうかで一致を判定する。
+//! 同義語が登録されている単語は、同義語のどれかを含んでいれば一致とみなす。
+use crate::{
+    error_handling::{QrError, Result},
+    search_engine::{
+        normalize::{expand_synonyms, searchable_strings, Synonyms},
+        query::{self, IndexSettings, Keywords, MatchMode, SearchPage, SearchQuery, Term},
+        SearchBackend,
+    },
+};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::RwLock;
+
+/// インデックスに登録された文書
+#[derive(Debug, Clone)]
+struct Document {
+    /// 登録された文書そのもの
+    value: Value,
+    /// 正規化した文字列フィールドを改行でつなげたもの
+    text: String,
+    /// 文書に含まれるトークン
+    tokens: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    documents: BTreeMap<String, Document>,
+    postings: HashMap<String, BTreeSet<String>>,
+}
+
+impl Index {
+    fn insert(&mut self, key: String, document: Document) {
+        self.remove(&key);
+        for token in document.tokens.iter() {
+            self.postings
+                .entry(token.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        self.documents.insert(key, document);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(old) = self.documents.remove(key) {
+            for token in old.tokens.iter() {
+                if let Some(keys) = self.postings.get_mut(token) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.postings.remove(token);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 文字列を部分文字列として含む文書のキーを返す
+    fn lookup(&self, s: &str) -> BTreeSet<String> {
+        let mut candidates: Option<BTreeSet<String>> = None;
+        for token in split_words(s).flat_map(word_tokens) {
+            let keys = self.postings.get(&token).cloned().unwrap_or_default();
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&keys).cloned().collect(),
+                None => keys,
+            });
+        }
+        // 記号だけの検索語などでトークンが無い場合は全ての文書を候補にする
+        candidates
+            .unwrap_or_else(|| self.documents.keys().cloned().collect())
+            .into_iter()
+            .filter(|key| self.documents[key].text.contains(s))
+            .collect()
+    }
+}
+
+/// プロセス内のメモリにインデックスを持つ検索コンテキスト
+#[derive(Debug)]
+pub struct InMemoryContext {
+    index: String,
+    primary_key: String,
+    /// 検索対象の属性
+    /// 空の場合は全ての属性を対象にする
+    searchable_attributes: Vec<String>,
+    inner: RwLock<Index>,
+    synonyms: RwLock<Synonyms>,
+}
+
+impl InMemoryContext {
+    /// 空のインデックスを作成
+    pub fn new(index: &str, primary_key: &str) -> Self {
+        InMemoryContext {
+            index: index.to_string(),
+            primary_key: primary_key.to_string(),
+            searchable_attributes: Vec::new(),
+            inner: RwLock::new(Index::default()),
+            synonyms: RwLock::new(Synonyms::new()),
+        }
+    }
+
+    /// 検索対象の属性を指定する
+    /// 文書を登録する前に指定しておく必要がある
+    pub fn with_searchable_attributes(mut self, attributes: &[&str]) -> Self {
+        self.searchable_attributes = attributes.iter().map(|s| s.to_string()).collect();
+        self
+    }
+}
+
+/// 正規化済みの文字列を単語に分割する
+fn split_words(s: &str) -> impl Iterator<Item = &str> {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+}
+
+/// 単語を検索用のトークンに分割する
+/// 日本語は分かち書きされないので文字bigramを使う
+fn word_tokens(word: &str) -> Vec<String> {
+    let chars = word.chars().collect::<Vec<_>>();
+    if chars.len() < 2 {
+        vec![word.to_string()]
+    } else {
+        chars.windows(2).map(|w| w.iter().collect()).collect()
+    }
+}
+
+fn to_document(value: Value, attributes: &[String]) -> Document {
+    let strings = searchable_strings(&value, attributes);
+    let mut tokens = BTreeSet::new();
+    for s in strings.iter() {
+        for word in split_words(s) {
+            // 一文字の単語でも検索できるようにunigramも入れておく
+            tokens.extend(word.chars().map(|c| c.to_string()));
+            tokens.extend(word_tokens(word));
+        }
+    }
+    Document {
+        value,
+        text: strings.join("\n"),
+        tokens,
+    }
+}
+
+#[async_trait]
+impl SearchBackend for InMemoryContext {
+    async fn add_or_replace_documents<T>(&self, documents: &[T]) -> Result<()>
+    where
+        T: Serialize + Send + Sync,
+    {
+        let err = || QrError::SearchEngineAddOrReplace(self.index.clone());
+        let mut lst = Vec::new();
+        for document in documents.iter() {
+            let value = serde_json::to_value(document).map_err(|_| err())?;
+            let key = match value.get(&self.primary_key) {
+                Some(Value::String(s)) => s.clone(),
+                Some(v) => v.to_string(),
+                None => return Err(err()),
+            };
+            lst.push((key, to_document(value, &self.searchable_attributes)));
+        }
+        let mut inner = self.inner.write().map_err(|_| err())?;
+        for (key, document) in lst {
+            inner.insert(key, document);
+        }
+        Ok(())
+    }
+
+    async fn delete_documents<K>(&self, keys: &[K]) -> Result<()>
+    where
+        K: std::fmt::Display + Serialize + std::fmt::Debug + Send + Sync,
+    {
+        let mut inner = self
+            .inner
+            .write()
+            .map_err(|_| QrError::SearchEngineDelete(self.index.clone()))?;
+        for key in keys.iter() {
+            inner.remove(&key.to_string());
+        }
+        Ok(())
+    }
+
+    async fn search<T>(&self, query: &SearchQuery) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send,
+    {
+        let inner = self
+            .inner
+            .read()
+            .map_err(|_| QrError::SearchEngineSearch(self.index.clone()))?;
+        let synonyms = self
+            .synonyms
+            .read()
+            .map_err(|_| QrError::SearchEngineSearch(self.index.clone()))?;
+        let keywords = Keywords::parse(&query.keyword);
+        let matches = |term: &Term| {
+            expand_synonyms(&term.text, &synonyms)
+                .iter()
+                .flat_map(|s| inner.lookup(s))
+                .collect::<BTreeSet<_>>()
+        };
+        let excluded = keywords
+            .excludes
+            .iter()
+            .flat_map(matches)
+            .collect::<BTreeSet<_>>();
+
+        // 一致した検索語の割合をスコアにする
+        let mut scores: BTreeMap<&str, usize> = BTreeMap::new();
+        if keywords.terms.is_empty() {
+            // 除外する語しか無い時や空の検索語の時は全件を対象にする
+            for key in inner.documents.keys() {
+                scores.insert(key, 1);
+            }
+        }
+        for term in keywords.terms.iter() {
+            for key in matches(term) {
+                let (key, _) = inner.documents.get_key_value(&key).unwrap();
+                *scores.entry(key).or_default() += 1;
+            }
+        }
+        let total = keywords.terms.len().max(1);
+        let mut hits = scores
+            .into_iter()
+            .filter(|(key, score)| {
+                !excluded.contains(*key) && (query.mode == MatchMode::Or || *score == total)
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        let hits = hits
+            .into_iter()
+            .map(|(key, score)| {
+                let ranking = score as f64 / total as f64;
+                (inner.documents[key].value.clone(), ranking)
+            })
+            .collect();
+        query::evaluate(&self.index, query, hits)
+    }
+
+    async fn apply_settings(&self, settings: &IndexSettings) -> Result<()> {
+        let mut synonyms = self
+            .synonyms
+            .write()
+            .map_err(|_| QrError::SearchEngineSettings(self.index.clone()))?;
+        *synonyms = settings.synonyms.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search_engine::memory::InMemoryContext;
+    use crate::search_engine::{
+        normalize::synonym_map,
+        query::{IndexSettings, MatchMode, SearchQuery},
+        SearchBackend,
+    };
+    use crate::Synonym;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Doc {
+        id: String,
+        name: String,
+    }
+
+    fn doc(id: &str, name: &str) -> Doc {
+        Doc {
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_search() {
+        let context = InMemoryContext::new("test", "id");
+        context
+            .add_or_replace_documents(&[
+                doc("1", "延長コード 5m"),
+                doc("2", "HDMIケーブル"),
+                doc("3", "机"),
+            ])
+            .await
+            .unwrap();
+
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("ｹｰﾌﾞﾙ"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.id, "2");
+
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("hdmi 延長"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].ranking, Some(0.5));
+
+        let mut query = SearchQuery::keyword("hdmi 延長");
+        query.mode = MatchMode::And;
+        let res = context.search::<Doc>(&query).await.unwrap().hits;
+        assert!(res.is_empty());
+
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("\"コード 5m\" -hdmi"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.id, "1");
+
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("-ケーブル"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 2);
+
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("机"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword(""))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 3);
+
+        // 置き換えると古い内容では引っかからなくなる
+        context
+            .add_or_replace_documents(&[doc("1", "プロジェクター")])
+            .await
+            .unwrap();
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("延長"))
+            .await
+            .unwrap()
+            .hits;
+        assert!(res.is_empty());
+
+        context.delete_documents(&["2"]).await.unwrap();
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("ケーブル"))
+            .await
+            .unwrap()
+            .hits;
+        assert!(res.is_empty());
+
+        // 同義語を登録すると別の表記でも引っかかる
+        context
+            .add_or_replace_documents(&[doc("4", "Projector EB-X05")])
+            .await
+            .unwrap();
+        let settings = IndexSettings {
+            synonyms: synonym_map(&[Synonym {
+                id: Uuid::nil(),
+                words: vec!["プロジェクター".to_string(), "projector".to_string()],
+            }]),
+            ..Default::default()
+        };
+        context.apply_settings(&settings).await.unwrap();
+        let res = context
+            .search::<Doc>(&SearchQuery::keyword("ﾌﾟﾛｼﾞｪｸﾀ"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 2);
+    }
+}
@@ -0,0 +1,138 @@
+//! 検索語と文書の表記ゆれを吸収する正規化
+//!
+//! インデックスへの登録時と検索時の両方で同じ正規化をかけることで、
+//! `ケーブル`、`けーぶる`、`ｹｰﾌﾞﾙ`のような入力の違いを同じものとして扱う。
+//! 同義語の辞書もここで正規化したうえで各バックエンドに渡す。
+use crate::Synonym;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+
+/// 同義語の辞書
+/// 正規化した単語から、同じ意味として扱う正規化済みの単語の一覧への対応
+pub type Synonyms = BTreeMap<String, Vec<String>>;
+
+/// 全角半角や大文字小文字、ひらがなカタカナ、長音の有無の違いを吸収する
+///
+/// NFKCで半角カナや全角英数字を寄せた後に小文字にし、カタカナをひらがなにする。
+/// `プロジェクタ`と`プロジェクター`のように長音は書かれたり書かれなかったりするので、
+/// 長音符は取り除いてしまう。
+pub fn normalize(s: &str) -> String {
+    s.nfkc()
+        .flat_map(char::to_lowercase)
+        .filter(|c| *c != 'ー')
+        .map(|c| match c {
+            // カタカナをひらがなに寄せる
+            'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// 文書に含まれる文字列を全て正規化して取り出す
+pub fn normalized_strings(value: &Value) -> Vec<String> {
+    let mut out = Vec::new();
+    collect_strings(value, &mut out);
+    out
+}
+
+/// 文書の指定した属性に含まれる文字列を全て正規化して取り出す
+/// 属性が指定されていない場合は文書全体から取り出す
+pub fn searchable_strings(value: &Value, attributes: &[String]) -> Vec<String> {
+    if attributes.is_empty() {
+        return normalized_strings(value);
+    }
+    let mut out = Vec::new();
+    for attribute in attributes.iter() {
+        if let Some(value) = value.get(attribute) {
+            collect_strings(value, &mut out);
+        }
+    }
+    out
+}
+
+fn collect_strings(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::String(s) => out.push(normalize(s)),
+        Value::Number(n) => out.push(n.to_string()),
+        Value::Array(lst) => lst.iter().for_each(|v| collect_strings(v, out)),
+        Value::Object(map) => map.values().for_each(|v| collect_strings(v, out)),
+        _ => (),
+    }
+}
+
+/// 登録されている同義語のグループを正規化した辞書にする
+/// グループ内のそれぞれの単語から、残りの全ての単語を引けるようにする
+pub fn synonym_map(lst: &[Synonym]) -> Synonyms {
+    let mut map: Synonyms = BTreeMap::new();
+    for synonym in lst.iter() {
+        let mut words = synonym
+            .words
+            .iter()
+            .map(|w| normalize(w.trim()))
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>();
+        words.sort();
+        words.dedup();
+        for word in words.iter() {
+            let entry = map.entry(word.clone()).or_default();
+            for other in words.iter().filter(|w| *w != word) {
+                if !entry.contains(other) {
+                    entry.push(other.clone());
+                }
+            }
+        }
+    }
+    map.retain(|_, v| !v.is_empty());
+    map
+}
+
+/// 正規化済みの検索語に含まれる同義語を置き換えた検索語の候補を返す
+/// 先頭は元の検索語そのもの
+pub fn expand_synonyms(keyword: &str, synonyms: &Synonyms) -> Vec<String> {
+    let mut lst = vec![keyword.to_string()];
+    for (word, others) in synonyms.iter() {
+        if keyword.contains(word.as_str()) {
+            for other in others.iter() {
+                let expanded = keyword.replace(word.as_str(), other);
+                if !lst.contains(&expanded) {
+                    lst.push(expanded);
+                }
+            }
+        }
+    }
+    lst
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search_engine::normalize::{expand_synonyms, normalize, synonym_map};
+    use crate::Synonym;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("ｹｰﾌﾞﾙ"), normalize("けーぶる"));
+        assert_eq!(normalize("ケーブル"), normalize("けーぶる"));
+        assert_eq!(normalize("プロジェクタ"), normalize("プロジェクター"));
+        assert_eq!(normalize("ＨＤＭＩ"), "hdmi");
+        assert_eq!(normalize("ケーブル"), "けぶる");
+    }
+
+    #[test]
+    fn test_synonyms() {
+        let synonyms = synonym_map(&[Synonym {
+            id: Uuid::nil(),
+            words: vec!["プロジェクター".to_string(), "Projector".to_string()],
+        }]);
+        assert_eq!(synonyms["ぷろじぇくた"], vec!["projector".to_string()]);
+        assert_eq!(synonyms["projector"], vec!["ぷろじぇくた".to_string()]);
+
+        let lst = expand_synonyms(&normalize("ﾌﾟﾛｼﾞｪｸﾀ 台"), &synonyms);
+        assert_eq!(
+            lst,
+            vec!["ぷろじぇくた 台".to_string(), "projector 台".to_string()]
+        );
+        assert_eq!(expand_synonyms("机", &synonyms), vec!["机".to_string()]);
+    }
+}
@@ -0,0 +1,596 @@
+//! PostgreSQLの`pg_trgm`を使う検索バックエンド
+//!
+//! 文書の実体はデータベースのテーブルそのものなので、
+//! 追加や削除はデータベースへの書き込みで済んでおり何もしない。
+//! 検索は指定したカラムをつなげて正規化した文字列に対する部分一致とトライグラムの類似度で行う。
+//! 複数の検索語やフレーズ、除外する語はWHERE句の条件を組み立てて一度の問い合わせで処理する。
+//! 絞り込み・並び替え・ページ分割もSQLで行い、一ページ分の行だけを取り出す。
+//! ファセットは属性ごとに`GROUP BY`で集計する。
+use crate::{
+    error_handling::{QrError, Result},
+    search_engine::{
+        normalize::{expand_synonyms, Synonyms},
+        query::{IndexSettings, Keywords, MatchMode, SearchPage, SearchQuery, Term},
+        SearchBackend, SearchResult, CONTAINER_SEARCHABLE_ATTRIBUTES,
+        LENDING_SEARCHABLE_ATTRIBUTES, SPOT_SEARCHABLE_ATTRIBUTES,
+    },
+};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sqlx::{
+    pool::Pool,
+    postgres::{PgArguments, PgRow, Postgres},
+    query::QueryAs,
+    FromRow,
+};
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// PostgreSQLのテーブル一つに対応する検索コンテキスト
+#[derive(Debug, Clone)]
+pub struct PgSearchContext {
+    conn: Arc<Pool<Postgres>>,
+    table: String,
+    /// 検索対象の行を取り出すテーブルまたはサブクエリ
+    source: String,
+    columns: Vec<String>,
+    synonyms: Arc<RwLock<Synonyms>>,
+}
+
+impl PgSearchContext {
+    /// コンテキストを新しく作成
+    /// `table`と`columns`はSQLに埋め込まれるので外部からの入力を渡してはいけない
+    pub fn new(conn: Arc<Pool<Postgres>>, table: &str, columns: &[&str]) -> Self {
+        PgSearchContext {
+            conn,
+            table: table.to_string(),
+            source: table.to_string(),
+            columns: columns.iter().map(|s| s.to_string()).collect(),
+            synonyms: Arc::new(RwLock::new(Synonyms::new())),
+        }
+    }
+
+    /// 物品情報を検索するためのコンテキスト
+    /// 検索対象のカラムはマイグレーションで作るインデックスと揃えておく必要がある
+    /// 貸し出し中かどうかで絞り込めるように`is_lending`を付け加える
+    pub fn fixtures(conn: Arc<Pool<Postgres>>) -> Self {
+        let mut context = PgSearchContext::new(
+            conn,
+            "fixtures",
+            &["name", "description", "model_number", "note"],
+        );
+        context.source = r#"(
+        SELECT f.*, EXISTS (
+            SELECT 1 FROM lending AS l WHERE l.fixtures_id = f.id AND l.returned_at IS NULL
+        ) AS is_lending
+        FROM fixtures AS f
+    )"#
+        .to_string();
+        context
+    }
+
+    /// 地点情報を検索するためのコンテキスト
+    /// 検索エンジンでのIDを名前から作って付け加える
+    pub fn spot(conn: Arc<Pool<Postgres>>) -> Self {
+        let mut context = PgSearchContext::new(conn, "spot", &SPOT_SEARCHABLE_ATTRIBUTES);
+        context.source =
+            "(SELECT s.*, encode(convert_to(s.name, 'UTF8'), 'hex') AS id FROM spot AS s)"
+                .to_string();
+        context
+    }
+
+    /// コンテナの情報を検索するためのコンテキスト
+    pub fn container(conn: Arc<Pool<Postgres>>) -> Self {
+        PgSearchContext::new(conn, "container", &CONTAINER_SEARCHABLE_ATTRIBUTES)
+    }
+
+    /// 貸し出し中の貸出情報を検索するためのコンテキスト
+    pub fn lending(conn: Arc<Pool<Postgres>>) -> Self {
+        let mut context = PgSearchContext::new(conn, "lending", &LENDING_SEARCHABLE_ATTRIBUTES);
+        context.source = "(SELECT * FROM lending WHERE returned_at IS NULL)".to_string();
+        context
+    }
+
+    /// 検索対象のカラムをつなげて正規化した式
+    /// インデックスが使われるようにマイグレーションと同じ形にする
+    fn search_text(&self) -> String {
+        let text = self
+            .columns
+            .iter()
+            .map(|c| format!("coalesce({c}, '')"))
+            .collect::<Vec<_>>()
+            .join(" || ' ' || ");
+        normalize_expression(&text)
+    }
+}
+
+/// [`normalize`]と同じ変換をするSQLの式
+/// NFKCと小文字化の後に、カタカナをひらがなに置き換えて長音符を取り除く
+fn normalize_expression(expr: &str) -> String {
+    let katakana = ('ァ'..='ヶ').collect::<String>();
+    let hiragana = ('ぁ'..='ゖ').collect::<String>();
+    format!("translate(lower(normalize({expr}, NFKC)), '{katakana}ー', '{hiragana}')")
+}
+
+/// 属性の値を絞り込みやファセットと同じ文字列にする式
+/// 属性が無い場合はNULLになり、値がnullの場合は`"null"`になる
+fn attribute_expression(attribute: &str) -> String {
+    format!("(CASE WHEN to_jsonb(t) ? {attribute} THEN coalesce(to_jsonb(t) ->> {attribute}, 'null') END)")
+}
+
+/// SQLに渡す値
+#[derive(Debug, Clone)]
+enum Bind {
+    Text(String),
+    Texts(Vec<String>),
+    Int(i64),
+}
+
+/// SQLに渡す値を順に集める
+/// 属性の名前も値として渡し、SQLに埋め込まないようにする
+#[derive(Debug, Clone, Default)]
+struct Binds(Vec<Bind>);
+
+impl Binds {
+    /// 値を追加し、SQLの中で参照するためのプレースホルダーを返す
+    fn push(&mut self, value: Bind) -> String {
+        self.0.push(value);
+        format!("${}", self.0.len())
+    }
+
+    fn text(&mut self, value: String) -> String {
+        self.push(Bind::Text(value))
+    }
+
+    fn query_as<'q, O>(&self, sql: &'q str) -> QueryAs<'q, Postgres, O, PgArguments>
+    where
+        O: for<'r> FromRow<'r, PgRow>,
+    {
+        let mut query = sqlx::query_as(sql);
+        for value in self.0.iter().cloned() {
+            query = match value {
+                Bind::Text(value) => query.bind(value),
+                Bind::Texts(values) => query.bind(values),
+                Bind::Int(value) => query.bind(value),
+            };
+        }
+        query
+    }
+}
+
+/// `LIKE`で使う特殊文字をエスケープする
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[async_trait]
+impl SearchBackend for PgSearchContext {
+    async fn add_or_replace_documents<T>(&self, _documents: &[T]) -> Result<()>
+    where
+        T: Serialize + Send + Sync,
+    {
+        Ok(())
+    }
+
+    async fn delete_documents<K>(&self, _keys: &[K]) -> Result<()>
+    where
+        K: std::fmt::Display + Serialize + std::fmt::Debug + Send + Sync,
+    {
+        Ok(())
+    }
+
+    async fn search<T>(&self, query: &SearchQuery) -> Result<SearchPage<T>>
+    where
+        T: DeserializeOwned + 'static + Clone + Send,
+    {
+        let keywords = Keywords::parse(&query.keyword);
+        let synonyms = self
+            .synonyms
+            .read()
+            .map_err(|_| QrError::SearchEngineSearch(self.table.clone()))?
+            .clone();
+        let text = self.search_text();
+        let mut binds = Binds::default();
+        // 同義語で置き換えた検索語のどれかに一致すればその検索語に一致したとみなす
+        // フレーズは部分一致だけを、それ以外はトライグラムの類似度も使う
+        let mut term_condition = |term: &Term, fuzzy: bool| {
+            let mut conditions = Vec::new();
+            let mut rankings = Vec::new();
+            for word in expand_synonyms(&term.text, &synonyms) {
+                let pattern = binds.text(format!("%{}%", escape_like(&word)));
+                conditions.push(format!("({text}) LIKE {pattern}"));
+                let word = binds.text(word);
+                if fuzzy && !term.phrase {
+                    conditions.push(format!("{word} <% ({text})"));
+                }
+                rankings.push(format!("word_similarity({word}, ({text}))"));
+            }
+            (
+                format!("({})", conditions.join(" OR ")),
+                format!("greatest({})", rankings.join(", ")),
+            )
+        };
+        let mut conditions = Vec::new();
+        let mut rankings = Vec::new();
+        for term in keywords.terms.iter() {
+            let (condition, ranking) = term_condition(term, true);
+            conditions.push(condition);
+            rankings.push(ranking);
+        }
+        let mut excludes = Vec::new();
+        for term in keywords.excludes.iter() {
+            let (condition, _) = term_condition(term, false);
+            excludes.push(format!("NOT {condition}"));
+        }
+        let mut condition = match query.mode {
+            _ if conditions.is_empty() => "TRUE".to_string(),
+            MatchMode::Or => format!("({})", conditions.join(" OR ")),
+            MatchMode::And => format!("({})", conditions.join(" AND ")),
+        };
+        for exclude in excludes {
+            condition = format!("{condition} AND {exclude}");
+        }
+        // 同じ属性に対する値はOR、異なる属性の間はANDで結合する
+        for (attribute, values) in query.filters.iter() {
+            let attribute = attribute_expression(&binds.text(attribute.clone()));
+            let values = binds.push(Bind::Texts(values.clone()));
+            condition = format!("{condition} AND {attribute} = ANY({values})");
+        }
+        // 検索語ごとの類似度の平均を関連度とする
+        let ranking = if rankings.is_empty() {
+            "1::real".to_string()
+        } else {
+            format!("(({}) / {})", rankings.join(" + "), rankings.len())
+        };
+        let source = &self.source;
+        let search_error = |_| QrError::SearchEngineSearch(self.table.clone());
+
+        let mut facets = BTreeMap::new();
+        for attribute in query.facets.iter() {
+            let mut binds = binds.clone();
+            let value = attribute_expression(&binds.text(attribute.clone()));
+            let sql = format!(
+                r#"
+    SELECT {value} AS value, count(*) AS count
+    FROM {source} AS t
+    WHERE {condition} AND {value} IS NOT NULL
+    GROUP BY 1"#
+            );
+            let counts: Vec<(String, i64)> = binds
+                .query_as(&sql)
+                .fetch_all(&*self.conn)
+                .await
+                .map_err(search_error)?;
+            let counts = counts
+                .into_iter()
+                .map(|(value, count)| (value, count as usize))
+                .collect();
+            facets.insert(attribute.clone(), counts);
+        }
+
+        // 並び替えの指定が無いか、同じ値の中では関連度の順に並べる
+        let mut order = "ranking DESC".to_string();
+        if let Some(sort) = &query.sort {
+            let attribute = binds.text(sort.attribute.clone());
+            order = format!(
+                "nullif(to_jsonb(t) -> {attribute}, 'null') {} NULLS LAST, {order}",
+                sort.order
+            );
+        }
+        let mut page_binds = binds.clone();
+        let limit = page_binds.push(Bind::Int(query.limit as i64));
+        let offset = page_binds.push(Bind::Int(query.offset as i64));
+        let sql = format!(
+            r#"
+    SELECT to_jsonb(t) AS document, {ranking}::real AS ranking, count(*) OVER () AS total
+    FROM {source} AS t
+    WHERE {condition}
+    ORDER BY {order}
+    LIMIT {limit} OFFSET {offset}"#
+        );
+        let rows: Vec<(Value, f32, i64)> = page_binds
+            .query_as(&sql)
+            .fetch_all(&*self.conn)
+            .await
+            .map_err(search_error)?;
+
+        let total = match rows.first() {
+            Some((_, _, total)) => *total as usize,
+            // ページが範囲の外にあると行が無いので、件数だけを数える
+            None if query.offset > 0 => {
+                let sql = format!("SELECT count(*) FROM {source} AS t WHERE {condition}");
+                let (total,): (i64,) = binds
+                    .query_as(&sql)
+                    .fetch_one(&*self.conn)
+                    .await
+                    .map_err(search_error)?;
+                total as usize
+            }
+            None => 0,
+        };
+        let mut hits = Vec::new();
+        for (document, ranking, _) in rows {
+            let data = serde_json::from_value(document)
+                .map_err(|_| QrError::SearchEngineSearch(self.table.clone()))?;
+            hits.push(SearchResult {
+                data,
+                ranking: Some(ranking as f64),
+            });
+        }
+        Ok(SearchPage {
+            hits,
+            total,
+            offset: query.offset,
+            limit: query.limit,
+            facets,
+        })
+    }
+
+    async fn apply_settings(&self, settings: &IndexSettings) -> Result<()> {
+        let mut synonyms = self
+            .synonyms
+            .write()
+            .map_err(|_| QrError::SearchEngineSettings(self.table.clone()))?;
+        *synonyms = settings.synonyms.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::insert_fixtures::insert_fixtures;
+    use crate::database::insert_lending::insert_lending;
+    use crate::database::insert_spot::insert_spot;
+    use crate::database::returned_lending::returned_lending;
+    use crate::search_engine::{
+        normalize::synonym_map,
+        postgres::PgSearchContext,
+        query::{IndexSettings, MatchMode, SearchQuery, Sort},
+        FixturesDocument, SearchBackend, SpotDocument,
+    };
+    use crate::{Fixtures, Lending, Synonym};
+    use sqlx::{pool::Pool, Postgres};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_pg_search(pool: Pool<Postgres>) {
+        let info: Fixtures = serde_json::from_value(serde_json::json!({
+          "id": "550e8400-e29b-41d4-a716-446655440000",
+          "qr_id": "test",
+          "created_at": "2023-08-07 15:56:35 UTC",
+          "qr_color":"red",
+          "name":"延長コード",
+          "description":"5m 100%",
+          "model_number": "ABC-123",
+          "storage": "room101",
+          "note": "",
+          "parent_id": "null"
+        }))
+        .unwrap();
+        insert_fixtures(&pool, info).await.unwrap();
+        let info: Fixtures = serde_json::from_value(serde_json::json!({
+          "id": "550e8400-e29b-41d4-a716-446655440001",
+          "qr_id": "test2",
+          "created_at": "2023-08-07 15:56:35 UTC",
+          "qr_color":"blue",
+          "name":"プロジェクター",
+          "storage": "room102",
+          "note": "HDMIケーブル付き",
+          "parent_id": "null"
+        }))
+        .unwrap();
+        insert_fixtures(&pool, info).await.unwrap();
+
+        let conn = Arc::new(pool);
+        let context = PgSearchContext::fixtures(Arc::clone(&conn));
+
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("コード"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.fixtures.name, "延長コード");
+
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("abc-123"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("ケーブル"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.fixtures.name, "プロジェクター");
+
+        // 半角カナや長音の有無の違いは吸収される
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("ｹﾌﾞﾙ"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("ぷろじぇくた"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+
+        // 同義語を登録すると別の表記でも引っかかる
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("projector"))
+            .await
+            .unwrap()
+            .hits;
+        assert!(res.is_empty());
+        let settings = IndexSettings {
+            synonyms: synonym_map(&[Synonym {
+                id: Uuid::nil(),
+                words: vec!["プロジェクター".to_string(), "projector".to_string()],
+            }]),
+            ..Default::default()
+        };
+        context.apply_settings(&settings).await.unwrap();
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("Projector"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+
+        // 複数の検索語
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("コード,プロジェクター"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 2);
+        let mut query = SearchQuery::keyword("コード プロジェクター");
+        query.mode = MatchMode::And;
+        let res = context
+            .search::<FixturesDocument>(&query)
+            .await
+            .unwrap()
+            .hits;
+        assert!(res.is_empty());
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("-hdmi"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.fixtures.name, "延長コード");
+        let query = SearchQuery {
+            facets: vec!["storage".to_string()],
+            ..SearchQuery::keyword("\"5m 100%\"")
+        };
+        let page = context.search::<FixturesDocument>(&query).await.unwrap();
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.facets["storage"]["room101"], 1);
+
+        // LIKEの特殊文字はそのまま検索される
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword("%"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+
+        let res = context
+            .search::<FixturesDocument>(&SearchQuery::keyword(""))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 2);
+
+        // 並び替えとページ分割
+        let mut query = SearchQuery {
+            sort: Some(Sort::parse("qr_id:desc").unwrap()),
+            offset: 1,
+            limit: 1,
+            ..Default::default()
+        };
+        let page = context.search::<FixturesDocument>(&query).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].data.fixtures.qr_id, "test");
+        query.offset = 5;
+        let page = context.search::<FixturesDocument>(&query).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert!(page.hits.is_empty());
+
+        // 貸し出し中のものだけに絞り込む
+        let lending = serde_json::from_value(serde_json::json!({
+          "id": "550e8400-e29b-41d4-a716-446655440002",
+          "fixtures_id": "550e8400-e29b-41d4-a716-446655440000",
+          "fixtures_qr_id": "test",
+          "spot_name": "test",
+          "lending_at": "2023-08-07 15:56:35 UTC",
+          "borrower_name": "test",
+          "borrower_number": 202200000,
+          "borrower_org": "jsys"
+        }))
+        .unwrap();
+        insert_lending(&*conn, lending).await.unwrap();
+        let mut query = SearchQuery::default();
+        query
+            .filters
+            .insert("is_lending".to_string(), vec!["true".to_string()]);
+        query.facets = vec!["storage".to_string()];
+        let page = context.search::<FixturesDocument>(&query).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert!(page.hits[0].data.is_lending);
+        assert_eq!(page.facets["storage"]["room101"], 1);
+        assert_eq!(page.facets["storage"].get("room102"), None);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_pg_search_spot_and_lending(pool: Pool<Postgres>) {
+        let spot = serde_json::from_value(serde_json::json!({
+          "name": "3C棟 ラウンジ",
+          "area": "area3",
+          "building": "3C棟",
+          "room": "coinsラウンジ"
+        }))
+        .unwrap();
+        insert_spot(&pool, spot).await.unwrap();
+        for (id, org, returned) in [
+            ("550e8400-e29b-41d4-a716-446655440001", "情報科学類", false),
+            ("550e8400-e29b-41d4-a716-446655440002", "情報科学類", true),
+            ("550e8400-e29b-41d4-a716-446655440003", "jsys", false),
+        ] {
+            let lending = serde_json::from_value(serde_json::json!({
+              "id": id,
+              "fixtures_id": id,
+              "fixtures_qr_id": id,
+              "spot_name": "test",
+              "lending_at": "2023-08-07 15:56:35 UTC",
+              "borrower_name": "test",
+              "borrower_number": 202200000,
+              "borrower_org": org
+            }))
+            .unwrap();
+            insert_lending(&pool, lending).await.unwrap();
+            if returned {
+                let id = uuid::Uuid::parse_str(id).unwrap();
+                returned_lending(&pool, id, chrono::Utc::now())
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let conn = Arc::new(pool);
+        let context = PgSearchContext::spot(Arc::clone(&conn));
+        let res = context
+            .search::<SpotDocument>(&SearchQuery::keyword("COINS"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.id, SpotDocument::id("3C棟 ラウンジ"));
+
+        // 返却済みのものは含まない
+        let context = PgSearchContext::lending(Arc::clone(&conn));
+        let res = context
+            .search::<Lending>(&SearchQuery::keyword("情報科学類"))
+            .await
+            .unwrap()
+            .hits;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].data.borrower_org.as_deref(), Some("情報科学類"));
+    }
+}
@@ -0,0 +1,495 @@
+//! 検索条件と検索結果のページの定義
+//!
+//! 絞り込みやファセットの集計を自前で持たないバックエンドのために、
+//! 取得済みの文書に対して検索条件を適用する関数もここで提供する。
+use crate::{
+    error_handling::{QrError, Result},
+    search_engine::{
+        normalize::{normalize, Synonyms},
+        SearchResult,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use utoipa::openapi::{
+    schema::{ArrayBuilder, ObjectBuilder, Ref, Schema, SchemaType},
+    RefOr,
+};
+use utoipa::ToSchema;
+
+/// 一度の検索で返す件数の上限
+pub const MAX_LIMIT: usize = 1000;
+
+/// 複数の検索語の組み合わせ方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// どれか一つの検索語に一致すればよい
+    #[default]
+    Or,
+    /// 全ての検索語に一致する必要がある
+    And,
+}
+
+impl MatchMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "or" => Some(MatchMode::Or),
+            "and" => Some(MatchMode::And),
+            _ => None,
+        }
+    }
+}
+
+/// 検索語一つ分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    /// 正規化済みの検索語
+    pub text: String,
+    /// `"`で囲まれたフレーズかどうか
+    /// フレーズは空白を含めてそのまま一致するものだけを探す
+    pub phrase: bool,
+}
+
+/// 検索語の文字列を解釈したもの
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keywords {
+    /// 一致させる検索語
+    pub terms: Vec<Term>,
+    /// `-`を先頭に付けて指定された、含んでいてはいけない検索語
+    pub excludes: Vec<Term>,
+}
+
+impl Keywords {
+    /// 空白またはカンマ区切りの検索語を読む
+    ///
+    /// - `"延長 コード"`のように`"`で囲むとフレーズとして扱う
+    /// - `-ケーブル`のように先頭に`-`を付けるとその語を含むものを除く
+    ///
+    /// 文字列は先に正規化するので、全角の記号や空白も同じように扱われる。
+    pub fn parse(s: &str) -> Self {
+        let s = normalize(s);
+        let mut keywords = Keywords::default();
+        let mut chars = s.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+            let Some(&c) = chars.peek() else {
+                break;
+            };
+            let exclude = c == '-';
+            if exclude {
+                chars.next();
+            }
+            let phrase = chars.next_if_eq(&'"').is_some();
+            let mut text = String::new();
+            if phrase {
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    text.push(c);
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',') {
+                    text.push(c);
+                }
+            }
+            let text = text.trim().to_string();
+            if text.is_empty() {
+                continue;
+            }
+            let term = Term { text, phrase };
+            if exclude {
+                keywords.excludes.push(term);
+            } else {
+                keywords.terms.push(term);
+            }
+        }
+        keywords
+    }
+}
+
+/// 並び替えの向き
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl std::fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SortOrder::Asc => write!(f, "asc")?,
+            SortOrder::Desc => write!(f, "desc")?,
+        };
+        Ok(())
+    }
+}
+
+/// 並び替えの指定
+/// 指定がない場合は関連度の順に並ぶ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort {
+    pub attribute: String,
+    pub order: SortOrder,
+}
+
+impl Sort {
+    /// `created_at:desc`のような形式の文字列を読む
+    /// 向きを省略した場合は昇順とする
+    pub fn parse(s: &str) -> Option<Self> {
+        let (attribute, order) = match s.split_once(':') {
+            Some((attribute, "asc")) => (attribute, SortOrder::Asc),
+            Some((attribute, "desc")) => (attribute, SortOrder::Desc),
+            Some(_) => return None,
+            None => (s, SortOrder::Asc),
+        };
+        if attribute.is_empty() {
+            None
+        } else {
+            Some(Sort {
+                attribute: attribute.to_string(),
+                order,
+            })
+        }
+    }
+}
+
+/// 検索条件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// 検索語
+    /// 解釈の仕方は[`Keywords::parse`]を参照
+    pub keyword: String,
+    /// 複数の検索語の組み合わせ方
+    pub mode: MatchMode,
+    /// 属性ごとの絞り込み
+    /// 同じ属性に対する値はOR、異なる属性の間はANDで結合する
+    pub filters: BTreeMap<String, Vec<String>>,
+    /// 件数を集計する属性
+    pub facets: Vec<String>,
+    pub sort: Option<Sort>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        SearchQuery {
+            keyword: String::new(),
+            mode: MatchMode::default(),
+            filters: BTreeMap::new(),
+            facets: Vec::new(),
+            sort: None,
+            offset: 0,
+            limit: MAX_LIMIT,
+        }
+    }
+}
+
+impl SearchQuery {
+    /// 検索語だけを指定した検索条件
+    pub fn keyword(keyword: &str) -> Self {
+        SearchQuery {
+            keyword: keyword.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// 検索結果の一ページ分
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchPage<T> {
+    pub hits: Vec<SearchResult<T>>,
+    /// 条件に一致した全体の件数
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// 属性ごとの値と件数
+    pub facets: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+/// 仕様書での`SearchPage`の形
+/// utoipaの導出では`hits`の`SearchResult<T>`の`T`を置き換えられないので、文書の型から作る
+/// 文書の型ごとに形が変わるので、参照せずに埋め込んで使う
+impl<'s, T: ToSchema<'s>> ToSchema<'s> for SearchPage<T> {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let integer = || ObjectBuilder::new().schema_type(SchemaType::Integer);
+        let hit = ObjectBuilder::new()
+            .property("data", Ref::from_schema_name(T::schema().0))
+            .required("data")
+            .property(
+                "ranking",
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Number)
+                    .nullable(true)
+                    .description(Some("検索語との一致の度合い")),
+            )
+            .required("ranking");
+        let schema = ObjectBuilder::new()
+            .property("hits", ArrayBuilder::new().items(hit))
+            .required("hits")
+            .property(
+                "total",
+                integer().description(Some("条件に一致した全体の件数")),
+            )
+            .required("total")
+            .property("offset", integer())
+            .required("offset")
+            .property("limit", integer())
+            .required("limit")
+            .property(
+                "facets",
+                ObjectBuilder::new()
+                    .additional_properties(Some(
+                        ObjectBuilder::new().additional_properties(Some(integer())),
+                    ))
+                    .description(Some("属性ごとの値と件数")),
+            )
+            .required("facets");
+        ("SearchPage", schema.into())
+    }
+}
+
+impl<T> SearchPage<T> {
+    /// 検索結果のそれぞれの文書を変換する
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> SearchPage<U> {
+        SearchPage {
+            hits: self
+                .hits
+                .into_iter()
+                .map(|hit| SearchResult {
+                    data: f(hit.data),
+                    ranking: hit.ranking,
+                })
+                .collect(),
+            total: self.total,
+            offset: self.offset,
+            limit: self.limit,
+            facets: self.facets,
+        }
+    }
+}
+
+/// インデックスに設定する内容
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexSettings {
+    /// 絞り込みとファセットに使える属性
+    pub filterable_attributes: Vec<String>,
+    /// 並び替えに使える属性
+    pub sortable_attributes: Vec<String>,
+    /// 正規化済みの同義語の辞書
+    pub synonyms: Synonyms,
+}
+
+/// 絞り込みやファセットで使うために値を文字列にする
+pub fn value_to_facet(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn compare_value(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(Value::Bool(a)), Some(Value::Bool(b))) => a.cmp(b),
+        // 値が無いものは後ろに回す
+        (None | Some(Value::Null), None | Some(Value::Null)) => Ordering::Equal,
+        (None | Some(Value::Null), _) => Ordering::Greater,
+        (_, None | Some(Value::Null)) => Ordering::Less,
+        (Some(a), Some(b)) => value_to_facet(a).cmp(&value_to_facet(b)),
+    }
+}
+
+/// 検索語ごとのスコアを合算して関連度の順に並べる
+/// 文書はキーで同一視し、スコアは検索語の数で割って0から1の範囲にする
+pub fn merge_hits(key: &str, results: Vec<Vec<(Value, f64)>>) -> Vec<(Value, f64)> {
+    let n = results.len().max(1) as f64;
+    let mut merged: Vec<(Value, f64)> = Vec::new();
+    let mut position: BTreeMap<String, usize> = BTreeMap::new();
+    for (document, ranking) in results.into_iter().flatten() {
+        let k = document.get(key).map(value_to_facet).unwrap_or_default();
+        match position.get(&k) {
+            Some(&i) => merged[i].1 += ranking,
+            None => {
+                position.insert(k, merged.len());
+                merged.push((document, ranking));
+            }
+        }
+    }
+    for (_, ranking) in merged.iter_mut() {
+        *ranking /= n;
+    }
+    // 安定ソートなので同じスコアの中では最初に見つかった順が保たれる
+    merged.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+    merged
+}
+
+/// 関連度の順に並んだ検索語に一致する文書に対して、
+/// 絞り込み・ファセットの集計・並び替え・ページ分割を行う
+pub fn evaluate<T: DeserializeOwned>(
+    index: &str,
+    query: &SearchQuery,
+    hits: Vec<(Value, f64)>,
+) -> Result<SearchPage<T>> {
+    let mut hits = hits
+        .into_iter()
+        .filter(|(document, _)| {
+            query.filters.iter().all(|(attribute, values)| {
+                let value = document.get(attribute).map(value_to_facet);
+                value.map(|v| values.contains(&v)).unwrap_or(false)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut facets = BTreeMap::new();
+    for attribute in query.facets.iter() {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for (document, _) in hits.iter() {
+            if let Some(value) = document.get(attribute) {
+                *counts.entry(value_to_facet(value)).or_default() += 1;
+            }
+        }
+        facets.insert(attribute.clone(), counts);
+    }
+
+    if let Some(sort) = &query.sort {
+        // 安定ソートなので同じ値の中では関連度の順が保たれる
+        hits.sort_by(|(a, _), (b, _)| {
+            let ord = compare_value(a.get(&sort.attribute), b.get(&sort.attribute));
+            match sort.order {
+                SortOrder::Asc => ord,
+                SortOrder::Desc => ord.reverse(),
+            }
+        });
+    }
+
+    let total = hits.len();
+    let mut lst = Vec::new();
+    for (document, ranking) in hits.into_iter().skip(query.offset).take(query.limit) {
+        let data = serde_json::from_value(document)
+            .map_err(|_| QrError::SearchEngineSearch(index.to_string()))?;
+        lst.push(SearchResult {
+            data,
+            ranking: Some(ranking),
+        });
+    }
+    Ok(SearchPage {
+        hits: lst,
+        total,
+        offset: query.offset,
+        limit: query.limit,
+        facets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search_engine::query::{
+        evaluate, merge_hits, Keywords, SearchPage, SearchQuery, Sort, SortOrder, Term,
+    };
+    use serde_json::{json, Value};
+
+    fn term(text: &str, phrase: bool) -> Term {
+        Term {
+            text: text.to_string(),
+            phrase,
+        }
+    }
+
+    #[test]
+    fn test_parse_keywords() {
+        let keywords = Keywords::parse("延長,ｺｰﾄﾞ　-HDMI \"5m 白\" -\"abc-123\" x-1");
+        assert_eq!(
+            keywords.terms,
+            vec![
+                term("延長", false),
+                term("こど", false),
+                term("5m 白", true),
+                term("x-1", false)
+            ]
+        );
+        assert_eq!(
+            keywords.excludes,
+            vec![term("hdmi", false), term("abc-123", true)]
+        );
+        assert_eq!(Keywords::parse(" , - \"\""), Keywords::default());
+    }
+
+    #[test]
+    fn test_merge_hits() {
+        let merged = merge_hits(
+            "id",
+            vec![
+                vec![(json!({"id": 1}), 0.4), (json!({"id": 2}), 0.2)],
+                vec![(json!({"id": 2}), 1.0), (json!({"id": 3}), 0.6)],
+            ],
+        );
+        let ids = merged
+            .iter()
+            .map(|(d, _)| d["id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![json!(2), json!(3), json!(1)]);
+        assert_eq!(merged[0].1, 0.6);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let hits = vec![
+            (
+                json!({"id": 1, "storage": "room101", "is_lending": false}),
+                1.0,
+            ),
+            (
+                json!({"id": 2, "storage": "room206", "is_lending": true}),
+                0.8,
+            ),
+            (
+                json!({"id": 3, "storage": "room206", "is_lending": false}),
+                0.5,
+            ),
+            (
+                json!({"id": 4, "storage": "room206", "is_lending": false}),
+                0.3,
+            ),
+        ];
+
+        let mut query = SearchQuery::default();
+        query
+            .filters
+            .insert("storage".to_string(), vec!["room206".to_string()]);
+        query
+            .filters
+            .insert("is_lending".to_string(), vec!["false".to_string()]);
+        query.facets = vec!["storage".to_string(), "is_lending".to_string()];
+        query.sort = Some(Sort {
+            attribute: "id".to_string(),
+            order: SortOrder::Desc,
+        });
+        query.limit = 1;
+
+        let page: SearchPage<Value> = evaluate("test", &query, hits).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.hits.len(), 1);
+        assert_eq!(page.hits[0].data["id"], 4);
+        assert_eq!(page.facets["storage"]["room206"], 2);
+        assert_eq!(page.facets["is_lending"].get("true"), None);
+
+        assert_eq!(
+            Sort::parse("created_at:desc"),
+            Some(Sort {
+                attribute: "created_at".to_string(),
+                order: SortOrder::Desc
+            })
+        );
+        assert_eq!(Sort::parse("name:up"), None);
+    }
+}
@@ -0,0 +1,405 @@
+//! 物品・貸出情報・地点の入力値の検証
+//!
+//! 本文を読み込んだ後、データベースに書き込む前に検証する。
+//! 不正な項目があれば項目ごとの理由をまとめて`QrError::Validation`にし、
+//! `422 Unprocessable Entity`で返す。フロントエンドは`field`を見て入力欄を強調できる。
+use crate::error_handling::{QrError, Result};
+use crate::patch::{FixturesPatch, LendingPatch, SpotPatch};
+use crate::{Fixtures, Lending, NewFixtures, NewLending, Spot};
+use serde::Serialize;
+use std::ops::RangeInclusive;
+use utoipa::ToSchema;
+
+/// 文字列の項目の長さの上限
+pub const MAX_TEXT_LENGTH: usize = 255;
+
+/// 学籍番号として受け付ける範囲
+/// 学籍番号は9桁
+pub const BORROWER_NUMBER_RANGE: RangeInclusive<i32> = 100_000_000..=999_999_999;
+
+/// 階数として受け付ける範囲
+pub const FLOOR_RANGE: RangeInclusive<i32> = -5..=30;
+
+/// 不正な理由を表す機械向けのコード
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldErrorCode {
+    /// 空になっている
+    Required,
+    /// 長すぎる
+    TooLong,
+    /// 範囲の外の値になっている
+    OutOfRange,
+    /// 参照先が登録されていない
+    NotFound,
+}
+
+/// 不正な項目とその理由
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    /// 不正な項目の名前
+    pub field: String,
+    pub code: FieldErrorCode,
+    /// 利用者に見せる理由
+    pub message: String,
+}
+
+/// 検証で見つかった不正な項目を集める
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    fn push(&mut self, field: &str, code: FieldErrorCode, message: String) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            code,
+            message,
+        });
+    }
+
+    /// 空にできない文字列の項目
+    pub fn required(&mut self, field: &str, value: &str) {
+        if value.trim().is_empty() {
+            self.push(
+                field,
+                FieldErrorCode::Required,
+                "入力してください".to_string(),
+            );
+        } else {
+            self.text(field, value);
+        }
+    }
+
+    /// 空にできる文字列の項目
+    pub fn text(&mut self, field: &str, value: &str) {
+        if value.chars().count() > MAX_TEXT_LENGTH {
+            self.push(
+                field,
+                FieldErrorCode::TooLong,
+                format!("{MAX_TEXT_LENGTH}文字以内で入力してください"),
+            );
+        }
+    }
+
+    /// 値が範囲の中にあるか
+    pub fn range(&mut self, field: &str, value: i32, range: &RangeInclusive<i32>) {
+        if !range.contains(&value) {
+            self.push(
+                field,
+                FieldErrorCode::OutOfRange,
+                format!(
+                    "{}から{}の範囲で入力してください",
+                    range.start(),
+                    range.end()
+                ),
+            );
+        }
+    }
+
+    /// 参照先が登録されていない項目
+    pub fn not_found(&mut self, field: &str, message: &str) {
+        self.push(field, FieldErrorCode::NotFound, message.to_string());
+    }
+
+    /// 不正な項目があれば`QrError::Validation`にする
+    pub fn into_result(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(QrError::Validation(self.0))
+        }
+    }
+}
+
+/// データベースに問い合わせずに確かめられる規則
+pub trait Validate {
+    fn validate(&self, errors: &mut FieldErrors);
+}
+
+/// 規則を満たさない項目があれば`QrError::Validation`を返す
+pub fn validate<T: Validate>(value: &T) -> Result<()> {
+    let mut errors = FieldErrors::default();
+    value.validate(&mut errors);
+    errors.into_result()
+}
+
+/// 規則に加え、`spot_name`で指定した地点が登録されているかを確かめる
+/// 部分的な更新で`spot_name`を含まない場合は`None`を渡す
+pub async fn validate_with_spot<'a, T, E>(conn: E, value: &T, spot_name: Option<&str>) -> Result<()>
+where
+    T: Validate,
+    E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+{
+    let mut errors = FieldErrors::default();
+    value.validate(&mut errors);
+    // 空の場合は既に`required`として報告している
+    if let Some(spot_name) = spot_name.filter(|name| !name.trim().is_empty()) {
+        match crate::database::get_one_spot::get_one_spot(conn, spot_name).await {
+            Ok(_) => (),
+            Err(QrError::DatabaseNotFound(_)) => {
+                errors.not_found("spot_name", "登録されていない地点です")
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    errors.into_result()
+}
+
+/// 物品の項目の規則
+/// 物品の登録・更新・部分的な更新で共通にする
+#[allow(clippy::too_many_arguments)]
+fn validate_fixtures(
+    errors: &mut FieldErrors,
+    qr_id: Option<&str>,
+    name: Option<&str>,
+    description: Option<&str>,
+    model_number: Option<&str>,
+    usage: Option<&str>,
+    usage_season: Option<&str>,
+    note: Option<&str>,
+) {
+    if let Some(qr_id) = qr_id {
+        errors.required("qr_id", qr_id);
+    }
+    if let Some(name) = name {
+        errors.required("name", name);
+    }
+    for (field, value) in [
+        ("description", description),
+        ("model_number", model_number),
+        ("usage", usage),
+        ("usage_season", usage_season),
+        ("note", note),
+    ] {
+        if let Some(value) = value {
+            errors.text(field, value);
+        }
+    }
+}
+
+impl Validate for NewFixtures {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_fixtures(
+            errors,
+            Some(&self.qr_id),
+            Some(&self.name),
+            self.description.as_deref(),
+            self.model_number.as_deref(),
+            self.usage.as_deref(),
+            self.usage_season.as_deref(),
+            Some(&self.note),
+        );
+    }
+}
+
+impl Validate for Fixtures {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_fixtures(
+            errors,
+            Some(&self.qr_id),
+            Some(&self.name),
+            self.description.as_deref(),
+            self.model_number.as_deref(),
+            self.usage.as_deref(),
+            self.usage_season.as_deref(),
+            Some(&self.note),
+        );
+    }
+}
+
+impl Validate for FixturesPatch {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_fixtures(
+            errors,
+            self.qr_id.as_deref(),
+            self.name.as_deref(),
+            self.description.clone().flatten().as_deref(),
+            self.model_number.clone().flatten().as_deref(),
+            self.usage.clone().flatten().as_deref(),
+            self.usage_season.clone().flatten().as_deref(),
+            self.note.as_deref(),
+        );
+    }
+}
+
+/// 貸出情報の項目の規則
+/// 地点が登録されているかは`validate_with_spot`で確かめる
+fn validate_lending(
+    errors: &mut FieldErrors,
+    fixtures_qr_id: Option<&str>,
+    spot_name: Option<&str>,
+    borrower_name: Option<&str>,
+    borrower_number: Option<i32>,
+    borrower_org: Option<&str>,
+) {
+    if let Some(fixtures_qr_id) = fixtures_qr_id {
+        errors.required("fixtures_qr_id", fixtures_qr_id);
+    }
+    if let Some(spot_name) = spot_name {
+        errors.required("spot_name", spot_name);
+    }
+    if let Some(borrower_name) = borrower_name {
+        errors.required("borrower_name", borrower_name);
+    }
+    if let Some(borrower_number) = borrower_number {
+        errors.range("borrower_number", borrower_number, &BORROWER_NUMBER_RANGE);
+    }
+    if let Some(borrower_org) = borrower_org {
+        errors.text("borrower_org", borrower_org);
+    }
+}
+
+impl Validate for NewLending {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_lending(
+            errors,
+            Some(&self.fixtures_qr_id),
+            Some(&self.spot_name),
+            Some(&self.borrower_name),
+            Some(self.borrower_number),
+            self.borrower_org.as_deref(),
+        );
+    }
+}
+
+impl Validate for Lending {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_lending(
+            errors,
+            Some(&self.fixtures_qr_id),
+            Some(&self.spot_name),
+            Some(&self.borrower_name),
+            Some(self.borrower_number),
+            self.borrower_org.as_deref(),
+        );
+    }
+}
+
+impl Validate for LendingPatch {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_lending(
+            errors,
+            self.fixtures_qr_id.as_deref(),
+            self.spot_name.as_deref(),
+            self.borrower_name.as_deref(),
+            self.borrower_number,
+            self.borrower_org.clone().flatten().as_deref(),
+        );
+    }
+}
+
+/// 地点の項目の規則
+fn validate_spot(
+    errors: &mut FieldErrors,
+    building: Option<&str>,
+    floor: Option<i32>,
+    room: Option<&str>,
+    note: Option<&str>,
+) {
+    if let Some(floor) = floor {
+        errors.range("floor", floor, &FLOOR_RANGE);
+    }
+    for (field, value) in [("building", building), ("room", room), ("note", note)] {
+        if let Some(value) = value {
+            errors.text(field, value);
+        }
+    }
+}
+
+impl Validate for Spot {
+    fn validate(&self, errors: &mut FieldErrors) {
+        errors.required("name", &self.name);
+        validate_spot(
+            errors,
+            self.building.as_deref(),
+            self.floor,
+            self.room.as_deref(),
+            self.note.as_deref(),
+        );
+    }
+}
+
+impl Validate for SpotPatch {
+    fn validate(&self, errors: &mut FieldErrors) {
+        validate_spot(
+            errors,
+            self.building.clone().flatten().as_deref(),
+            self.floor.flatten(),
+            self.room.clone().flatten().as_deref(),
+            self.note.clone().flatten().as_deref(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error_handling::QrError;
+    use crate::validation::{validate, validate_with_spot, FieldErrorCode};
+    use crate::{NewLending, Spot};
+    use sqlx::{pool::Pool, Postgres};
+
+    #[test]
+    fn test_validate_spot() {
+        let spot: Spot = serde_json::from_value(serde_json::json!({
+          "name": " ",
+          "area": "area3",
+          "floor": 9999,
+        }))
+        .unwrap();
+        let Err(QrError::Validation(errors)) = validate(&spot) else {
+            panic!("spot should be invalid");
+        };
+        let errors = errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.code))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("name", FieldErrorCode::Required),
+                ("floor", FieldErrorCode::OutOfRange)
+            ]
+        );
+
+        let spot = Spot {
+            name: "room101".to_string(),
+            floor: Some(3),
+            ..spot
+        };
+        assert_eq!(validate(&spot), Ok(()));
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn test_validate_with_spot(pool: Pool<Postgres>) {
+        let mut lending: NewLending = serde_json::from_value(serde_json::json!({
+          "fixtures_id": "550e8400-e29b-41d4-a716-446655440000",
+          "fixtures_qr_id": "test",
+          "spot_name": "unknown",
+          "borrower_name": "筑波太郎",
+          "borrower_number": -1,
+          "borrower_org": null
+        }))
+        .unwrap();
+        let Err(QrError::Validation(errors)) =
+            validate_with_spot(&pool, &lending, Some(&lending.spot_name)).await
+        else {
+            panic!("lending should be invalid");
+        };
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].field, "borrower_number");
+        assert_eq!(errors[1].field, "spot_name");
+        assert_eq!(errors[1].code, FieldErrorCode::NotFound);
+
+        let spot: Spot =
+            serde_json::from_value(serde_json::json!({"name": "unknown", "area": "area3"}))
+                .unwrap();
+        crate::database::insert_spot::insert_spot(&pool, spot)
+            .await
+            .unwrap();
+        lending.borrower_number = 202200000;
+        assert_eq!(
+            validate_with_spot(&pool, &lending, Some(&lending.spot_name)).await,
+            Ok(())
+        );
+    }
+}